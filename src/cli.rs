@@ -1,13 +1,17 @@
 use crate::config::{Config, Project};
 use crate::daemon::Daemon;
-use clap::{arg, command, Arg, Command};
+use crate::plugin::{ActionDescriptor, HealthCheck, HealthReport, ParamKind};
+use crate::shell;
+use crate::tui;
+use clap::{arg, command, Arg, ArgAction, Command};
 use futures::executor::block_on;
 use futures::sink::SinkExt;
 use serde_json::{json, Value};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{UnixListener, UnixStream};
@@ -69,12 +73,53 @@ pub async fn run(cli: Cli) {
                     Command::new("disable-plugin")
                         .about("disable a plugin for a project")
                         .arg(Arg::new("project-name"))
-                        .arg(Arg::new("plugin")),
+                        .arg(Arg::new("plugin"))
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                                .help("disable it even if other enabled plugins depend on it"),
+                        ),
                     Command::new("enable-plugin")
                         .about("enable a plugin for a project")
                         .arg(Arg::new("project-name"))
                         .arg(Arg::new("plugin")),
+                    Command::new("switch")
+                        .about("make a project, or one of its contexts as `project/context`, the current one")
+                        .arg(Arg::new("project-name").required(true)),
+                    Command::new("current").about("print the current project, and context if any"),
+                    Command::new("add-context")
+                        .about("add a context, like a worktree, with its own directory")
+                        .arg(Arg::new("project-name").required(true))
+                        .arg(Arg::new("context").required(true))
+                        .arg(Arg::new("path").required(true)),
+                    Command::new("remove-context")
+                        .about("remove a context from a project, its directory is kept")
+                        .arg(Arg::new("project-name").required(true))
+                        .arg(Arg::new("context").required(true)),
                     Command::new("list").about("list the defined projects"),
+                    Command::new("recent")
+                        .about("print the projects by frecency, with their path, branch and last use, as json"),
+                    Command::new("pick")
+                        .about("print the projects for fzf, rofi -dmenu or dmenu, the most used first and the current one last")
+                        .arg(
+                            Arg::new("switch")
+                                .long("switch")
+                                .action(ArgAction::SetTrue)
+                                .help("read the picked line on stdin and switch to its project"),
+                        ),
+                    Command::new("overview").about(
+                        "print the projects with their plugins, status and time tracked today, as json",
+                    ),
+                    Command::new("resolve")
+                        .about("print the project, or context, whose directory contains a path")
+                        .arg(Arg::new("path").required(true))
+                        .arg(
+                            Arg::new("switch")
+                                .long("switch")
+                                .action(ArgAction::SetTrue)
+                                .help("also make it the current project"),
+                        ),
                 ]),
         )
         .subcommand(
@@ -82,25 +127,102 @@ pub async fn run(cli: Cli) {
                 .about("work with events")
                 .subcommands([
                     Command::new("trigger")
-                        .about("trigger an event")
-                        .arg(Arg::new("event-name").required(true)),
+                        .about("trigger an event, for all the projects or just one")
+                        .arg(Arg::new("event-name").required(true))
+                        .arg(Arg::new("project-name")),
                     Command::new("list").about("list the possible events"),
+                    Command::new("subscribe")
+                        .about("print the events dispatched by the daemon as they happen, a json line each"),
                 ]),
         )
         .subcommand(
             Command::new("plugin")
                 .about("work with plugins")
-                .arg(Arg::new("plugin").required(true))
                 .subcommands([
                     Command::new("run")
-                        .about("run a plugin action")
+                        .about("run a plugin action, use `run <plugin> <action> --help` for its arguments")
+                        // --help is handled by the action itself
+                        .disable_help_flag(true)
+                        .arg(Arg::new("plugin").required(true))
                         .arg(Arg::new("action").required(true))
-                        .arg(Arg::new("project-name"))
-                        .arg(Arg::new("args").num_args(1..)),
+                        .arg(
+                            Arg::new("args")
+                                .num_args(0..)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true),
+                        ),
+                    Command::new("list")
+                        .about("list the registered plugins, with what they do and who uses them"),
+                    Command::new("info")
+                        .about("describe a plugin and its configuration")
+                        .arg(Arg::new("plugin").required(true)),
+                    Command::new("set-config")
+                        .about("change a setting of the plugin configuration, or remove it without a value")
+                        .arg(Arg::new("plugin").required(true))
+                        .arg(Arg::new("key").required(true))
+                        .arg(Arg::new("value")),
                     Command::new("list-actions")
-                        .about("list the available actions for this plugin"),
+                        .about("list the available actions for this plugin")
+                        .arg(Arg::new("plugin").required(true)),
+                    Command::new("reconcile")
+                        .about("check that the plugin state matches the configuration")
+                        .arg(Arg::new("plugin").required(true)),
+                    Command::new("load")
+                        .about("load a plugin from a shared library")
+                        .arg(Arg::new("path").required(true)),
+                    Command::new("unload")
+                        .about("unload a plugin loaded from a file")
+                        .arg(Arg::new("plugin").required(true)),
                 ]),
         )
+        .subcommand(
+            Command::new("shell-init")
+                .about("print a snippet to source in the shell, showing the current project in the prompt")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish"]),
+                )
+                .arg(
+                    Arg::new("auto-switch")
+                        .long("auto-switch")
+                        .action(ArgAction::SetTrue)
+                        .help("switch to the project of the directory after each cd"),
+                )
+                .arg(
+                    Arg::new("no-prompt")
+                        .long("no-prompt")
+                        .action(ArgAction::SetTrue)
+                        .help("leave the prompt alone, $PROJECT_PILOT_PROJECT is still set"),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("print the completion script of a shell, completing names known by the daemon")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish"]),
+                ),
+        )
+        .subcommand(
+            // called by the completion scripts with the words typed so far
+            Command::new("__complete").hide(true).arg(
+                Arg::new("words")
+                    .num_args(0..)
+                    .trailing_var_arg(true)
+                    .allow_hyphen_values(true),
+            ),
+        )
+        .subcommand(
+            Command::new("tui").about(
+                "a dashboard of the projects, switching them, toggling plugins, editing properties and running actions",
+            ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("check the installation, the configuration and the plugins, suggesting fixes"),
+        )
         .subcommand(
             Command::new("daemon")
                 .about("work with the background process")
@@ -127,6 +249,51 @@ pub async fn run(cli: Cli) {
         command_line.print_help().unwrap();
         return;
     };
+    if subject == "shell-init" {
+        let shell = sub_args.get_one::<String>("shell").unwrap();
+        let binary = env::current_exe()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or("project-pilot".to_string());
+        print!(
+            "{}",
+            shell::init_script(
+                shell,
+                &binary,
+                sub_args.get_flag("auto-switch"),
+                !sub_args.get_flag("no-prompt"),
+            )
+        );
+        return;
+    }
+    if subject == "completions" {
+        let shell = sub_args.get_one::<String>("shell").unwrap();
+        let binary = env::current_exe()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or("project-pilot".to_string());
+        print!("{}", shell::completion_script(shell, &binary));
+        return;
+    }
+    if subject == "__complete" {
+        let words: Vec<String> = sub_args
+            .get_many::<String>("words")
+            .map(|words| words.cloned().collect())
+            .unwrap_or_default();
+        for candidate in complete(&socket_path, &command_line, &words).await {
+            println!("{}", candidate);
+        }
+        return;
+    }
+    if subject == "tui" {
+        if let Err(e) = tokio::task::block_in_place(|| tui::run(&socket_path)) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    if subject == "doctor" {
+        let config_path = PathBuf::from(home.clone() + "/.config/project-pilot/config.toml");
+        doctor(&config_path, &socket_path).await;
+        return;
+    }
     let (command, com_args) = if let Some(res) = sub_args.subcommand() {
         res
     } else {
@@ -141,7 +308,9 @@ pub async fn run(cli: Cli) {
     if subject == "daemon" && command == "start" {
         println!("Starting daemon");
         let config_path = PathBuf::from(home.clone() + "/.config/project-pilot/config.toml");
-        let mut daemon = Daemon::new(config_path);
+        let plugin_dir = PathBuf::from(home.clone() + "/.local/share/project-pilot/plugins");
+        let script_dir = PathBuf::from(home.clone() + "/.config/project-pilot/scripts");
+        let mut daemon = Daemon::new(config_path, plugin_dir, script_dir);
         let force: bool = *(com_args.get_one("force").unwrap());
         // let force: bool=match force_arg {
         //     Some(x) => *x,
@@ -150,21 +319,63 @@ pub async fn run(cli: Cli) {
         daemon.start(&socket_path,force).await;
         return;
     }
+    if subject == "event" && command == "subscribe" {
+        if let Err(e) = subscribe_events(&socket_path).await {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    if subject == "project" && command == "pick" {
+        pick_project(&socket_path, com_args).await;
+        return;
+    }
+    if subject == "plugin" && command == "run" {
+        run_plugin_action(&socket_path, com_args).await;
+        return;
+    }
 
     fn collect_args(args: &clap::ArgMatches) -> impl Iterator<Item = (String, String)> + '_ {
         args
             .ids()
-            .filter_map(|id| {
-                let many = args.get_many::<String>(id.as_str());
-                let joined = many.unwrap().map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
-                Some((String::from(id.as_str()), joined))
+            .map(|id| {
+                // flags are sent as "true" or "false"
+                let joined = match args.try_get_many::<String>(id.as_str()) {
+                    Ok(many) => many.unwrap().map(|x| x.to_string()).collect::<Vec<String>>().join(" "),
+                    Err(_) => args.get_flag(id.as_str()).to_string(),
+                };
+                (String::from(id.as_str()), joined)
             })
     }
-    let args_map: HashMap<String, String> = collect_args(sub_args).chain(collect_args(com_args)).collect();
+    let mut args_map: HashMap<String, String> = collect_args(sub_args).chain(collect_args(com_args)).collect();
+    // the daemon runs in another directory
+    if let Some(path) = args_map.get_mut("path") {
+        if let Ok(absolute) = std::fs::canonicalize(&path) {
+            *path = absolute.to_string_lossy().to_string();
+        }
+    }
+
+    let response = send_request(
+        &socket_path,
+        json!({
+            "subject":subject,
+            "command":command,
+            "params":args_map
+        }),
+    )
+    .await;
+    println!("{}", response);
+}
 
+async fn send_request(socket_path: &str, request: Value) -> String {
+    try_send_request(socket_path, request)
+        .await
+        .expect("Failed to connect to daemon")
+}
+
+async fn try_send_request(socket_path: &str, request: Value) -> Result<String, String> {
     let stream = UnixStream::connect(socket_path)
         .await
-        .expect("Failed to connect to daemon");
+        .map_err(|e| e.to_string())?;
     let (read_socket, write_socket) = split(stream);
 
     let length_delimited_write = FramedWrite::new(write_socket, LengthDelimitedCodec::new());
@@ -172,22 +383,478 @@ pub async fn run(cli: Cli) {
         length_delimited_write,
         SymmetricalJson::<Value>::default(),
     );
-    serializer
-        .send(json!({
-            "subject":subject,
-            "command":command,
-            "params":args_map
-        }))
-        .await
-        .unwrap();
+    serializer.send(request).await.map_err(|e| e.to_string())?;
 
     let length_delimited_read = FramedRead::new(read_socket, LengthDelimitedCodec::new());
     let mut deserializer = tokio_serde::SymmetricallyFramed::new(
         length_delimited_read,
         SymmetricalJson::<Value>::default(),
     );
-    block_on(async move {
-        let msg = deserializer.try_next().await.unwrap().unwrap();
-        println!("{}", msg.get("lines").unwrap().as_str().unwrap());
+    let msg = deserializer
+        .try_next()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("the daemon closed the connection".to_string())?;
+    Ok(msg.get("lines").unwrap().as_str().unwrap().to_string())
+}
+
+// `project pick | fzf | project pick --switch`, a line is the project name, a tab, then its
+// annotations aligned with spaces. Names may contain spaces, not tabs
+async fn pick_project(socket_path: &str, args: &clap::ArgMatches) {
+    if args.get_flag("switch") {
+        let mut selection = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut selection) {
+            eprintln!("cannot read the selection: {}", e);
+            return;
+        }
+        // nothing picked
+        let project_name = match picked_project(&selection) {
+            Some(project_name) => project_name,
+            None => return,
+        };
+        let response = send_request(
+            socket_path,
+            json!({"subject": "project", "command": "switch", "params": {"project-name": project_name}}),
+        )
+        .await;
+        println!("{}", response);
+        return;
+    }
+    let response = send_request(
+        socket_path,
+        json!({"subject": "project", "command": "recent", "params": {}}),
+    )
+    .await;
+    let projects: Vec<Value> = match serde_json::from_str(&response) {
+        Ok(projects) => projects,
+        Err(_) => {
+            println!("{}", response);
+            return;
+        }
+    };
+    // by frecency, the current project last since it is rarely the one picked
+    let (current, others): (Vec<&Value>, Vec<&Value>) =
+        projects.iter().partition(|p| p["current"].as_bool() == Some(true));
+    let width = projects
+        .iter()
+        .map(|p| p["name"].as_str().unwrap_or_default().chars().count())
+        .max()
+        .unwrap_or(0);
+    let home = env::var("HOME").unwrap_or_default();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    for project in others.into_iter().chain(current) {
+        println!("{}", pick_line(project, width, &home, now));
+    }
+}
+
+fn pick_line(project: &Value, width: usize, home: &str, now: i64) -> String {
+    let mut annotations = vec![];
+    if let Some(path) = project["path"].as_str() {
+        annotations.push(match path.strip_prefix(home) {
+            Some(rest) if !home.is_empty() => format!("~{}", rest),
+            _ => path.to_string(),
+        });
+    }
+    if let Some(branch) = project["branch"].as_str() {
+        annotations.push(format!("[{}]", branch));
+    }
+    annotations.push(match project["last-used"].as_i64() {
+        Some(time) => ago(now - time),
+        None => "never used".to_string(),
+    });
+    if project["current"].as_bool() == Some(true) {
+        annotations.push("(current)".to_string());
+    }
+    let name = project["name"].as_str().unwrap_or_default();
+    let padding = " ".repeat(width.saturating_sub(name.chars().count()) + 1);
+    format!("{}\t{}{}", name, padding, annotations.join("  "))
+}
+
+// the project of a picked line, a typed name without annotations works too
+fn picked_project(selection: &str) -> Option<&str> {
+    let line = selection.trim_end_matches(['\n', '\r']);
+    let name = line.split('\t').next().unwrap_or_default();
+    (!name.trim().is_empty()).then_some(name)
+}
+
+fn ago(seconds: i64) -> String {
+    match seconds {
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    }
+}
+
+async fn subscribe_events(socket_path: &str) -> Result<(), String> {
+    let stream = UnixStream::connect(socket_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (read_socket, write_socket) = split(stream);
+    let mut serializer = tokio_serde::SymmetricallyFramed::new(
+        FramedWrite::new(write_socket, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Value>::default(),
+    );
+    let request = json!({"subject": "event", "command": "subscribe", "params": {}});
+    serializer.send(request).await.map_err(|e| e.to_string())?;
+    let mut deserializer = tokio_serde::SymmetricallyFramed::new(
+        FramedRead::new(read_socket, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Value>::default(),
+    );
+    while let Some(msg) = deserializer.try_next().await.map_err(|e| e.to_string())? {
+        println!("{}", msg.get("lines").and_then(|l| l.as_str()).unwrap_or_default());
+    }
+    Ok(())
+}
+
+// the candidates for the last word, those needing the daemon are left out when it's not running
+async fn complete(socket_path: &str, command_line: &Command, words: &[String]) -> Vec<String> {
+    let current = words.last().map(|w| w.as_str()).unwrap_or("");
+    let ask = |command: &str, params: Value| {
+        try_send_request(
+            socket_path,
+            json!({"subject": "complete", "command": command, "params": params}),
+        )
+    };
+    let candidates: Vec<String> = match shell::completion_for(command_line, words) {
+        shell::Completion::Words(words) => words,
+        shell::Completion::Files => vec![],
+        shell::Completion::Daemon(kind, params) => match ask(kind, Value::Object(params)).await {
+            Ok(lines) => lines.lines().map(String::from).collect(),
+            Err(_) => vec![],
+        },
+        shell::Completion::ActionArgs {
+            plugin,
+            action,
+            words,
+        } => {
+            let request = json!({
+                "subject": "plugin",
+                "command": "describe-action",
+                "params": {"plugin": plugin, "action": action}
+            });
+            let descriptor: ActionDescriptor = match try_send_request(socket_path, request)
+                .await
+                .ok()
+                .and_then(|response| serde_json::from_str(&response).ok())
+            {
+                Some(descriptor) => descriptor,
+                None => return vec![],
+            };
+            let given = words.iter().filter(|w| !w.starts_with('-')).count();
+            if descriptor.needs_project && given == 0 && !current.starts_with('-') {
+                match ask("projects", json!({})).await {
+                    Ok(lines) => lines.lines().map(String::from).collect(),
+                    Err(_) => vec![],
+                }
+            } else {
+                descriptor
+                    .params
+                    .iter()
+                    .filter(|p| !p.positional)
+                    .map(|p| format!("--{}", p.name))
+                    .collect()
+            }
+        }
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current))
+        .collect()
+}
+
+// the action arguments are parsed here, following the descriptor published by the plugin
+async fn run_plugin_action(socket_path: &str, args: &clap::ArgMatches) {
+    let plugin = args.get_one::<String>("plugin").unwrap();
+    let action = args.get_one::<String>("action").unwrap();
+    let words: Vec<String> = args
+        .get_many::<String>("args")
+        .map(|words| words.cloned().collect())
+        .unwrap_or_default();
+
+    let response = send_request(
+        socket_path,
+        json!({
+            "subject": "plugin",
+            "command": "describe-action",
+            "params": {"plugin": plugin, "action": action}
+        }),
+    )
+    .await;
+    let descriptor: ActionDescriptor = match serde_json::from_str(&response) {
+        Ok(descriptor) => descriptor,
+        Err(_) => {
+            eprintln!("{}", response);
+            std::process::exit(1);
+        }
+    };
+    let matches = action_command(plugin, &descriptor)
+        .get_matches_from(std::iter::once(action.clone()).chain(words));
+
+    let mut params = json!({
+        "plugin": plugin,
+        "action": action,
+        "args": action_arguments(&descriptor, &matches),
+    });
+    if descriptor.needs_project {
+        params["project-name"] = json!(matches.get_one::<String>("project-name").unwrap());
+    }
+    // plugins may act differently when we are called from inside tmux
+    if let Ok(tmux) = env::var("TMUX") {
+        params["client-tmux"] = json!(tmux);
+    }
+    if let Ok(pane) = env::var("TMUX_PANE") {
+        params["client-pane"] = json!(pane);
+    }
+    let response = send_request(
+        socket_path,
+        json!({"subject": "plugin", "command": "run", "params": params}),
+    )
+    .await;
+    // scripts rely on the exit status, the attach script of the terminal plugin among them
+    if response.starts_with("Error:") {
+        eprintln!("{}", response);
+        std::process::exit(1);
+    }
+    println!("{}", response);
+}
+
+pub fn action_command(plugin: &str, descriptor: &ActionDescriptor) -> Command {
+    let mut command = Command::new(descriptor.name.clone())
+        .bin_name(format!("project-pilot plugin run {} {}", plugin, descriptor.name))
+        .about(descriptor.description.clone());
+    if descriptor.needs_project {
+        command = command.arg(
+            Arg::new("project-name")
+                .required(true)
+                .help("the project to act on"),
+        );
+    }
+    let last_positional = descriptor.params.iter().rposition(|p| p.positional);
+    for (index, param) in descriptor.params.iter().enumerate() {
+        let mut arg = Arg::new(param.name.clone())
+            .help(param.description.clone())
+            .required(param.required);
+        if !param.positional {
+            arg = arg.long(param.name.clone());
+        }
+        arg = match param.kind {
+            ParamKind::String => arg.value_parser(clap::value_parser!(String)),
+            ParamKind::Integer => arg
+                .value_parser(clap::value_parser!(i64))
+                .allow_negative_numbers(true),
+            ParamKind::Bool if param.positional => arg.value_parser(clap::value_parser!(bool)),
+            ParamKind::Bool => arg.action(ArgAction::SetTrue),
+        };
+        if param.multiple && param.positional {
+            arg = arg.num_args(1..);
+            // lets commands like `ls -la` through untouched
+            if Some(index) == last_positional {
+                arg = arg.trailing_var_arg(true).allow_hyphen_values(true);
+            }
+        } else if param.multiple {
+            arg = arg.action(ArgAction::Append);
+        }
+        command = command.arg(arg);
+    }
+    command
+}
+
+pub fn action_arguments(descriptor: &ActionDescriptor, matches: &clap::ArgMatches) -> Value {
+    fn values<T: Clone + Send + Sync + serde::Serialize + 'static>(
+        matches: &clap::ArgMatches,
+        name: &str,
+    ) -> Vec<Value> {
+        matches
+            .get_many::<T>(name)
+            .map(|values| values.map(|v| json!(v)).collect())
+            .unwrap_or_default()
+    }
+    let mut arguments = serde_json::Map::new();
+    for param in &descriptor.params {
+        let values = match param.kind {
+            ParamKind::Bool if !param.positional => vec![json!(matches.get_flag(&param.name))],
+            ParamKind::Bool => values::<bool>(matches, &param.name),
+            ParamKind::Integer => values::<i64>(matches, &param.name),
+            ParamKind::String => values::<String>(matches, &param.name),
+        };
+        if values.is_empty() {
+            continue;
+        }
+        let value = if param.multiple {
+            Value::Array(values)
+        } else {
+            values.into_iter().next().unwrap()
+        };
+        arguments.insert(param.name.clone(), value);
+    }
+    Value::Object(arguments)
+}
+
+// checks what can be checked without the daemon, then asks it about projects and plugins
+async fn doctor(config_path: &Path, socket_path: &str) {
+    let mut report = vec![HealthReport {
+        source: "configuration".to_string(),
+        checks: vec![check_config_file(config_path)],
+    }];
+    let daemon = check_daemon(socket_path).await;
+    let daemon_up = daemon.iter().all(|check| check.ok);
+    report.push(HealthReport {
+        source: "daemon".to_string(),
+        checks: daemon,
     });
+    if daemon_up {
+        let request = json!({"subject": "plugin", "command": "health", "params": {}});
+        match try_send_request(socket_path, request)
+            .await
+            .and_then(|r| serde_json::from_str::<Vec<HealthReport>>(&r).map_err(|e| e.to_string()))
+        {
+            Ok(reports) => report.extend(reports),
+            Err(e) => report.push(HealthReport {
+                source: "plugins".to_string(),
+                checks: vec![HealthCheck::failed(
+                    "health",
+                    &e,
+                    "restart the daemon, it may be older than this cli",
+                )],
+            }),
+        }
+    }
+
+    let mut problems = 0;
+    for part in report.iter().filter(|part| !part.checks.is_empty()) {
+        println!("{}", part.source);
+        for check in &part.checks {
+            let status = if check.ok { "ok" } else { "FAIL" };
+            println!("  {:<5} {}: {}", status, check.name, check.message);
+            if let Some(fix) = check.fix.as_ref().filter(|_| !check.ok) {
+                println!("        fix: {}", fix);
+                problems += 1;
+            }
+        }
+    }
+    if problems == 0 {
+        println!("no problems found");
+    } else {
+        println!("problems found: {}", problems);
+        std::process::exit(1);
+    }
+}
+
+fn check_config_file(config_path: &Path) -> HealthCheck {
+    let name = "config file";
+    if !config_path.exists() {
+        return HealthCheck::ok(
+            name,
+            &format!("{} not created yet, the daemon will write it", config_path.display()),
+        );
+    }
+    let content = match std::fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) => {
+            return HealthCheck::failed(
+                name,
+                &format!("cannot read {}: {}", config_path.display(), e),
+                &format!("check the permissions of {}", config_path.display()),
+            )
+        }
+    };
+    let config = match toml::from_str::<Config>(&content) {
+        Ok(config) => config,
+        Err(e) => {
+            return HealthCheck::failed(
+                name,
+                &format!("{} is invalid: {}", config_path.display(), e),
+                "fix the file, the daemon does not start with it",
+            )
+        }
+    };
+    let read_only = std::fs::metadata(config_path)
+        .map(|m| m.permissions().readonly())
+        .unwrap_or(false);
+    if read_only {
+        return HealthCheck::failed(
+            name,
+            &format!("{} is read only, changes cannot be saved", config_path.display()),
+            &format!("run `chmod u+w {}`", config_path.display()),
+        );
+    }
+    HealthCheck::ok(
+        name,
+        &format!("{}, {} projects", config_path.display(), config.projects.len()),
+    )
+}
+
+fn check_socket(socket_path: &str) -> HealthCheck {
+    let name = "socket";
+    let metadata = match std::fs::metadata(socket_path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            return HealthCheck::failed(
+                name,
+                &format!("{} not found, the daemon is not running", socket_path),
+                "start it with `project-pilot daemon start`",
+            )
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        return HealthCheck::failed(
+            name,
+            &format!("{} is not a socket", socket_path),
+            "remove it and run `project-pilot daemon start`",
+        );
+    }
+    if metadata.permissions().mode() & 0o002 != 0 {
+        return HealthCheck::failed(
+            name,
+            &format!("any user can send commands through {}", socket_path),
+            &format!("run `chmod o-w {}`", socket_path),
+        );
+    }
+    HealthCheck::ok(name, socket_path)
+}
+
+async fn check_daemon(socket_path: &str) -> Vec<HealthCheck> {
+    let socket = check_socket(socket_path);
+    if !socket.ok {
+        return vec![socket];
+    }
+    let request = json!({"subject": "daemon", "command": "status", "params": {}});
+    let daemon = match try_send_request(socket_path, request).await {
+        Ok(status) => HealthCheck::ok("daemon", &status),
+        Err(e) => HealthCheck::failed(
+            "daemon",
+            &format!("not answering: {}", e),
+            "the socket is probably stale, run `project-pilot daemon start --force`",
+        ),
+    };
+    vec![socket, daemon]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picked_lines_give_back_the_project() {
+        let name = r#"it's a "project"; with spaces"#;
+        let project = json!({"name": name, "path": "/home/me/code", "branch": "main"});
+        let line = pick_line(&project, 40, "/home/me", 0);
+        assert!(line.ends_with("~/code  [main]  never used"), "{}", line);
+        assert_eq!(picked_project(&format!("{}\n", line)), Some(name));
+
+        let other = json!({"name": "api", "current": true, "last-used": 0});
+        let line = pick_line(&other, name.len(), "/home/me", 120);
+        assert_eq!(picked_project(&line), Some("api"));
+    }
+
+    #[test]
+    fn typed_or_empty_selections() {
+        assert_eq!(picked_project("web app\n"), Some("web app"));
+        assert_eq!(picked_project("\n"), None);
+        assert_eq!(picked_project(""), None);
+    }
 }
@@ -1,20 +1,86 @@
+use crate::history::History;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
-use toml;
 use std::collections::HashMap;
+use std::sync::Mutex;
+use serde_json::{Map, Value};
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct Project {
     pub name: String,
     pub plugins: Vec<String>,
     pub properties: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contexts: Vec<Context>,
+}
+
+impl Project {
+    pub fn context(&self, name: &str) -> Option<&Context> {
+        self.contexts.iter().find(|c| c.name == name)
+    }
+
+    // applies what a plugin changed from before to after, keeping the other changes made to
+    // this project meanwhile. Whether anything was applied
+    pub fn apply_changes(&mut self, before: &Project, after: &Project) -> bool {
+        let mut changed = false;
+        for (key, value) in &after.properties {
+            if before.properties.get(key) != Some(value) && self.properties.get(key) != Some(value)
+            {
+                self.properties.insert(key.clone(), value.clone());
+                changed = true;
+            }
+        }
+        for key in before.properties.keys() {
+            if !after.properties.contains_key(key) && self.properties.remove(key).is_some() {
+                changed = true;
+            }
+        }
+        if after.plugins != before.plugins && self.plugins != after.plugins {
+            self.plugins = after.plugins.clone();
+            changed = true;
+        }
+        if after.contexts != before.contexts && self.contexts != after.contexts {
+            self.contexts = after.contexts.clone();
+            changed = true;
+        }
+        changed
+    }
+}
+
+// a worktree or a branch of a project, living in its own directory
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Context {
+    pub name: String,
+    pub path: String,
+}
+
+// `project/context` is a context of the project, project names cannot contain `/`
+pub fn split_context(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('/') {
+        Some((project, context)) => (project, Some(context)),
+        None => (name, None),
+    }
+}
+
+pub fn context_name(project: &str, context: Option<&str>) -> String {
+    match context {
+        Some(context) => format!("{}/{}", project, context),
+        None => project.to_string(),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_context: Option<String>,
     pub projects: Vec<Project>,
     pub properties: HashMap<String, String>,
+    // the `[plugins.<name>]` tables, handed to the plugins when they are registered
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub plugins: HashMap<String, Map<String, Value>>,
 }
 
 impl Config {
@@ -42,15 +108,108 @@ impl Config {
 pub struct SavedConfig {
     pub path: PathBuf,
     pub data: Config,
+    // the canonical directories of the projects and contexts, until the next save
+    directories: Mutex<Option<Vec<(PathBuf, String)>>>,
 }
 
 impl SavedConfig {
     pub fn new(path: PathBuf) -> Self {
         let config = Config::load(&path);
-        SavedConfig { path, data:config }
+        SavedConfig { path, data:config, directories: Mutex::new(None) }
     }
 
     pub fn save(&self) {
         self.data.save(&self.path);
+        *self.directories.lock().unwrap() = None;
+    }
+
+    // the directory of each project having a path and of each context, with the
+    // `project/context` owning it. canonicalized once, the prompt asks for them often
+    pub fn directories(&self) -> Vec<(PathBuf, String)> {
+        let mut directories = self.directories.lock().unwrap();
+        directories
+            .get_or_insert_with(|| {
+                let canonical = |directory: &str| {
+                    fs::canonicalize(directory).unwrap_or_else(|_| PathBuf::from(directory))
+                };
+                let mut found = Vec::new();
+                for project in &self.data.projects {
+                    if let Some(directory) = project.properties.get("path") {
+                        found.push((canonical(directory), context_name(&project.name, None)));
+                    }
+                    for context in &project.contexts {
+                        let owner = context_name(&project.name, Some(&context.name));
+                        found.push((canonical(&context.path), owner));
+                    }
+                }
+                found
+            })
+            .clone()
+    }
+
+    // the switch history lives next to the configuration
+    pub fn history(&self) -> History {
+        History::new(self.path.with_file_name("history.jsonl"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(properties: &[(&str, &str)]) -> Project {
+        Project {
+            name: "web".to_string(),
+            plugins: vec!["tmux".to_string()],
+            properties: properties
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            contexts: vec![],
+        }
+    }
+
+    #[test]
+    fn changes_are_applied_over_concurrent_ones() {
+        let before = project(&[("kept", "1"), ("changed", "1"), ("removed", "1")]);
+        let after = project(&[("kept", "1"), ("changed", "2"), ("added", "1")]);
+        // set meanwhile by someone else
+        let mut current = project(&[("kept", "1"), ("changed", "1"), ("removed", "1")]);
+        current.properties.insert("other".to_string(), "1".to_string());
+        current.plugins.push("git".to_string());
+
+        assert!(current.apply_changes(&before, &after));
+        let mut expected =
+            project(&[("kept", "1"), ("changed", "2"), ("added", "1"), ("other", "1")]);
+        expected.plugins.push("git".to_string());
+        assert_eq!(current, expected);
+        assert!(!current.apply_changes(&before, &after));
+    }
+
+    #[test]
+    fn directories_are_kept_until_saved() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = dir.path().join("src");
+        fs::create_dir(&source).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&source, &link).unwrap();
+        let mut config = SavedConfig::new(dir.path().join("config.toml"));
+        let mut web = project(&[("path", link.to_str().unwrap())]);
+        web.contexts.push(Context {
+            name: "wt".to_string(),
+            path: "/nowhere/wt".to_string(),
+        });
+        config.data.projects.push(web);
+
+        let source = fs::canonicalize(&source).unwrap();
+        let expected = vec![
+            (source.clone(), "web".to_string()),
+            (PathBuf::from("/nowhere/wt"), "web/wt".to_string()),
+        ];
+        assert_eq!(config.directories(), expected);
+        config.data.projects[0].contexts.clear();
+        assert_eq!(config.directories(), expected);
+        config.save();
+        assert_eq!(config.directories(), [(source, "web".to_string())]);
     }
 }
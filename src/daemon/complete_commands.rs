@@ -0,0 +1,75 @@
+use crate::config::{context_name, SavedConfig};
+use crate::daemon::Daemon;
+use crate::plugin::PluginFactory;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+impl Daemon {
+    // values for the shell completions, one per line, nothing when they cannot be found
+    pub async fn handle_complete(
+        config: &Arc<Mutex<SavedConfig>>,
+        plugin_manager: &Arc<Mutex<PluginFactory>>,
+        command: &str,
+        arguments: &serde_json::Map<String, Value>,
+    ) -> String {
+        let config = config.lock().await;
+        let plugin_manager = plugin_manager.lock().await;
+        let project = arguments
+            .get("project-name")
+            .and_then(|p| p.as_str())
+            .and_then(|name| config.data.projects.iter().find(|p| p.name == name));
+        let plugin = arguments.get("plugin").and_then(|p| p.as_str());
+        let mut values: Vec<String> = match command {
+            "projects" => {
+                let with_contexts =
+                    arguments.get("with-contexts").and_then(|w| w.as_bool()) == Some(true);
+                config
+                    .data
+                    .projects
+                    .iter()
+                    .flat_map(|project| {
+                        let contexts = project
+                            .contexts
+                            .iter()
+                            .filter(|_| with_contexts)
+                            .map(|c| context_name(&project.name, Some(&c.name)));
+                        std::iter::once(project.name.clone()).chain(contexts)
+                    })
+                    .collect()
+            }
+            "contexts" => project
+                .map(|p| p.contexts.iter().map(|c| c.name.clone()).collect())
+                .unwrap_or_default(),
+            // the plugins of a project, or all of them
+            "plugins" => match arguments.get("project-name") {
+                Some(_) => project.map(|p| p.plugins.clone()).unwrap_or_default(),
+                None => plugin_manager.plugin_names(),
+            },
+            // the properties of a project, or the global ones
+            "properties" => match arguments.get("project-name") {
+                Some(_) => project
+                    .map(|p| p.properties.keys().cloned().collect())
+                    .unwrap_or_default(),
+                None => config.data.properties.keys().cloned().collect(),
+            },
+            "actions" => match plugin.and_then(|p| plugin_manager.get_plugin(p)) {
+                Some(plugin) => plugin
+                    .lock()
+                    .await
+                    .list_actions()
+                    .into_iter()
+                    .map(|a| a.name)
+                    .collect(),
+                None => vec![],
+            },
+            "settings" => plugin
+                .and_then(|p| plugin_manager.plugin_info(p))
+                .map(|info| info.config.iter().map(|s| s.name.clone()).collect())
+                .unwrap_or_default(),
+            _ => return "Unknown command".to_string(),
+        };
+        values.sort();
+        values.join("\n")
+    }
+}
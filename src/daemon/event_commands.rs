@@ -1,5 +1,6 @@
 use crate::config::{Project, SavedConfig};
 use crate::event::Event;
+use crate::plugin::hooks::GLOBAL_HOOKS;
 use crate::plugin::{Plugin, PluginFactory, TmuxPlugin};
 use crate::daemon::Daemon;
 use futures::sink::SinkExt;
@@ -8,16 +9,23 @@ use std::borrow::BorrowMut;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixListener;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use tokio_serde::formats::SymmetricalJson;
 use tokio_stream::StreamExt;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
+// every dispatched event is also sent to the `event subscribe` connections, like the dashboard
+static FEED: OnceLock<broadcast::Sender<Value>> = OnceLock::new();
+
+pub fn event_feed() -> &'static broadcast::Sender<Value> {
+    FEED.get_or_init(|| broadcast::channel(64).0)
+}
+
 impl Daemon {
     pub async fn handle_event(
         config: &Arc<Mutex<SavedConfig>>,
@@ -33,11 +41,29 @@ impl Daemon {
                     arguments.get("event-name")
                 {
                     if let Ok(event) = Event::from_str(event_name) {
-                        for project in config.data.projects.iter_mut() {
-                            for plugin_name in &project.plugins.clone() {
-                                let plugin =
-                                    &plugin_manager.get_plugin(plugin_name).unwrap().lock().await;
-                                plugin.on_event(event, project, arguments);
+                        // all the projects, or just the given one
+                        let only = arguments.get("project-name").and_then(|p| p.as_str());
+                        let projects: Vec<(String, Vec<String>)> = config
+                            .data
+                            .projects
+                            .iter()
+                            .filter(|p| only.map(|only| p.name == only).unwrap_or(true))
+                            .map(|p| (p.name.clone(), p.plugins.clone()))
+                            .collect();
+                        // the global hooks run once, with the first project having the hooks
+                        let mut arguments = arguments.clone();
+                        for (project_name, plugins) in projects {
+                            Daemon::dispatch_event(
+                                &mut config,
+                                &plugin_manager,
+                                event,
+                                &project_name,
+                                &plugins,
+                                &arguments,
+                            )
+                            .await;
+                            if plugins.iter().any(|p| p == "hooks") {
+                                arguments.insert(GLOBAL_HOOKS.to_string(), json!(false));
                             }
                         }
                         "Event triggered".to_string()
@@ -58,4 +84,50 @@ impl Daemon {
             _ => "Unknown command".to_string(),
         }
     }
+
+    // sends the event to the given plugins of a project, in dependency order, saving the
+    // changes they make.
+    // plugins also receive the global properties, as "global-properties"
+    pub async fn dispatch_event(
+        config: &mut SavedConfig,
+        plugin_manager: &PluginFactory,
+        event: Event,
+        project_name: &str,
+        plugin_names: &[String],
+        arguments: &serde_json::Map<String, Value>,
+    ) {
+        // nobody may be listening
+        let _ = event_feed().send(json!({
+            "event": event.to_string(),
+            "project-name": project_name,
+            "arguments": arguments,
+        }));
+        let mut arguments = arguments.clone();
+        arguments.insert(
+            "global-properties".to_string(),
+            json!(config.data.properties),
+        );
+        let before = config.data.projects.clone();
+        let project = match config
+            .data
+            .projects
+            .iter_mut()
+            .find(|p| p.name == project_name)
+        {
+            Some(project) => project,
+            None => return,
+        };
+        for plugin_name in plugin_manager.dispatch_order(plugin_names) {
+            match plugin_manager.get_plugin(&plugin_name) {
+                Some(plugin) => plugin.lock().await.on_event(event, project, &arguments),
+                None => eprintln!(
+                    "plugin {} of project {} not found, {} not delivered",
+                    plugin_name, project_name, event
+                ),
+            }
+        }
+        if config.data.projects != before {
+            config.save();
+        }
+    }
 }
@@ -50,7 +50,7 @@ impl Daemon {
             "del-property" => {
                 let property = arguments.get("property").unwrap().as_str().unwrap();
 
-                if let Some(_) = config.data.properties.remove(property) {
+                if config.data.properties.remove(property).is_some() {
                     config.save();
                     format!("Global property {} removed", property)
                 } else {
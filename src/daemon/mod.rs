@@ -1,6 +1,10 @@
 use crate::config::{Project, SavedConfig};
 use crate::event::Event;
-use crate::plugin::{Plugin, PluginFactory, TmuxPlugin};
+use crate::plugin::{
+    ComposePlugin, DaemonRequest, EditorPlugin, EnvPlugin, GitPlugin, HooksPlugin, NotifyPlugin,
+    Plugin, PluginFactory, ServicesPlugin, SwayPlugin, TerminalPlugin, TmuxPlugin,
+};
+use crate::plugin::services;
 use futures::sink::SinkExt;
 use serde_json::{json, Value};
 use std::borrow::BorrowMut;
@@ -12,28 +16,60 @@ use std::sync::Arc;
 use strum::IntoEnumIterator;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::sync::Mutex;
 use tokio_serde::formats::SymmetricalJson;
 use tokio_stream::StreamExt;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
+mod complete_commands;
 mod event_commands;
 mod global_commands;
 mod plugin_commands;
 mod project_commands;
 
+// seconds between two scheduled reconciliations, see the `reconcile-interval` global property
+const DEFAULT_RECONCILE_INTERVAL: u64 = 60;
+
+// seconds an external plugin has to answer, see the `plugin-timeout` global property
+const DEFAULT_PLUGIN_TIMEOUT: u64 = 10;
+
+// a global property counting seconds, at least one. Bad values are reported and replaced
+fn seconds_property(config: &SavedConfig, name: &str, default: u64) -> u64 {
+    match config.data.properties.get(name).map(|value| (value, value.parse::<u64>())) {
+        None => default,
+        Some((_, Ok(0))) => {
+            eprintln!("{} must be at least 1 second, using 1", name);
+            1
+        }
+        Some((_, Ok(seconds))) => seconds,
+        Some((value, Err(_))) => {
+            eprintln!("invalid {} '{}', using {}", name, value, default);
+            default
+        }
+    }
+}
+
 pub struct Daemon {
+    plugin_dir: PathBuf,
+    script_dir: PathBuf,
     config: Arc<Mutex<SavedConfig>>,
     plugin_manager: Arc<Mutex<PluginFactory>>,
     should_stop: Arc<Mutex<bool>>,
+    plugin_requests: Option<UnboundedReceiver<DaemonRequest>>,
 }
 
 impl Daemon {
-    pub fn new(config_path: PathBuf) -> Self {
+    pub fn new(config_path: PathBuf, plugin_dir: PathBuf, script_dir: PathBuf) -> Self {
+        let (request_sender, plugin_requests) = unbounded_channel();
         Daemon {
+            plugin_dir,
+            script_dir,
             config: Arc::new(Mutex::new(SavedConfig::new(config_path))),
-            plugin_manager: Arc::new(Mutex::new(PluginFactory::new())),
+            plugin_manager: Arc::new(Mutex::new(PluginFactory::new(request_sender))),
             should_stop: Arc::new(Mutex::new(false)),
+            plugin_requests: Some(plugin_requests),
         }
     }
 
@@ -41,8 +77,68 @@ impl Daemon {
         self.plugin_manager
             .lock()
             .await
-            .register_plugin(Mutex::new(Box::new(TmuxPlugin {})))
+            .register_plugin(Mutex::new(Box::new(TmuxPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(HooksPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(GitPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(EnvPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(ComposePlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(ServicesPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(EditorPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(TerminalPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(SwayPlugin::new())))
+            .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .register_plugin(Mutex::new(Box::new(NotifyPlugin::new())))
+            .await;
+        self.discover_external_plugins().await;
+        self.plugin_manager
+            .lock()
+            .await
+            .discover_library_plugins(&self.plugin_dir)
             .await;
+        self.plugin_manager
+            .lock()
+            .await
+            .discover_script_plugins(&self.script_dir)
+            .await;
+        self.configure_plugins().await;
+
+        self.schedule_reconcile().await;
+        self.serve_plugin_requests();
 
         if force && std::path::Path::new(socket_path).exists() {
             std::fs::remove_file(socket_path).unwrap();
@@ -50,6 +146,7 @@ impl Daemon {
         let listener = UnixListener::bind(socket_path).expect("Failed to bind socket");
         let sp2 = RefCell::new(socket_path.to_string());
         ctrlc::set_handler(move || {
+            services::terminate_all();
             println!("removing socket file");
             let socket_path = sp2.borrow().clone();
             if force && std::path::Path::new(&socket_path).exists() {
@@ -89,9 +186,83 @@ impl Daemon {
         println!("--- stopping daemon ---");
         if force && std::path::Path::new(&socket_path).exists() {
             println!("--- removing socket file ---");
-            std::fs::remove_file(&socket_path).unwrap();
+            std::fs::remove_file(socket_path).unwrap();
         }
     }
+    // a plugin with an invalid configuration keeps running with its defaults
+    async fn configure_plugins(&self) {
+        let config = self.config.lock().await;
+        let plugin_manager = self.plugin_manager.lock().await;
+        for name in plugin_manager.plugin_names() {
+            if let Err(e) = Daemon::configure_plugin(&config, &plugin_manager, &name).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    async fn discover_external_plugins(&self) {
+        let timeout = seconds_property(
+            &*self.config.lock().await,
+            "plugin-timeout",
+            DEFAULT_PLUGIN_TIMEOUT,
+        );
+        let mut dirs = vec![self.plugin_dir.clone()];
+        if let Some(path) = std::env::var_os("PATH") {
+            dirs.extend(std::env::split_paths(&path));
+        }
+        self.plugin_manager
+            .lock()
+            .await
+            .discover_external_plugins(&dirs, std::time::Duration::from_secs(timeout))
+            .await;
+    }
+
+    // requests sent by the plugins are handled one at a time, after the plugin call that sent
+    // them has released the locks
+    fn serve_plugin_requests(&mut self) {
+        let mut plugin_requests = self.plugin_requests.take().unwrap();
+        let config = Arc::clone(&self.config);
+        let plugin_manager = Arc::clone(&self.plugin_manager);
+        let should_stop = Arc::clone(&self.should_stop);
+        tokio::spawn(async move {
+            while let Some(request) = plugin_requests.recv().await {
+                let response = Daemon::handle_request(
+                    &config,
+                    &plugin_manager,
+                    &should_stop,
+                    &request.subject,
+                    &request.command,
+                    &request.params,
+                )
+                .await;
+                println!(
+                    "plugin request {} {}: {}",
+                    request.subject, request.command, response
+                );
+            }
+        });
+    }
+
+    // periodically let every plugin compare its state with the configuration
+    async fn schedule_reconcile(&self) {
+        let interval = seconds_property(
+            &*self.config.lock().await,
+            "reconcile-interval",
+            DEFAULT_RECONCILE_INTERVAL,
+        );
+        let config = Arc::clone(&self.config);
+        let plugin_manager = Arc::clone(&self.plugin_manager);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+            loop {
+                ticker.tick().await;
+                for line in Daemon::reconcile_in_background(&config, &plugin_manager).await {
+                    eprintln!("reconcile: {}", line);
+                }
+            }
+        });
+    }
+
     async fn handle_connection(&mut self,stream: tokio::net::UnixStream,cancellation_token: tokio_util::sync::CancellationToken) {
         println!("--- new connection ---");
 
@@ -130,6 +301,31 @@ impl Daemon {
                         let command = msg.get("command").unwrap().as_str().unwrap();
                         let params = msg.get("params").unwrap().as_object().unwrap();
                         dbg!((subject,command,&params));
+                        if subject == "event" && command == "subscribe" {
+                            // from now on the connection only streams the events, a message each
+                            let mut events = event_commands::event_feed().subscribe();
+                            loop {
+                                tokio::select! {
+                                    event = events.recv() => match event {
+                                        Ok(event) => {
+                                            let message = json!({"lines": event.to_string()});
+                                            if serializer.send(message).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                        Err(RecvError::Lagged(_)) => continue,
+                                        Err(RecvError::Closed) => break,
+                                    },
+                                    // the client went away
+                                    message = deserializer.try_next() => {
+                                        if !matches!(message, Ok(Some(_))) {
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                            break;
+                        }
                         let response = Daemon::handle_request(
                             &config,
                             &plugin_manager,
@@ -167,6 +363,7 @@ impl Daemon {
             "project" => Daemon::handle_project(config, plugin_manager, command, arguments).await,
             "event" => Daemon::handle_event(config, plugin_manager, command, arguments).await,
             "plugin" => Daemon::handle_plugin(config, plugin_manager, command, arguments).await,
+            "complete" => Daemon::handle_complete(config, plugin_manager, command, arguments).await,
             "daemon" => {
                 Daemon::handle_daemon(config, plugin_manager, should_stop, command, arguments).await
             }
@@ -184,11 +381,11 @@ impl Daemon {
         let plugin_manager = plugin_manager.lock().await;
         match command {
             "status" => {
-                format!("up and running")
+                "up and running".to_string()
             }
             "stop" => {
                 *should_stop.lock().await = true;
-                format!("stopping")
+                "stopping".to_string()
             }
             _ => "Unknown command".to_string(),
         }
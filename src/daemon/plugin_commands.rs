@@ -1,7 +1,8 @@
 use crate::config::{Project, SavedConfig};
 use crate::daemon::Daemon;
 use crate::event::Event;
-use crate::plugin::{Plugin, PluginFactory, TmuxPlugin};
+use crate::plugin::action::validate_params;
+use crate::plugin::{HealthCheck, HealthReport, Plugin, PluginFactory, TmuxPlugin};
 use futures::sink::SinkExt;
 use serde_json::{json, Value};
 use std::borrow::BorrowMut;
@@ -10,6 +11,7 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 use strum::IntoEnumIterator;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixListener;
@@ -25,28 +27,485 @@ impl Daemon {
         command: &str,
         arguments: &serde_json::Map<String, Value>,
     ) -> String {
+        let shared_config = Arc::clone(config);
+        let shared_plugin_manager = Arc::clone(plugin_manager);
         let mut config = config.lock().await;
-        let plugin_manager = plugin_manager.lock().await;
+        let mut plugin_manager = plugin_manager.lock().await;
         match command {
             "run" => {
                 let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
                 let action = arguments.get("action").unwrap().as_str().unwrap();
-                let project_name = arguments.get("project-name").unwrap().as_str().unwrap();
-                let project = config.data.projects.iter_mut().find(|p| p.name == project_name).unwrap();
-                let plugin = plugin_manager.get_plugin(plugin_name).unwrap().lock().await;
-                plugin.run_action(action, project, arguments).unwrap()
+                let plugin = match plugin_manager.get_plugin(plugin_name) {
+                    Some(plugin) => Arc::clone(plugin),
+                    None => return format!("Plugin {} not found", plugin_name),
+                };
+                let plugin = plugin.lock().await;
+                let descriptor = match plugin.list_actions().into_iter().find(|a| a.name == action) {
+                    Some(descriptor) => descriptor,
+                    None => return format!("Action {} not found for plugin {}", action, plugin_name),
+                };
+                let empty_args = serde_json::Map::new();
+                let args = arguments
+                    .get("args")
+                    .and_then(|args| args.as_object())
+                    .unwrap_or(&empty_args);
+                if let Err(e) = descriptor.validate(args) {
+                    return format!("Error: {}", e);
+                }
+                let mut arguments = arguments.clone();
+                arguments.insert(
+                    "global-properties".to_string(),
+                    json!(config.data.properties),
+                );
+                let mut project = if descriptor.needs_project {
+                    let project_name = match arguments.get("project-name").and_then(|p| p.as_str()) {
+                        Some(project_name) => project_name,
+                        None => return format!("Error: action {} needs a project", action),
+                    };
+                    match config.data.projects.iter().find(|p| p.name == project_name) {
+                        Some(project) => Some(project.clone()),
+                        None => return format!("Error: project {} not found", project_name),
+                    }
+                } else {
+                    None
+                };
+                // the action works on a copy of the project, the daemon stays available while
+                // it runs, external plugins can take up to plugin-timeout to answer
+                let before = project.clone();
+                drop(plugin_manager);
+                drop(config);
+                let started = Instant::now();
+                let result = tokio::task::block_in_place(|| {
+                    plugin.run_action(action, project.as_mut(), &arguments)
+                });
+                let duration = started.elapsed();
+                drop(plugin);
+                let mut config = shared_config.lock().await;
+                let plugin_manager = shared_plugin_manager.lock().await;
+                // actions are allowed to change the project, what changed meanwhile is kept
+                if let (Some(before), Some(project)) = (before, project) {
+                    let changed = config
+                        .data
+                        .projects
+                        .iter_mut()
+                        .find(|p| p.name == project.name)
+                        .is_some_and(|current| current.apply_changes(&before, &project));
+                    if changed {
+                        config.save();
+                    }
+                }
+                // the plugins of the project, or of the current one, hear about the outcome
+                let project_name = if descriptor.needs_project {
+                    arguments
+                        .get("project-name")
+                        .and_then(|p| p.as_str())
+                        .map(String::from)
+                } else {
+                    config.data.current_project.clone()
+                };
+                let project_plugins = project_name.as_ref().and_then(|name| {
+                    config
+                        .data
+                        .projects
+                        .iter()
+                        .find(|p| &p.name == name)
+                        .map(|p| p.plugins.clone())
+                });
+                if let (Some(project_name), Some(project_plugins)) =
+                    (&project_name, project_plugins)
+                {
+                    let mut outcome = serde_json::Map::new();
+                    outcome.insert("project-name".to_string(), json!(project_name));
+                    outcome.insert("plugin".to_string(), json!(plugin_name));
+                    outcome.insert("action".to_string(), json!(action));
+                    outcome.insert("success".to_string(), json!(result.is_ok()));
+                    outcome.insert(
+                        "duration-ms".to_string(),
+                        json!(duration.as_millis() as u64),
+                    );
+                    if let Err(e) = &result {
+                        outcome.insert("message".to_string(), json!(e));
+                        Daemon::dispatch_event(
+                            &mut config,
+                            &plugin_manager,
+                            Event::PluginError,
+                            project_name,
+                            &project_plugins,
+                            &outcome,
+                        )
+                        .await;
+                    }
+                    Daemon::dispatch_event(
+                        &mut config,
+                        &plugin_manager,
+                        Event::ActionComplete,
+                        project_name,
+                        &project_plugins,
+                        &outcome,
+                    )
+                    .await;
+                }
+                match result {
+                    Ok(output) => output,
+                    Err(e) => format!("Error: {}", e),
+                }
+            }
+            "list" => {
+                let mut descriptions = Vec::new();
+                for name in plugin_manager.plugin_names() {
+                    descriptions.push(describe_plugin(&config, &plugin_manager, &name).await);
+                }
+                descriptions.join("\n\n")
+            }
+            "info" => {
+                let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                if plugin_manager.get_plugin(plugin_name).is_none() {
+                    return format!("Plugin {} not found", plugin_name);
+                }
+                let mut lines = vec![describe_plugin(&config, &plugin_manager, plugin_name).await];
+                let values = config.data.plugins.get(plugin_name).cloned().unwrap_or_default();
+                let settings = plugin_manager
+                    .plugin_info(plugin_name)
+                    .map(|info| info.config.clone())
+                    .unwrap_or_default();
+                lines.push(if settings.is_empty() { "  config: none" } else { "  config:" }.to_string());
+                for setting in settings {
+                    let value = match values.get(&setting.name) {
+                        Some(value) => value.to_string(),
+                        None => "unset".to_string(),
+                    };
+                    lines.push(format!("    {} = {} - {}", setting.name, value, setting.description));
+                }
+                lines.join("\n")
+            }
+            "set-config" => {
+                // without a value the setting is removed
+                let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                let key = arguments.get("key").unwrap().as_str().unwrap();
+                let setting = match plugin_manager
+                    .plugin_info(plugin_name)
+                    .and_then(|info| info.config.iter().find(|s| s.name == key))
+                {
+                    Some(setting) => setting.clone(),
+                    None if plugin_manager.get_plugin(plugin_name).is_none() => {
+                        return format!("Plugin {} not found", plugin_name)
+                    }
+                    None => return format!("Plugin {} has no setting {}", plugin_name, key),
+                };
+                let mut table = config.data.plugins.get(plugin_name).cloned().unwrap_or_default();
+                match arguments.get("value").and_then(|v| v.as_str()) {
+                    Some(value) => match setting.parse(value) {
+                        Ok(value) => {
+                            table.insert(key.to_string(), value);
+                        }
+                        Err(e) => return format!("Error: {}", e),
+                    },
+                    None => {
+                        table.remove(key);
+                    }
+                }
+                if let Err(e) = plugin_manager.configure_plugin(plugin_name, &table).await {
+                    return format!("Error: {}", e);
+                }
+                if table.is_empty() {
+                    config.data.plugins.remove(plugin_name);
+                } else {
+                    config.data.plugins.insert(plugin_name.to_string(), table);
+                }
+                config.save();
+                format!("Configuration of plugin {} updated", plugin_name)
+            }
+            "health" => {
+                // answers with json, for `project-pilot doctor`
+                let mut report = vec![HealthReport {
+                    source: "projects".to_string(),
+                    checks: check_configuration(&config, &plugin_manager),
+                }];
+                for name in plugin_manager.plugin_names() {
+                    let checks = plugin_manager.get_plugin(&name).unwrap().lock().await.health_check();
+                    report.push(HealthReport {
+                        source: format!("plugin {}", name),
+                        checks,
+                    });
+                }
+                json!(report).to_string()
             }
             "list-actions" => {
                 let plugin = arguments.get("plugin").unwrap().as_str().unwrap();
-                return plugin_manager
-                    .get_plugin(plugin)
-                    .unwrap()
-                    .lock()
+                match plugin_manager.get_plugin(plugin) {
+                    Some(plugin) => plugin
+                        .lock()
+                        .await
+                        .list_actions()
+                        .iter()
+                        .map(|action| action.usage())
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    None => format!("Plugin {} not found", plugin),
+                }
+            }
+            "describe-action" => {
+                // used by the cli to parse the action arguments, answers with json
+                let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                let action = arguments.get("action").unwrap().as_str().unwrap();
+                let plugin = match plugin_manager.get_plugin(plugin_name) {
+                    Some(plugin) => plugin.lock().await,
+                    None => return format!("Plugin {} not found", plugin_name),
+                };
+                match plugin.list_actions().into_iter().find(|a| a.name == action) {
+                    Some(descriptor) => json!(descriptor).to_string(),
+                    None => format!("Action {} not found for plugin {}", action, plugin_name),
+                }
+            }
+            "load" => {
+                let path = arguments.get("path").unwrap().as_str().unwrap();
+                match plugin_manager
+                    .load_library_plugin(std::path::Path::new(path))
                     .await
-                    .list_actions()
-                    .join(", ");
+                {
+                    Ok(name) => match Daemon::configure_plugin(&config, &plugin_manager, &name).await {
+                        Ok(()) => format!("Plugin {} loaded", name),
+                        Err(e) => format!("Plugin {} loaded without its configuration: {}", name, e),
+                    },
+                    Err(e) => format!("Error: {}", e),
+                }
+            }
+            "unload" => {
+                let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                let users: Vec<String> = config
+                    .data
+                    .projects
+                    .iter()
+                    .filter(|p| p.plugins.contains(&plugin_name.to_string()))
+                    .map(|p| p.name.clone())
+                    .collect();
+                if !users.is_empty() {
+                    return format!(
+                        "Plugin {} is enabled for projects {}, disable it first",
+                        plugin_name,
+                        users.join(", ")
+                    );
+                }
+                match plugin_manager.unregister_plugin(plugin_name) {
+                    Ok(()) => format!("Plugin {} unloaded", plugin_name),
+                    Err(e) => format!("Error: {}", e),
+                }
+            }
+            "reconcile" => {
+                let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                if plugin_manager.get_plugin(plugin_name).is_none() {
+                    return format!("Plugin {} not found", plugin_name);
+                }
+                let report =
+                    Daemon::reconcile_plugins(&mut config, &plugin_manager, &[plugin_name.to_string()])
+                        .await;
+                if report.is_empty() {
+                    format!("Plugin {} is in sync", plugin_name)
+                } else {
+                    report.join("\n")
+                }
             }
             _ => "Unknown command".to_string(),
         }
     }
+
+    // hands its `[plugins.<name>]` table to a plugin
+    pub async fn configure_plugin(
+        config: &SavedConfig,
+        plugin_manager: &PluginFactory,
+        plugin_name: &str,
+    ) -> Result<(), String> {
+        let table = config.data.plugins.get(plugin_name).cloned().unwrap_or_default();
+        plugin_manager.configure_plugin(plugin_name, &table).await
+    }
+
+    pub async fn reconcile_plugins(
+        config: &mut SavedConfig,
+        plugin_manager: &PluginFactory,
+        plugin_names: &[String],
+    ) -> Vec<String> {
+        let before = config.data.projects.clone();
+        let mut report = Vec::new();
+        for plugin_name in plugin_names {
+            if let Some(plugin) = plugin_manager.get_plugin(plugin_name) {
+                let plugin = plugin.lock().await;
+                report.extend(plugin.reconcile(&mut config.data.projects));
+            }
+        }
+        if config.data.projects != before {
+            config.save();
+        }
+        report
+    }
+
+    // the scheduled reconciliation works on a copy of the projects, so that the configuration
+    // stays available while the plugins query their tools, and writes back only the projects
+    // that nobody changed in the meantime
+    pub async fn reconcile_in_background(
+        config: &Arc<Mutex<SavedConfig>>,
+        plugin_manager: &Arc<Mutex<PluginFactory>>,
+    ) -> Vec<String> {
+        let before = config.lock().await.data.projects.clone();
+        let mut projects = before.clone();
+        let mut report = Vec::new();
+        {
+            let plugin_manager = plugin_manager.lock().await;
+            for plugin_name in plugin_manager.plugin_names() {
+                if let Some(plugin) = plugin_manager.get_plugin(&plugin_name) {
+                    let plugin = plugin.lock().await;
+                    report.extend(tokio::task::block_in_place(|| plugin.reconcile(&mut projects)));
+                }
+            }
+        }
+        let mut config = config.lock().await;
+        let mut changed = false;
+        for (old, new) in before.iter().zip(&projects).filter(|(old, new)| old != new) {
+            if let Some(current) = config.data.projects.iter_mut().find(|p| *p == old) {
+                *current = new.clone();
+                changed = true;
+            }
+        }
+        if changed {
+            config.save();
+        }
+        report
+    }
+}
+
+// a few lines about a registered plugin, for `plugin list`
+async fn describe_plugin(config: &SavedConfig, plugin_manager: &PluginFactory, name: &str) -> String {
+    let plugin = plugin_manager.get_plugin(name).unwrap().lock().await;
+    let info = plugin_manager.plugin_info(name).cloned().unwrap_or_default();
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let mut title = name.to_string();
+    if !info.version.is_empty() {
+        title = format!("{} {}", title, info.version);
+    }
+    if !info.description.is_empty() {
+        title = format!("{} - {}", title, info.description);
+    }
+    let source = match plugin_manager.plugin_source(name) {
+        Some(path) => path.display().to_string(),
+        None => "built in".to_string(),
+    };
+    let actions = plugin.list_actions().into_iter().map(|a| a.name).collect();
+    let projects = config
+        .data
+        .projects
+        .iter()
+        .filter(|p| p.plugins.iter().any(|plugin| plugin == name))
+        .map(|p| p.name.clone())
+        .collect();
+    [
+        title,
+        format!("  source: {}", source),
+        format!("  actions: {}", list(actions)),
+        format!("  events: {}", list(info.events)),
+        format!("  properties: {}", list(info.properties)),
+        format!("  arguments: {}", list(info.arguments)),
+        format!("  dependencies: {}", list(info.dependencies)),
+        format!("  projects: {}", list(projects)),
+    ]
+    .join("\n")
+}
+
+// the parts of the configuration that refer to plugins or projects
+fn check_configuration(config: &SavedConfig, plugin_manager: &PluginFactory) -> Vec<HealthCheck> {
+    let mut checks = Vec::new();
+    for project in &config.data.projects {
+        for plugin_name in &project.plugins {
+            let name = format!("{}/{}", project.name, plugin_name);
+            if plugin_manager.get_plugin(plugin_name).is_none() {
+                checks.push(HealthCheck::failed(
+                    &name,
+                    &format!("plugin {} is not registered", plugin_name),
+                    &format!(
+                        "install the plugin, or run `project-pilot project disable-plugin {} {}`",
+                        project.name, plugin_name
+                    ),
+                ));
+                continue;
+            }
+            match plugin_manager.dependencies(plugin_name) {
+                Ok(dependencies) => {
+                    for dependency in dependencies.iter().filter(|d| !project.plugins.contains(d)) {
+                        checks.push(HealthCheck::failed(
+                            &name,
+                            &format!("needs plugin {}, which is not enabled", dependency),
+                            &format!(
+                                "run `project-pilot project enable-plugin {} {}`",
+                                project.name, dependency
+                            ),
+                        ));
+                    }
+                }
+                Err(e) => checks.push(HealthCheck::failed(&name, &e, "install the missing plugin")),
+            }
+        }
+    }
+    if let Some(current) = &config.data.current_project {
+        match config.data.projects.iter().find(|p| &p.name == current) {
+            None => checks.push(HealthCheck::failed(
+                "current project",
+                &format!("project {} does not exist anymore", current),
+                "run `project-pilot project switch <project-name>`",
+            )),
+            Some(project) => {
+                if let Some(context) = config
+                    .data
+                    .current_context
+                    .as_ref()
+                    .filter(|c| project.context(c).is_none())
+                {
+                    checks.push(HealthCheck::failed(
+                        "current context",
+                        &format!("project {} has no context {} anymore", current, context),
+                        &format!("run `project-pilot project switch {}`", current),
+                    ));
+                }
+            }
+        }
+    }
+    let mut configured: Vec<&String> = config.data.plugins.keys().collect();
+    configured.sort();
+    for plugin_name in configured {
+        let name = format!("[plugins.{}]", plugin_name);
+        let settings = match plugin_manager.plugin_info(plugin_name) {
+            Some(info) => &info.config,
+            None => {
+                checks.push(HealthCheck::failed(
+                    &name,
+                    &format!("plugin {} is not registered", plugin_name),
+                    &format!("remove the table from {}", config.path.display()),
+                ));
+                continue;
+            }
+        };
+        if let Err(e) = validate_params(settings, &config.data.plugins[plugin_name]) {
+            checks.push(HealthCheck::failed(
+                &name,
+                &e,
+                &format!(
+                    "see `project-pilot plugin info {}` and fix it with `plugin set-config`",
+                    plugin_name
+                ),
+            ));
+        }
+    }
+    if checks.is_empty() {
+        checks.push(HealthCheck::ok(
+            "configuration",
+            &format!(
+                "{} projects, their plugins are registered",
+                config.data.projects.len()
+            ),
+        ));
+    }
+    checks
 }
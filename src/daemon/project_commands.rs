@@ -1,5 +1,7 @@
-use crate::config::{Project, SavedConfig};
+use crate::config::{context_name, split_context, Context, Project, SavedConfig};
 use crate::event::Event;
+use crate::history;
+use crate::plugin::git_client;
 use crate::plugin::{Plugin, PluginFactory, TmuxPlugin};
 use crate::daemon::Daemon;
 use futures::sink::SinkExt;
@@ -7,7 +9,7 @@ use serde_json::{json, Value};
 use std::borrow::BorrowMut;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::str::FromStr;
 use strum::IntoEnumIterator;
@@ -34,10 +36,12 @@ impl Daemon {
                     if config.data.projects.iter().any(|p| p.name == *project_name) {
                         return format!("Project {} already exists", project_name);
                     }
+                    if project_name.contains('/') {
+                        return "Project names cannot contain /, it separates the contexts".to_string();
+                    }
                     config.data.projects.push(Project {
                         name: project_name.clone(),
-                        plugins: Vec::new(),
-                        properties: HashMap::new(),
+                        ..Project::default()
                     });
                     config.save();
                     format!("Project {} added", project_name)
@@ -49,13 +53,26 @@ impl Daemon {
                 if let Some(serde_json::value::Value::String(project_name)) =
                     arguments.get("project-name")
                 {
-                    if let Some(index) = config
+                    if let Some(plugins) = config
                         .data
                         .projects
                         .iter()
-                        .position(|p| p.name == *project_name)
+                        .find(|p| p.name == *project_name)
+                        .map(|p| p.plugins.clone())
                     {
-                        config.data.projects.remove(index);
+                        // its plugins clean up, as when they are disabled
+                        let plugin_manager = plugin_manager.lock().await;
+                        Daemon::dispatch_event(
+                            &mut config,
+                            &plugin_manager,
+                            Event::PluginDisable,
+                            project_name,
+                            &plugins,
+                            arguments,
+                        )
+                        .await;
+                        drop(plugin_manager);
+                        config.data.projects.retain(|p| p.name != *project_name);
                         config.save();
                         format!("Project {} removed", project_name)
                     } else {
@@ -83,10 +100,27 @@ impl Daemon {
                             .map(|(k, v)| format!("{}: {}", k, v))
                             .collect();
                         let properties = properties.join(", ");
-                        format!(
+                        let mut info = format!(
                             "Project: {}\nPlugins: {}\nProperties: {}",
                             project_name, plugins, properties
-                        )
+                        );
+                        if !project.contexts.is_empty() {
+                            let contexts: Vec<String> = project
+                                .contexts
+                                .iter()
+                                .map(|c| format!("{} ({})", c.name, c.path))
+                                .collect();
+                            info = format!("{}\nContexts: {}", info, contexts.join(", "));
+                        }
+                        let project = project.clone();
+                        let global_properties = config.data.properties.clone();
+                        drop(config);
+                        let statuses =
+                            plugin_statuses(plugin_manager, &project, &global_properties).await;
+                        for (plugin_name, status) in statuses {
+                            info.push_str(&format!("\n{}: {}", plugin_name, status));
+                        }
+                        info
                     } else {
                         format!("Project {} not found", project_name)
                     }
@@ -108,7 +142,19 @@ impl Daemon {
                     project
                         .properties
                         .insert(property.to_string(), value.to_string());
+                    let plugins = project.plugins.clone();
                     config.save();
+                    // services restart the ones whose declaration changed
+                    let plugin_manager = plugin_manager.lock().await;
+                    Daemon::dispatch_event(
+                        &mut config,
+                        &plugin_manager,
+                        Event::ProjectUpdate,
+                        project_name,
+                        &plugins,
+                        arguments,
+                    )
+                    .await;
                     format!(
                         "Property {} set to {} for project {}",
                         property, value, project_name
@@ -145,8 +191,19 @@ impl Daemon {
                     .iter_mut()
                     .find(|p| p.name == project_name)
                 {
-                    if let Some(_) = project.properties.remove(property) {
+                    if project.properties.remove(property).is_some() {
+                        let plugins = project.plugins.clone();
                         config.save();
+                        let plugin_manager = plugin_manager.lock().await;
+                        Daemon::dispatch_event(
+                            &mut config,
+                            &plugin_manager,
+                            Event::ProjectUpdate,
+                            project_name,
+                            &plugins,
+                            arguments,
+                        )
+                        .await;
                         format!("Property {} removed for project {}", property, project_name)
                     } else {
                         format!(
@@ -161,6 +218,15 @@ impl Daemon {
             "enable-plugin" => {
                 let project_name = arguments.get("project-name").unwrap().as_str().unwrap();
                 let plugin_name = arguments.get("plugin").unwrap().as_str().unwrap();
+                let plugin_manager = plugin_manager.lock().await;
+                if plugin_manager.get_plugin(plugin_name).is_none() {
+                    return format!("Plugin {} not found", plugin_name);
+                }
+                let mut wanted = match plugin_manager.dependencies(plugin_name) {
+                    Ok(dependencies) => dependencies,
+                    Err(e) => return format!("Error: {}", e),
+                };
+                wanted.push(plugin_name.to_string());
 
                 if let Some(project) = config
                     .data
@@ -169,12 +235,41 @@ impl Daemon {
                     .find(|p| p.name == project_name)
                 {
                     if !project.plugins.contains(&plugin_name.to_string()) {
-                        project.plugins.push(plugin_name.to_string());
+                        // dependencies that were not enabled yet come along
+                        let enabled: Vec<String> = wanted
+                            .into_iter()
+                            .filter(|p| !project.plugins.contains(p))
+                            .collect();
+                        project.plugins.extend(enabled.iter().cloned());
                         config.save();
-                        format!(
-                            "Plugin {} enabled for project {}",
-                            plugin_name, project_name
+                        Daemon::dispatch_event(
+                            &mut config,
+                            &plugin_manager,
+                            Event::PluginEnable,
+                            project_name,
+                            &enabled,
+                            arguments,
                         )
+                        .await;
+                        let dependencies: Vec<&String> =
+                            enabled.iter().filter(|p| *p != plugin_name).collect();
+                        if dependencies.is_empty() {
+                            format!(
+                                "Plugin {} enabled for project {}",
+                                plugin_name, project_name
+                            )
+                        } else {
+                            format!(
+                                "Plugin {} enabled for project {}, with {}",
+                                plugin_name,
+                                project_name,
+                                dependencies
+                                    .iter()
+                                    .map(|d| d.as_str())
+                                    .collect::<Vec<&str>>()
+                                    .join(", ")
+                            )
+                        }
                     } else {
                         format!(
                             "Plugin {} already enabled for project {}",
@@ -196,7 +291,41 @@ impl Daemon {
                     .find(|p| p.name == project_name)
                 {
                     if let Some(index) = project.plugins.iter().position(|p| p == plugin_name) {
-                        project.plugins.remove(index);
+                        let plugin_manager = plugin_manager.lock().await;
+                        let force = arguments.get("force").and_then(|f| f.as_str()) == Some("true");
+                        let dependents: Vec<String> = project
+                            .plugins
+                            .iter()
+                            .filter(|p| {
+                                plugin_manager
+                                    .dependencies(p)
+                                    .map(|d| d.iter().any(|d| d == plugin_name))
+                                    .unwrap_or(false)
+                            })
+                            .cloned()
+                            .collect();
+                        if !dependents.is_empty() && !force {
+                            return format!(
+                                "Plugin {} is needed by {}, use --force to disable it anyway",
+                                plugin_name,
+                                dependents.join(", ")
+                            );
+                        }
+                        // the plugin is still enabled while it cleans up
+                        Daemon::dispatch_event(
+                            &mut config,
+                            &plugin_manager,
+                            Event::PluginDisable,
+                            project_name,
+                            &[plugin_name.to_string()],
+                            arguments,
+                        )
+                        .await;
+                        if let Some(project) =
+                            config.data.projects.iter_mut().find(|p| p.name == project_name)
+                        {
+                            project.plugins.retain(|p| p != plugin_name);
+                        }
                         config.save();
                         format!(
                             "Plugin {} disabled for project {}",
@@ -212,6 +341,82 @@ impl Daemon {
                     format!("Project {} not found", project_name)
                 }
             }
+            "switch" => {
+                // `project` or `project/context`
+                let target = arguments.get("project-name").unwrap().as_str().unwrap();
+                Daemon::switch_project(&mut config, plugin_manager, target, arguments).await
+            }
+            "resolve" => {
+                // the project, or context, whose directory is the longest prefix of the path
+                let path = arguments.get("path").unwrap().as_str().unwrap();
+                let target = match project_at(&config, Path::new(path)) {
+                    Some(target) => target,
+                    None => return format!("No project at {}", path),
+                };
+                let current = context_name(
+                    config.data.current_project.as_deref().unwrap_or_default(),
+                    config.data.current_context.as_deref(),
+                );
+                let switch = arguments.get("switch").and_then(|s| s.as_str()) == Some("true");
+                if switch && target != current {
+                    let mut switch_arguments = serde_json::Map::new();
+                    switch_arguments.insert("project-name".to_string(), json!(target));
+                    Daemon::switch_project(&mut config, plugin_manager, &target, &switch_arguments)
+                        .await;
+                }
+                target
+            }
+            "current" => match &config.data.current_project {
+                Some(project_name) => {
+                    // the context may have been removed since, its worktree with it
+                    let context = config.data.current_context.as_deref().filter(|context| {
+                        config
+                            .data
+                            .projects
+                            .iter()
+                            .any(|p| &p.name == project_name && p.context(context).is_some())
+                    });
+                    context_name(project_name, context)
+                }
+                None => "No current project".to_string(),
+            },
+            "add-context" => {
+                let project_name = arguments.get("project-name").unwrap().as_str().unwrap();
+                let context = arguments.get("context").unwrap().as_str().unwrap();
+                let path = arguments.get("path").unwrap().as_str().unwrap();
+                let project = match config.data.projects.iter_mut().find(|p| p.name == project_name) {
+                    Some(project) => project,
+                    None => return format!("Project {} not found", project_name),
+                };
+                if project.context(context).is_some() {
+                    return format!("Context {} already exists for project {}", context, project_name);
+                }
+                project.contexts.push(Context {
+                    name: context.to_string(),
+                    path: path.to_string(),
+                });
+                config.save();
+                format!("Context {} added to project {}", context, project_name)
+            }
+            "remove-context" => {
+                let project_name = arguments.get("project-name").unwrap().as_str().unwrap();
+                let context = arguments.get("context").unwrap().as_str().unwrap();
+                let project = match config.data.projects.iter_mut().find(|p| p.name == project_name) {
+                    Some(project) => project,
+                    None => return format!("Project {} not found", project_name),
+                };
+                if project.context(context).is_none() {
+                    return format!("Context {} not found for project {}", context, project_name);
+                }
+                project.contexts.retain(|c| c.name != context);
+                if config.data.current_project.as_deref() == Some(project_name)
+                    && config.data.current_context.as_deref() == Some(context)
+                {
+                    config.data.current_context = None;
+                }
+                config.save();
+                format!("Context {} removed from project {}", context, project_name)
+            }
             "list" => {
                 let project_names: Vec<String> = config
                     .data
@@ -221,7 +426,190 @@ impl Daemon {
                     .collect();
                 project_names.join(", ")
             }
+            "recent" => {
+                // the projects by frecency, for the pickers, as json
+                let switches = config.history().load();
+                let now = history::now();
+                let mut projects: Vec<(i64, Value)> = config
+                    .data
+                    .projects
+                    .iter()
+                    .map(|project| {
+                        let path = project.properties.get("path");
+                        let branch = path
+                            .and_then(|path| git_client::head_path(Path::new(path)))
+                            .and_then(|head| git_client::read_head(&head));
+                        let frecency = history::frecency(&switches, &project.name, now);
+                        let entry = json!({
+                            "name": project.name,
+                            "current": config.data.current_project.as_ref() == Some(&project.name),
+                            "path": path,
+                            "branch": branch,
+                            "last-used": history::last_used(&switches, &project.name),
+                            "frecency": frecency,
+                        });
+                        (frecency, entry)
+                    })
+                    .collect();
+                projects.sort_by(|(a, _), (b, _)| b.cmp(a));
+                json!(projects.into_iter().map(|(_, p)| p).collect::<Vec<Value>>()).to_string()
+            }
+            "overview" => {
+                // everything the dashboard shows, as json. the status of the plugins is only
+                // asked for the project in status-of when given, after releasing the config
+                let status_of = arguments.get("status-of").and_then(|p| p.as_str());
+                let switches = config.history().load();
+                let (today, now) = (history::today(), history::now());
+                let mut overview = vec![];
+                let mut wanted = vec![];
+                for project in &config.data.projects {
+                    if status_of.is_none_or(|name| name == project.name) {
+                        wanted.push((overview.len(), project.clone()));
+                    }
+                    overview.push(json!({
+                        "name": project.name,
+                        "current": config.data.current_project.as_ref() == Some(&project.name),
+                        "plugins": project.plugins,
+                        "properties": project.properties,
+                        "contexts": project.contexts,
+                        "status": {},
+                        "time-today": history::time_spent(&switches, &project.name, today, now),
+                    }));
+                }
+                let global_properties = config.data.properties.clone();
+                drop(config);
+                for (index, project) in wanted {
+                    let status: serde_json::Map<String, Value> =
+                        plugin_statuses(plugin_manager, &project, &global_properties)
+                            .await
+                            .into_iter()
+                            .map(|(plugin_name, line)| (plugin_name, json!(line)))
+                            .collect();
+                    overview[index]["status"] = json!(status);
+                }
+                json!(overview).to_string()
+            }
             _ => "Unknown command".to_string(),
         }
     }
+
+    // makes `project` or `project/context` the current one, telling the plugins of both projects
+    async fn switch_project(
+        config: &mut SavedConfig,
+        plugin_manager: &Arc<Mutex<PluginFactory>>,
+        target: &str,
+        arguments: &serde_json::Map<String, Value>,
+    ) -> String {
+        let (project_name, context) = split_context(target);
+        let project = match config.data.projects.iter().find(|p| p.name == project_name) {
+            Some(project) => project,
+            None => return format!("Project {} not found", project_name),
+        };
+        let plugins = project.plugins.clone();
+        let context_path = match context {
+            Some(context) => match project.context(context) {
+                Some(context) => Some(context.path.clone()),
+                None => {
+                    return format!(
+                        "Context {} not found for project {}",
+                        context, project_name
+                    )
+                }
+            },
+            None => None,
+        };
+        let previous = config.data.current_project.replace(project_name.to_string());
+        let previous_context = std::mem::replace(
+            &mut config.data.current_context,
+            context.map(|c| c.to_string()),
+        );
+        config.save();
+        if let Err(e) = config.history().record(project_name) {
+            eprintln!("switch to {} not recorded: {}", project_name, e);
+        }
+        let mut arguments = arguments.clone();
+        arguments.insert("project-name".to_string(), json!(project_name));
+        if let Some(previous) = &previous {
+            arguments.insert("previous-project".to_string(), json!(previous));
+        }
+        if let Some(previous_context) = &previous_context {
+            arguments.insert("previous-context".to_string(), json!(previous_context));
+        }
+        if let (Some(context), Some(context_path)) = (context, &context_path) {
+            arguments.insert("context".to_string(), json!(context));
+            arguments.insert("context-path".to_string(), json!(context_path));
+        }
+        let plugin_manager = plugin_manager.lock().await;
+        let left = previous.as_ref().filter(|previous| *previous != project_name);
+        if let Some(left) = left {
+            let left_plugins = config
+                .data
+                .projects
+                .iter()
+                .find(|p| &p.name == left)
+                .map(|p| p.plugins.clone());
+            if let Some(left_plugins) = left_plugins {
+                let mut leave_arguments = serde_json::Map::new();
+                leave_arguments.insert("project-name".to_string(), json!(left));
+                leave_arguments.insert("next-project".to_string(), json!(project_name));
+                Daemon::dispatch_event(
+                    config,
+                    &plugin_manager,
+                    Event::ProjectLeave,
+                    left,
+                    &left_plugins,
+                    &leave_arguments,
+                )
+                .await;
+            }
+        }
+        Daemon::dispatch_event(
+            config,
+            &plugin_manager,
+            Event::ProjectSwitch,
+            project_name,
+            &plugins,
+            &arguments,
+        )
+        .await;
+        format!("Switched to project {}", target)
+    }
+}
+
+// the status lines of the plugins of a project, to be called without holding the config: the
+// plugins run commands to find them, the plugin manager is only held to look the plugins up
+async fn plugin_statuses(
+    plugin_manager: &Arc<Mutex<PluginFactory>>,
+    project: &Project,
+    global_properties: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let arguments = json!({ "global-properties": global_properties });
+    let arguments = arguments.as_object().unwrap();
+    let plugins: Vec<_> = {
+        let plugin_manager = plugin_manager.lock().await;
+        project
+            .plugins
+            .iter()
+            .filter_map(|name| Some((name.clone(), Arc::clone(plugin_manager.get_plugin(name)?))))
+            .collect()
+    };
+    let mut statuses = Vec::new();
+    for (plugin_name, plugin) in plugins {
+        let plugin = plugin.lock().await;
+        if let Some(status) = tokio::task::block_in_place(|| plugin.status(project, arguments)) {
+            statuses.push((plugin_name, status));
+        }
+    }
+    statuses
+}
+
+// `project` or `project/context` for a directory, matching whole path components so that
+// /src/app2 is not in /src/app
+fn project_at(config: &SavedConfig, path: &Path) -> Option<String> {
+    config
+        .directories()
+        .into_iter()
+        .filter(|(directory, _)| path.starts_with(directory))
+        .max_by_key(|(directory, _)| directory.components().count())
+        .map(|(_, target)| target)
 }
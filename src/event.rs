@@ -1,11 +1,21 @@
 use strum::EnumString;
 
-#[derive(strum::Display, strum::EnumIter, Debug, Eq, PartialEq, EnumString, Clone, Copy)]
+#[derive(strum::Display, strum::EnumIter, Debug, Eq, PartialEq, Hash, EnumString, Clone, Copy)]
 #[strum(serialize_all = "kebab-case")]
 pub enum Event {
     ProjectPeek,
     ProjectSwitch,
+    // sent to the plugins of the previous project on a switch, with "next-project"
+    ProjectLeave,
     ProjectUpdate,
     PluginEnable,
     PluginDisable,
+    // sent by the git plugin, with the "branch" and "previous-branch" arguments
+    ProjectBranchChange,
+    // sent by the daemon when a plugin action fails, with "plugin", "action" and "message".
+    // plugins can send it for their own failures
+    PluginError,
+    // sent by the daemon after each plugin action, with "plugin", "action", "success" and
+    // "duration-ms"
+    ActionComplete,
 }
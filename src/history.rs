@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the switches between projects, a json line each, appended by the daemon.
+// The time spent on a project runs from a switch to it until the next switch.
+// Only the last switches are kept, enough for the 90 days frecency looks at when switching
+// 50 times a day, the file is trimmed once it holds about twice as many
+const MAX_SWITCHES: usize = 5000;
+// a generous size for a line, to tell the number of switches from the file size
+const LINE_SIZE: u64 = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Switch {
+    pub project: String,
+    // seconds since the epoch
+    pub time: i64,
+}
+
+pub struct History {
+    path: PathBuf,
+    max_switches: usize,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// the local midnight, in seconds since the epoch
+pub fn today() -> i64 {
+    let midnight = chrono::Local::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+    midnight
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|t| t.timestamp())
+        .unwrap_or_else(now)
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        History {
+            path,
+            max_switches: MAX_SWITCHES,
+        }
+    }
+
+    pub fn record(&self, project: &str) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let switch = Switch {
+            project: project.to_string(),
+            time: now(),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("cannot open {}: {}", self.path.display(), e))?;
+        writeln!(file, "{}", serde_json::to_string(&switch).unwrap())
+            .map_err(|e| format!("cannot write {}: {}", self.path.display(), e))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        if size > 2 * self.max_switches as u64 * LINE_SIZE {
+            self.trim()?;
+        }
+        Ok(())
+    }
+
+    // keeps the last switches, replacing the file at once
+    fn trim(&self) -> Result<(), String> {
+        let lines: Vec<String> = self
+            .load()
+            .iter()
+            .map(|switch| serde_json::to_string(switch).unwrap() + "\n")
+            .collect();
+        let trimmed = self.path.with_extension("jsonl.tmp");
+        fs::write(&trimmed, lines.concat())
+            .and_then(|_| fs::rename(&trimmed, &self.path))
+            .map_err(|e| format!("cannot trim {}: {}", self.path.display(), e))
+    }
+
+    // the last switches, oldest first, damaged lines are skipped
+    pub fn load(&self) -> Vec<Switch> {
+        let mut switches: Vec<Switch> = fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let excess = switches.len().saturating_sub(self.max_switches);
+        switches.drain(..excess);
+        switches
+    }
+}
+
+// seconds spent on a project between since and now, the current project counting until now
+pub fn time_spent(switches: &[Switch], project: &str, since: i64, now: i64) -> i64 {
+    let ends = switches.iter().skip(1).map(|s| s.time).chain(std::iter::once(now));
+    switches
+        .iter()
+        .zip(ends)
+        .filter(|(switch, _)| switch.project == project)
+        .map(|(switch, end)| (end.min(now) - switch.time.max(since)).max(0))
+        .sum()
+}
+
+// how often and how recently a project was switched to, each switch counting less as it ages
+pub fn frecency(switches: &[Switch], project: &str, now: i64) -> i64 {
+    const HOUR: i64 = 3600;
+    const DAY: i64 = 24 * HOUR;
+    switches
+        .iter()
+        .filter(|switch| switch.project == project)
+        .map(|switch| match now - switch.time {
+            age if age < 4 * HOUR => 100,
+            age if age < DAY => 80,
+            age if age < 7 * DAY => 60,
+            age if age < 30 * DAY => 40,
+            age if age < 90 * DAY => 20,
+            _ => 10,
+        })
+        .sum()
+}
+
+pub fn last_used(switches: &[Switch], project: &str) -> Option<i64> {
+    switches
+        .iter()
+        .rev()
+        .find(|switch| switch.project == project)
+        .map(|switch| switch.time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn history(dir: &TempDir, max_switches: usize) -> History {
+        History {
+            path: dir.path().join("history.jsonl"),
+            max_switches,
+        }
+    }
+
+    #[test]
+    fn load_keeps_the_last_switches() {
+        let dir = TempDir::new().unwrap();
+        let lines: Vec<String> = (0..5)
+            .map(|time| format!(r#"{{"project":"p{}","time":{}}}"#, time, time))
+            .collect();
+        let content = lines.join("\n") + "\nnot json\n";
+        fs::write(dir.path().join("history.jsonl"), content).unwrap();
+        let projects: Vec<String> = history(&dir, 3).load().into_iter().map(|s| s.project).collect();
+        assert_eq!(projects, ["p2", "p3", "p4"]);
+    }
+
+    #[test]
+    fn record_trims_the_file() {
+        let dir = TempDir::new().unwrap();
+        let history = history(&dir, 2);
+        for index in 0..50 {
+            history.record(&format!("p{}", index)).unwrap();
+        }
+        let lines = fs::read_to_string(&history.path).unwrap().lines().count();
+        assert!(lines < 10, "{} lines kept", lines);
+        let projects: Vec<String> = history.load().into_iter().map(|s| s.project).collect();
+        assert_eq!(projects, ["p48", "p49"]);
+    }
+
+    #[test]
+    fn time_spent_counts_until_the_next_switch() {
+        let switches = vec![
+            Switch { project: "a".to_string(), time: 100 },
+            Switch { project: "b".to_string(), time: 160 },
+            Switch { project: "a".to_string(), time: 200 },
+        ];
+        assert_eq!(time_spent(&switches, "a", 0, 250), 110);
+        assert_eq!(time_spent(&switches, "a", 130, 250), 80);
+        assert_eq!(time_spent(&switches, "b", 0, 250), 40);
+    }
+}
@@ -3,7 +3,10 @@ mod cli;
 mod config;
 mod daemon;
 mod event;
+mod history;
 mod plugin;
+mod shell;
+mod tui;
 
 use cli::{Cli, run};
 use structopt::StructOpt;
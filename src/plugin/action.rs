@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParamKind {
+    #[default]
+    String,
+    Integer,
+    Bool,
+}
+
+// everything but the name can be omitted by plugins describing their actions
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParamDescriptor {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub kind: ParamKind,
+    // positional parameters are given in order, the others as `--name value`
+    #[serde(default)]
+    pub positional: bool,
+    #[serde(default)]
+    pub required: bool,
+    // accepts many values, received as an array
+    #[serde(default)]
+    pub multiple: bool,
+    // the smallest value accepted by an integer parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<i64>,
+}
+
+impl ParamDescriptor {
+    pub fn positional(name: &str, description: &str) -> Self {
+        ParamDescriptor {
+            name: name.to_string(),
+            description: description.to_string(),
+            kind: ParamKind::String,
+            positional: true,
+            required: false,
+            multiple: false,
+            min: None,
+        }
+    }
+
+    pub fn named(name: &str, description: &str) -> Self {
+        ParamDescriptor {
+            positional: false,
+            ..ParamDescriptor::positional(name, description)
+        }
+    }
+
+    pub fn kind(mut self, kind: ParamKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn multiple(mut self) -> Self {
+        self.multiple = true;
+        self
+    }
+
+    pub fn min(mut self, min: i64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    // turns a value typed on the command line into the json expected by the parameter
+    pub fn parse(&self, value: &str) -> Result<Value, String> {
+        match self.kind {
+            ParamKind::String => Ok(Value::String(value.to_string())),
+            ParamKind::Integer => value
+                .parse::<i64>()
+                .map(Value::from)
+                .map_err(|_| format!("parameter {} expects an integer", self.name)),
+            ParamKind::Bool => value
+                .parse::<bool>()
+                .map(Value::from)
+                .map_err(|_| format!("parameter {} expects true or false", self.name)),
+        }
+    }
+
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let values = match (self.multiple, value) {
+            (true, Value::Array(values)) => values.iter().collect(),
+            (true, _) => return Err(format!("parameter {} expects a list", self.name)),
+            (false, value) => vec![value],
+        };
+        for value in values {
+            let valid = match self.kind {
+                ParamKind::String => value.is_string(),
+                ParamKind::Integer => value.is_i64(),
+                ParamKind::Bool => value.is_boolean(),
+            };
+            if !valid {
+                return Err(format!(
+                    "parameter {} expects a {}",
+                    self.name,
+                    serde_json::to_value(self.kind).unwrap().as_str().unwrap()
+                ));
+            }
+            if let (Some(min), Some(value)) = (self.min, value.as_i64()) {
+                if value < min {
+                    return Err(format!("parameter {} must be at least {}", self.name, min));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActionDescriptor {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub params: Vec<ParamDescriptor>,
+    #[serde(default)]
+    pub needs_project: bool,
+}
+
+impl ActionDescriptor {
+    pub fn new(name: &str, description: &str) -> Self {
+        ActionDescriptor {
+            name: name.to_string(),
+            description: description.to_string(),
+            params: Vec::new(),
+            needs_project: false,
+        }
+    }
+
+    pub fn needs_project(mut self) -> Self {
+        self.needs_project = true;
+        self
+    }
+
+    pub fn param(mut self, param: ParamDescriptor) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    // checks the structured arguments sent by the cli, before they reach the plugin
+    pub fn validate(&self, args: &Map<String, Value>) -> Result<(), String> {
+        validate_params(&self.params, args)
+    }
+
+    // one line summary, like `send-keys <project-name> <target> <keys>... - send keys to a pane`
+    pub fn usage(&self) -> String {
+        let mut words = vec![self.name.clone()];
+        if self.needs_project {
+            words.push("<project-name>".to_string());
+        }
+        for param in &self.params {
+            let mut word = if param.positional {
+                format!("<{}>", param.name)
+            } else if param.kind == ParamKind::Bool {
+                format!("--{}", param.name)
+            } else {
+                format!("--{} <value>", param.name)
+            };
+            if param.multiple {
+                word.push_str("...");
+            }
+            if !param.required {
+                word = format!("[{}]", word);
+            }
+            words.push(word);
+        }
+        format!("{} - {}", words.join(" "), self.description)
+    }
+}
+
+// checks values against their descriptors, for action arguments and plugin configurations
+pub fn validate_params(params: &[ParamDescriptor], values: &Map<String, Value>) -> Result<(), String> {
+    for param in params {
+        match values.get(&param.name) {
+            Some(value) => param.check(value)?,
+            None if param.required => {
+                return Err(format!("missing required parameter {}", param.name))
+            }
+            None => {}
+        }
+    }
+    if let Some(unknown) = values
+        .keys()
+        .find(|key| !params.iter().any(|p| &p.name == *key))
+    {
+        return Err(format!("unknown parameter {}", unknown));
+    }
+    Ok(())
+}
+
+// the value of an action argument, as sent by `plugin run`
+pub fn action_arg<'a>(arguments: &'a Map<String, Value>, name: &str) -> Option<&'a Value> {
+    arguments.get("args").and_then(|args| args.get(name))
+}
+
+pub fn action_arg_list(arguments: &Map<String, Value>, name: &str) -> Vec<String> {
+    match action_arg(arguments, name) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str().map(|s| s.to_string()))
+            .collect(),
+        Some(Value::String(value)) => vec![value.clone()],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn args(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn validate_checks_kinds_and_bounds() {
+        let action = ActionDescriptor::new("log", "").param(
+            ParamDescriptor::named("count", "")
+                .kind(ParamKind::Integer)
+                .min(1),
+        );
+        assert_eq!(action.validate(&args(json!({"count": 5}))), Ok(()));
+        assert_eq!(action.validate(&args(json!({}))), Ok(()));
+        assert_eq!(
+            action.validate(&args(json!({"count": -5}))),
+            Err("parameter count must be at least 1".to_string())
+        );
+        assert_eq!(
+            action.validate(&args(json!({"count": "5"}))),
+            Err("parameter count expects a integer".to_string())
+        );
+        assert_eq!(
+            action.validate(&args(json!({"other": 1}))),
+            Err("unknown parameter other".to_string())
+        );
+    }
+
+    #[test]
+    fn min_is_optional_in_descriptors() {
+        let param: ParamDescriptor = serde_json::from_value(json!({"name": "lines"})).unwrap();
+        assert_eq!(param.min, None);
+        assert!(serde_json::to_value(&param).unwrap().get("min").is_none());
+    }
+}
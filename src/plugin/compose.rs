@@ -0,0 +1,428 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::health::check_command;
+use crate::plugin::{
+    properties, ActionDescriptor, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// the services of a project are started when it is enabled or becomes the current one.
+// properties, global or per project:
+//   compose.file          the compose file, relative to `path`, found by its usual names otherwise
+//   compose.project-name  passed as `-p`
+//   compose.stop-after    seconds after which the services of a project we left are stopped
+const PREFIX: &str = "compose.";
+const DEFAULT_COMMAND: &str = "docker compose";
+const DEFAULT_FILES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+const DEFAULT_LOG_LINES: i64 = 50;
+
+// how to run compose for a project
+#[derive(Debug, Clone)]
+struct ComposeProject {
+    command: Vec<String>,
+    directory: PathBuf,
+    file: PathBuf,
+    project_name: Option<String>,
+}
+
+impl ComposeProject {
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.command[0]);
+        command
+            .args(&self.command[1..])
+            .arg("-f")
+            .arg(&self.file)
+            .arg("--project-directory")
+            .arg(&self.directory)
+            .current_dir(&self.directory);
+        if let Some(project_name) = &self.project_name {
+            command.arg("-p").arg(project_name);
+        }
+        command.args(args);
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, String> {
+        let output = self
+            .command(args)
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", self.command.join(" "), e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!(
+                "compose {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    // long commands, like `up` pulling images, don't block the daemon
+    fn run_in_background(&self, project: &str, args: &[&str]) {
+        let compose = self.clone();
+        let project = project.to_string();
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        std::thread::spawn(move || {
+            let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+            match compose.run(&args) {
+                Ok(_) => println!("compose {} done for project {}", args[0], project),
+                Err(e) => eprintln!("project {}: {}", project, e),
+            }
+        });
+    }
+
+    fn services(&self, args: &[&str]) -> Result<Vec<String>, String> {
+        Ok(self
+            .run(args)?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect())
+    }
+}
+
+pub struct ComposePlugin {
+    command: Vec<String>,
+    // projects we left, waiting for their services to be stopped, with a token to cancel it
+    pending_stops: Arc<Mutex<HashMap<String, u64>>>,
+    next_token: AtomicU64,
+}
+
+impl ComposePlugin {
+    pub fn new() -> Self {
+        ComposePlugin {
+            command: DEFAULT_COMMAND.split_whitespace().map(String::from).collect(),
+            pending_stops: Arc::new(Mutex::new(HashMap::new())),
+            next_token: AtomicU64::new(1),
+        }
+    }
+
+    fn compose_project(
+        &self,
+        project: &Project,
+        arguments: &Map<String, Value>,
+    ) -> Result<ComposeProject, String> {
+        let properties = properties(project, arguments);
+        let directory = PathBuf::from(
+            project
+                .properties
+                .get("path")
+                .ok_or(format!("project {} has no path property", project.name))?,
+        );
+        let file = match properties.get(&format!("{}file", PREFIX)) {
+            Some(file) => directory.join(file),
+            None => DEFAULT_FILES
+                .iter()
+                .map(|name| directory.join(name))
+                .find(|file| file.is_file())
+                .ok_or(format!(
+                    "no compose file in {}, set it with the compose.file property",
+                    directory.display()
+                ))?,
+        };
+        Ok(ComposeProject {
+            command: self.command.clone(),
+            directory,
+            file,
+            project_name: properties.get(&format!("{}project-name", PREFIX)).cloned(),
+        })
+    }
+
+    fn start(&self, project: &Project, arguments: &Map<String, Value>) {
+        // coming back before the grace period ends keeps the services running
+        self.pending_stops.lock().unwrap().remove(&project.name);
+        match self.compose_project(project, arguments) {
+            Ok(compose) => compose.run_in_background(&project.name, &["up", "-d"]),
+            Err(e) => eprintln!("compose plugin: {}", e),
+        }
+    }
+
+    fn schedule_stop(&self, project: &Project, arguments: &Map<String, Value>) {
+        let grace = match properties(project, arguments)
+            .get(&format!("{}stop-after", PREFIX))
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            Some(grace) => Duration::from_secs(grace),
+            None => return,
+        };
+        let compose = match self.compose_project(project, arguments) {
+            Ok(compose) => compose,
+            Err(e) => return eprintln!("compose plugin: {}", e),
+        };
+        let token = self.next_token.fetch_add(1, Ordering::SeqCst);
+        self.pending_stops
+            .lock()
+            .unwrap()
+            .insert(project.name.clone(), token);
+        let pending_stops = Arc::clone(&self.pending_stops);
+        let project_name = project.name.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(grace);
+            let mut pending_stops = pending_stops.lock().unwrap();
+            if pending_stops.get(&project_name) == Some(&token) {
+                pending_stops.remove(&project_name);
+                compose.run_in_background(&project_name, &["stop"]);
+            }
+        });
+    }
+}
+
+impl Plugin for ComposePlugin {
+    fn name(&self) -> String {
+        "compose".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        match event {
+            Event::PluginEnable | Event::ProjectSwitch => self.start(project, arguments),
+            Event::ProjectLeave => self.schedule_stop(project, arguments),
+            Event::PluginDisable => {
+                self.pending_stops.lock().unwrap().remove(&project.name);
+                match self.compose_project(project, arguments) {
+                    Ok(compose) => compose.run_in_background(&project.name, &["stop"]),
+                    Err(e) => eprintln!("compose plugin: {}", e),
+                }
+            }
+            _ => {}
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "start the compose services of the current project".to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectSwitch.to_string(),
+                Event::ProjectLeave.to_string(),
+            ],
+            properties: vec![
+                "path".to_string(),
+                format!("{}file", PREFIX),
+                format!("{}project-name", PREFIX),
+                format!("{}stop-after", PREFIX),
+            ],
+            config: vec![ParamDescriptor::named(
+                "command",
+                "how to run compose, `docker compose` by default, like `podman-compose`",
+            )],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new("up", "start the project services").needs_project(),
+            ActionDescriptor::new("stop", "stop the project services").needs_project(),
+            ActionDescriptor::new("ps", "list the project containers").needs_project(),
+            ActionDescriptor::new("logs", "print the recent logs of the services")
+                .needs_project()
+                .param(ParamDescriptor::positional(
+                    "service",
+                    "only this service, all of them if omitted",
+                ))
+                .param(
+                    ParamDescriptor::named("lines", "how many lines for each service")
+                        .kind(ParamKind::Integer)
+                        .min(0),
+                ),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        let compose = self.compose_project(project, arguments)?;
+        match action {
+            "up" => {
+                self.pending_stops.lock().unwrap().remove(&project.name);
+                compose.run(&["up", "-d"]).map(|_| "services started".to_string())
+            }
+            "stop" => compose
+                .run(&["stop"])
+                .map(|_| "services stopped".to_string()),
+            "ps" => compose.run(&["ps"]),
+            "logs" => {
+                let lines = action_arg(arguments, "lines")
+                    .and_then(|l| l.as_i64())
+                    .unwrap_or(DEFAULT_LOG_LINES)
+                    .to_string();
+                let mut args = vec!["logs", "--no-color", "--tail", &lines];
+                if let Some(service) = action_arg(arguments, "service").and_then(|s| s.as_str()) {
+                    args.push(service);
+                }
+                compose.run(&args)
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let compose = match self.compose_project(project, arguments) {
+            Ok(compose) => compose,
+            Err(e) => return Some(e),
+        };
+        let all = compose.services(&["config", "--services"]);
+        let running = compose.services(&["ps", "--services", "--filter", "status=running"]);
+        let status = match (all, running) {
+            (Ok(all), Ok(running)) => {
+                let stopped: Vec<&String> = all.iter().filter(|s| !running.contains(s)).collect();
+                let mut status = format!("{}/{} services running", running.len(), all.len());
+                if !stopped.is_empty() {
+                    let stopped: Vec<&str> = stopped.iter().map(|s| s.as_str()).collect();
+                    status = format!("{}, stopped: {}", status, stopped.join(", "));
+                }
+                status
+            }
+            (Err(e), _) | (_, Err(e)) => e,
+        };
+        Some(status)
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let mut args: Vec<&str> = self.command[1..].iter().map(|a| a.as_str()).collect();
+        args.push("version");
+        vec![check_command(
+            "command",
+            &self.command[0],
+            &args,
+            "install docker with the compose plugin, or set another command with `plugin set-config compose command <command>`",
+        )]
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        let command = config
+            .get("command")
+            .and_then(|c| c.as_str())
+            .unwrap_or(DEFAULT_COMMAND);
+        let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+        if command.is_empty() {
+            return Err("the compose command cannot be empty".to_string());
+        }
+        self.command = command;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::{fake_command, wait_for_lines};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    // a compose command logging its arguments, with services db and web, only db running
+    fn plugin(dir: &TempDir) -> (ComposePlugin, PathBuf) {
+        let log = dir.path().join("compose.log");
+        let body = format!(
+            r#"echo "$*" >> {}
+case "$*" in
+  *"config --services") printf 'db\nweb\n' ;;
+  *"ps --services"*) echo db ;;
+esac"#,
+            log.display()
+        );
+        let command = fake_command(dir.path(), "compose", &body);
+        let mut plugin = ComposePlugin::new();
+        let config = json!({"command": format!("{} compose", command.display())});
+        plugin.configure(config.as_object().unwrap()).unwrap();
+        (plugin, log)
+    }
+
+    fn project(dir: &TempDir, properties: &[(&str, &str)]) -> Project {
+        let mut project = Project {
+            name: "web".to_string(),
+            ..Project::default()
+        };
+        project.properties.insert("path".to_string(), dir.path().display().to_string());
+        for (key, value) in properties {
+            project.properties.insert(key.to_string(), value.to_string());
+        }
+        project
+    }
+
+    #[test]
+    fn compose_file_is_found_or_set() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, _) = plugin(&dir);
+        let error = plugin.compose_project(&project(&dir, &[]), &Map::new()).unwrap_err();
+        assert!(error.starts_with("no compose file"), "{}", error);
+
+        std::fs::write(dir.path().join("docker-compose.yml"), "").unwrap();
+        let compose = plugin.compose_project(&project(&dir, &[]), &Map::new()).unwrap();
+        assert_eq!(compose.file, dir.path().join("docker-compose.yml"));
+
+        // global properties apply, the project ones win
+        let arguments = json!({"global-properties": {"compose.file": "global.yml"}});
+        let arguments = arguments.as_object().unwrap();
+        let compose = plugin.compose_project(&project(&dir, &[]), arguments).unwrap();
+        assert_eq!(compose.file, dir.path().join("global.yml"));
+        let project = project(&dir, &[("compose.file", "other.yml")]);
+        let compose = plugin.compose_project(&project, arguments).unwrap();
+        assert_eq!(compose.file, dir.path().join("other.yml"));
+    }
+
+    #[test]
+    fn empty_command_is_refused() {
+        let mut plugin = ComposePlugin::new();
+        let config = json!({"command": " "});
+        assert!(plugin.configure(config.as_object().unwrap()).is_err());
+        assert_eq!(plugin.command, ["docker", "compose"]);
+    }
+
+    #[test]
+    fn actions_pass_the_project_arguments() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, log) = plugin(&dir);
+        let properties = [("compose.file", "c.yml"), ("compose.project-name", "p")];
+        let mut project = project(&dir, &properties);
+        let reply = plugin.run_action("up", Some(&mut project), &Map::new());
+        assert_eq!(reply, Ok("services started".to_string()));
+        let reply = plugin.run_action("stop", Some(&mut project), &Map::new());
+        assert_eq!(reply, Ok("services stopped".to_string()));
+        let prefix = format!(
+            "compose -f {} --project-directory {} -p p",
+            dir.path().join("c.yml").display(),
+            dir.path().display()
+        );
+        let lines = wait_for_lines(&log, |lines| lines.len() == 2);
+        assert_eq!(lines, [format!("{} up -d", prefix), format!("{} stop", prefix)]);
+    }
+
+    #[test]
+    fn status_lists_the_stopped_services() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, _) = plugin(&dir);
+        // the file may be a global property
+        let global = json!({"global-properties": {"compose.file": "c.yml"}});
+        let status = plugin.status(&project(&dir, &[]), global.as_object().unwrap());
+        assert_eq!(status.as_deref(), Some("1/2 services running, stopped: web"));
+    }
+
+    #[test]
+    fn coming_back_cancels_the_pending_stop() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, log) = plugin(&dir);
+        let mut project = project(&dir, &[("compose.file", "c.yml"), ("compose.stop-after", "1")]);
+        plugin.on_event(Event::ProjectLeave, &mut project, &Map::new());
+        plugin.on_event(Event::ProjectSwitch, &mut project, &Map::new());
+        std::thread::sleep(Duration::from_millis(1500));
+        let lines = wait_for_lines(&log, |lines| !lines.is_empty());
+        assert!(lines.iter().all(|line| !line.ends_with(" stop")), "{:?}", lines);
+
+        plugin.on_event(Event::ProjectLeave, &mut project, &Map::new());
+        let lines = wait_for_lines(&log, |lines| lines.iter().any(|l| l.ends_with(" stop")));
+        assert!(lines.last().unwrap().ends_with(" stop"), "{:?}", lines);
+    }
+}
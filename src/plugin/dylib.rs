@@ -0,0 +1,242 @@
+// Plugins loaded in process from shared libraries. The interface is plain C, so plugins can be
+// built with any compiler version: the library exports
+//
+//   const PluginVTable *project_pilot_plugin_vtable(void);
+//
+// and the vtable starts with the ABI version, checked before anything else is touched. The
+// functions may be null, a plugin missing one of them is refused.
+// Calls carry the json methods described in rpc.rs: `call` receives the method name and its
+// params as nul terminated utf-8 and returns {"result": ...} or {"error": {"message": ...}},
+// allocated by the plugin and given back to it with `free_string`.
+use crate::plugin::rpc::{RpcChannel, RpcPlugin};
+use libloading::Library;
+use serde_json::Value;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const ABI_VERSION: u32 = 1;
+pub const VTABLE_SYMBOL: &[u8] = b"project_pilot_plugin_vtable";
+
+pub type CreateFn = extern "C" fn() -> *mut c_void;
+pub type DestroyFn = extern "C" fn(instance: *mut c_void);
+pub type CallFn = extern "C" fn(
+    instance: *mut c_void,
+    method: *const c_char,
+    params: *const c_char,
+) -> *mut c_char;
+pub type FreeStringFn = extern "C" fn(string: *mut c_char);
+
+#[repr(C)]
+pub struct PluginVTable {
+    pub abi_version: u32,
+    pub create: Option<CreateFn>,
+    pub destroy: Option<DestroyFn>,
+    pub call: Option<CallFn>,
+    pub free_string: Option<FreeStringFn>,
+}
+
+pub type LibraryPlugin = RpcPlugin<LibraryChannel>;
+
+pub struct LibraryChannel {
+    instance: *mut c_void,
+    destroy: DestroyFn,
+    call: CallFn,
+    free_string: FreeStringFn,
+    // plugins are not required to be thread safe
+    lock: Mutex<()>,
+    // dropped last, unloading the code the pointers above refer to
+    _library: Library,
+}
+
+// the instance is only touched by one thread at a time, through the lock
+unsafe impl Send for LibraryChannel {}
+unsafe impl Sync for LibraryChannel {}
+
+impl RpcChannel for LibraryChannel {
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let _guard = self.lock.lock().unwrap();
+        let method = CString::new(method).map_err(|e| e.to_string())?;
+        let params = CString::new(params.to_string()).map_err(|e| e.to_string())?;
+        let answer = (self.call)(self.instance, method.as_ptr(), params.as_ptr());
+        if answer.is_null() {
+            return Err("plugin returned no answer".to_string());
+        }
+        let text = unsafe { CStr::from_ptr(answer) }
+            .to_string_lossy()
+            .to_string();
+        (self.free_string)(answer);
+        let answer: Value =
+            serde_json::from_str(&text).map_err(|e| format!("invalid answer: {}", e))?;
+        if let Some(error) = answer.get("error") {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            return Err(message.to_string());
+        }
+        Ok(answer.get("result").cloned().unwrap_or(Value::Null))
+    }
+}
+
+impl Drop for LibraryChannel {
+    fn drop(&mut self) {
+        (self.destroy)(self.instance);
+    }
+}
+
+pub fn load(path: &Path) -> Result<LibraryPlugin, String> {
+    let library = unsafe { Library::new(path) }
+        .map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+    let vtable = unsafe {
+        let get_vtable = library
+            .get::<extern "C" fn() -> *const PluginVTable>(VTABLE_SYMBOL)
+            .map_err(|e| format!("{} is not a project-pilot plugin: {}", path.display(), e))?;
+        get_vtable()
+    };
+    if vtable.is_null() {
+        return Err(format!("{} returned no plugin vtable", path.display()));
+    }
+    let abi_version = unsafe { (*vtable).abi_version };
+    if abi_version != ABI_VERSION {
+        return Err(format!(
+            "{} was built for plugin ABI version {}, this daemon supports version {}",
+            path.display(),
+            abi_version,
+            ABI_VERSION
+        ));
+    }
+    let vtable = unsafe { &*vtable };
+    let missing = |entry: &str| format!("{} has no {} function", path.display(), entry);
+    let create = vtable.create.ok_or_else(|| missing("create"))?;
+    let destroy = vtable.destroy.ok_or_else(|| missing("destroy"))?;
+    let call = vtable.call.ok_or_else(|| missing("call"))?;
+    let free_string = vtable.free_string.ok_or_else(|| missing("free_string"))?;
+    let instance = create();
+    if instance.is_null() {
+        return Err(format!("{} failed to create the plugin", path.display()));
+    }
+    RpcPlugin::new(LibraryChannel {
+        instance,
+        destroy,
+        call,
+        free_string,
+        lock: Mutex::new(()),
+        _library: library,
+    })
+}
+
+// shared libraries in the plugin directory
+pub fn find_libraries(dir: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map(|e| e == "so").unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{Plugin, PluginFactory};
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    // a plugin named mock, answering only name and list_actions
+    const MOCK: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct {
+    unsigned int abi_version;
+    void *(*create)(void);
+    void (*destroy)(void *);
+    char *(*call)(void *, const char *, const char *);
+    void (*free_string)(char *);
+} PluginVTable;
+
+static int instance;
+static void *create(void) { return &instance; }
+static void destroy(void *plugin) {}
+static char *call(void *plugin, const char *method, const char *params) {
+    if (strcmp(method, "name") == 0) return strdup("{\"result\": \"mock\"}");
+    if (strcmp(method, "list_actions") == 0) return strdup("{\"result\": []}");
+    return strdup("{\"error\": {\"message\": \"unknown method\"}}");
+}
+static void free_string(char *string) { free(string); }
+
+static const PluginVTable vtable = { ABI, create, destroy, CALL, free_string };
+const PluginVTable *project_pilot_plugin_vtable(void) { return &vtable; }
+"#;
+
+    // builds the mock with the given ABI version and call function
+    fn build(dir: &TempDir, name: &str, abi: u32, call: &str) -> PathBuf {
+        let source = dir.path().join("mock.c");
+        std::fs::write(&source, MOCK).unwrap();
+        let library = dir.path().join(format!("{}.so", name));
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC"])
+            .arg(format!("-DABI={}", abi))
+            .arg(format!("-DCALL={}", call))
+            .arg("-o")
+            .arg(&library)
+            .arg(&source)
+            .status()
+            .expect("a C compiler is needed to build the mock plugin");
+        assert!(status.success());
+        library
+    }
+
+    #[test]
+    fn other_abi_versions_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let library = build(&dir, "old", ABI_VERSION + 1, "call");
+        let error = load(&library).err().unwrap();
+        assert_eq!(
+            error,
+            format!(
+                "{} was built for plugin ABI version {}, this daemon supports version {}",
+                library.display(),
+                ABI_VERSION + 1,
+                ABI_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn missing_functions_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let library = build(&dir, "partial", ABI_VERSION, "0");
+        let error = load(&library).err().unwrap();
+        assert_eq!(error, format!("{} has no call function", library.display()));
+    }
+
+    #[tokio::test]
+    async fn libraries_are_loaded_and_unloaded() {
+        let dir = TempDir::new().unwrap();
+        let library = build(&dir, "mock", ABI_VERSION, "call");
+        assert_eq!(find_libraries(dir.path()), vec![library.clone()]);
+
+        let (requests, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut plugins = PluginFactory::new(requests);
+        assert_eq!(plugins.load_library_plugin(&library).await, Ok("mock".to_string()));
+        let plugin = plugins.get_plugin("mock").unwrap();
+        assert!(plugin.lock().await.list_actions().is_empty());
+        assert_eq!(
+            plugins.load_library_plugin(&library).await,
+            Err("plugin mock is already registered".to_string())
+        );
+
+        assert_eq!(plugins.unregister_plugin("mock"), Ok(()));
+        assert!(plugins.get_plugin("mock").is_none());
+        assert_eq!(
+            plugins.unregister_plugin("mock"),
+            Err("plugin mock not found".to_string())
+        );
+    }
+}
@@ -0,0 +1,643 @@
+use crate::config::{context_name, Project};
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::env::project_environment;
+use crate::plugin::health::check_command;
+use crate::plugin::services::signal_group;
+use crate::plugin::{
+    properties, ActionDescriptor, DaemonRequest, HealthCheck, ParamDescriptor, ParamKind, Plugin,
+    PluginInfo, RequestSender,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// opens the project in an editor, properties (global or per project):
+//   editor.kind       nvim or code, the `kind` of the plugin config otherwise
+//   editor.workspace  the .code-workspace file to open, relative to `path`
+// neovim runs a server per project, listening on <socket-dir>/<project>.socket: in an `editor`
+// window of the tmux session when the project uses tmux, headless otherwise
+const PREFIX: &str = "editor.";
+const WINDOW_NAME: &str = "editor";
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+enum EditorKind {
+    Nvim,
+    Code,
+}
+
+// a file to open, with its position
+struct FileTarget {
+    path: PathBuf,
+    line: Option<i64>,
+    column: Option<i64>,
+}
+
+pub struct EditorPlugin {
+    kind: EditorKind,
+    nvim: String,
+    code: String,
+    socket_dir: PathBuf,
+    requests: Option<RequestSender>,
+    // the process groups of the headless servers, by owner
+    headless: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl EditorPlugin {
+    pub fn new() -> Self {
+        EditorPlugin {
+            kind: EditorKind::Nvim,
+            nvim: "nvim".to_string(),
+            code: "code".to_string(),
+            socket_dir: default_socket_dir(),
+            requests: None,
+            headless: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn kind(&self, project: &Project, arguments: &Map<String, Value>) -> Result<EditorKind, String> {
+        match properties(project, arguments).get(&format!("{}kind", PREFIX)) {
+            Some(kind) => EditorKind::from_str(kind)
+                .map_err(|_| format!("unknown editor {}, use nvim or code", kind)),
+            None => Ok(self.kind),
+        }
+    }
+
+    // the server socket of a project or of one of its contexts
+    fn socket(&self, owner: &str) -> PathBuf {
+        self.socket_dir
+            .join(format!("{}.socket", owner.replace('/', "@")))
+    }
+
+    fn nvim_running(&self, socket: &Path) -> bool {
+        socket.exists()
+            && Command::new(&self.nvim)
+                .arg("--server")
+                .arg(socket)
+                .args(["--remote-expr", "1"])
+                .stdin(Stdio::null())
+                .output()
+                .is_ok_and(|output| output.status.success())
+    }
+
+    fn nvim_open_file(&self, socket: &Path, file: &FileTarget) -> Result<(), String> {
+        let path = file.path.to_string_lossy().replace('\'', "''");
+        let mut expression = format!("execute('edit ' . fnameescape('{}'))", path);
+        if let Some(line) = file.line {
+            expression = format!(
+                "{} . execute('call cursor({}, {})')",
+                expression,
+                line,
+                file.column.unwrap_or(1)
+            );
+        }
+        let output = Command::new(&self.nvim)
+            .arg("--server")
+            .arg(socket)
+            .arg("--remote-expr")
+            .arg(expression)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", self.nvim, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "nvim could not open {}: {}",
+                file.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    // `nvim --listen <socket> [+line file]`
+    fn nvim_command(&self, socket: &Path, file: Option<&FileTarget>) -> Vec<String> {
+        let mut command = vec![
+            self.nvim.clone(),
+            "--listen".to_string(),
+            socket.to_string_lossy().to_string(),
+        ];
+        if let Some(file) = file {
+            if let Some(line) = file.line {
+                command.push(format!("+call cursor({}, {})", line, file.column.unwrap_or(1)));
+            }
+            command.push(file.path.to_string_lossy().to_string());
+        }
+        command
+    }
+
+    fn open_nvim(
+        &self,
+        project: &Project,
+        context: Option<&str>,
+        directory: Option<&str>,
+        file: Option<&FileTarget>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let owner = context_name(&project.name, context);
+        let socket = self.socket(&owner);
+        if self.nvim_running(&socket) {
+            return match file {
+                Some(file) => self
+                    .nvim_open_file(&socket, file)
+                    .map(|_| format!("{} opened in {}", file.path.display(), socket.display())),
+                None => Ok(format!("nvim server of {} running at {}", owner, socket.display())),
+            };
+        }
+        std::fs::create_dir_all(&self.socket_dir)
+            .map_err(|e| format!("cannot create {}: {}", self.socket_dir.display(), e))?;
+        // a server that did not exit cleanly leaves its socket behind
+        let _ = std::fs::remove_file(&socket);
+        let command = self.nvim_command(&socket, file);
+        let socket_path = socket.clone();
+
+        if project.plugins.iter().any(|p| p == "tmux") {
+            let requests = self
+                .requests
+                .as_ref()
+                .ok_or("the editor plugin is not connected to the daemon".to_string())?;
+            let mut args = json!({ "command": command, "name": WINDOW_NAME });
+            if let Some(context) = context {
+                args["context"] = json!(context);
+            }
+            let params = json!({
+                "plugin": "tmux",
+                "action": "run",
+                "project-name": project.name,
+                "args": args,
+            });
+            requests
+                .send(DaemonRequest::new(
+                    "plugin",
+                    "run",
+                    params.as_object().unwrap().clone(),
+                ))
+                .map_err(|_| "the daemon is not listening".to_string())?;
+            return Ok(format!(
+                "nvim server of {} starting in the tmux window {}, at {}",
+                owner,
+                WINDOW_NAME,
+                socket.display()
+            ));
+        }
+
+        let environment: Vec<(String, String)> = if project.plugins.iter().any(|p| p == "env") {
+            project_environment(project, directory, arguments)
+                .map(|environment| environment.into_iter().collect())
+                .unwrap_or_else(|e| {
+                    eprintln!("environment of project {} not loaded: {}", project.name, e);
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
+        let mut headless = Command::new(&command[0]);
+        headless
+            .arg("--headless")
+            .args(&command[1..])
+            .envs(environment)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0);
+        if let Some(directory) = directory {
+            headless.current_dir(directory);
+        }
+        let mut child = headless
+            .spawn()
+            .map_err(|e| format!("failed to execute {}: {}", self.nvim, e))?;
+        let group = child.id();
+        self.headless.lock().unwrap().insert(owner.clone(), group);
+        let servers = self.headless.clone();
+        let server = owner.clone();
+        std::thread::spawn(move || {
+            let _ = child.wait();
+            let mut servers = servers.lock().unwrap();
+            // a newer server of the owner may have taken the socket over
+            if servers.get(&server) == Some(&group) {
+                servers.remove(&server);
+                let _ = std::fs::remove_file(&socket_path);
+            }
+        });
+        Ok(format!(
+            "headless nvim server of {} started at {}, attach with `{} --remote-ui --server {}`",
+            owner,
+            socket.display(),
+            self.nvim,
+            socket.display()
+        ))
+    }
+
+    fn open_code(
+        &self,
+        project: &Project,
+        directory: Option<&str>,
+        file: Option<&FileTarget>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let directory = PathBuf::from(
+            directory.ok_or(format!("project {} has no path property", project.name))?,
+        );
+        let target = match properties(project, arguments).get(&format!("{}workspace", PREFIX)) {
+            Some(workspace) => directory.join(workspace),
+            None => find_workspace(&directory).unwrap_or(directory),
+        };
+        let mut command = Command::new(&self.code);
+        command.arg(&target);
+        if let Some(file) = file {
+            let mut goto = file.path.to_string_lossy().to_string();
+            if let Some(line) = file.line {
+                goto = format!("{}:{}", goto, line);
+                if let Some(column) = file.column {
+                    goto = format!("{}:{}", goto, column);
+                }
+            }
+            command.arg("--goto").arg(goto);
+        }
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", self.code, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                self.code,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(match file {
+            Some(file) => format!("{} opened in {}", file.path.display(), target.display()),
+            None => format!("{} opened", target.display()),
+        })
+    }
+
+    fn open(
+        &self,
+        project: &Project,
+        context: Option<&str>,
+        file: Option<&FileTarget>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let directory = match context {
+            Some(context) => Some(
+                project
+                    .context(context)
+                    .ok_or(format!(
+                        "context {} not found for project {}",
+                        context, project.name
+                    ))?
+                    .path
+                    .clone(),
+            ),
+            None => project.properties.get("path").cloned(),
+        };
+        match self.kind(project, arguments)? {
+            EditorKind::Nvim => {
+                self.open_nvim(project, context, directory.as_deref(), file, arguments)
+            }
+            EditorKind::Code => self.open_code(project, directory.as_deref(), file, arguments),
+        }
+    }
+
+    // the project and each of its contexts have their own server
+    fn owners(project: &Project) -> Vec<String> {
+        std::iter::once(None)
+            .chain(project.contexts.iter().map(|c| Some(c.name.as_str())))
+            .map(|context| context_name(&project.name, context))
+            .collect()
+    }
+
+    // stops the headless servers of the project, the ones in tmux go with its session
+    fn stop_servers(&self, project: &Project) {
+        for owner in Self::owners(project) {
+            let group = self.headless.lock().unwrap().get(&owner).copied();
+            match group {
+                // its socket is removed once it exited
+                Some(group) => signal_group(group, "TERM"),
+                None => {
+                    let socket = self.socket(&owner);
+                    if socket.exists() && !self.nvim_running(&socket) {
+                        let _ = std::fs::remove_file(&socket);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn find_workspace(directory: &Path) -> Option<PathBuf> {
+    let mut workspaces: Vec<PathBuf> = std::fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "code-workspace"))
+        .collect();
+    workspaces.sort();
+    workspaces.into_iter().next()
+}
+
+fn default_socket_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".cache/project-pilot/nvim")
+}
+
+fn context_param() -> ParamDescriptor {
+    ParamDescriptor::named("context", "use this context of the project")
+}
+
+impl Plugin for EditorPlugin {
+    fn name(&self) -> String {
+        "editor".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let context = match event {
+            Event::PluginDisable => return self.stop_servers(project),
+            Event::PluginEnable => None,
+            Event::ProjectSwitch => arguments.get("context").and_then(|c| c.as_str()),
+            _ => return,
+        };
+        match self.open(project, context, None, arguments) {
+            Ok(result) => println!("{}", result),
+            Err(e) => eprintln!("editor plugin: {}", e),
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "opens the project in neovim or vs code".to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectSwitch.to_string(),
+            ],
+            properties: vec![
+                "path".to_string(),
+                format!("{}kind", PREFIX),
+                format!("{}workspace", PREFIX),
+            ],
+            after: vec!["tmux".to_string(), "env".to_string()],
+            config: vec![
+                ParamDescriptor::named("kind", "the editor of the projects, nvim or code"),
+                ParamDescriptor::named("nvim", "the neovim binary"),
+                ParamDescriptor::named("code", "the vs code binary"),
+                ParamDescriptor::named(
+                    "socket-dir",
+                    "where the neovim servers listen, ~/.cache/project-pilot/nvim by default",
+                ),
+            ],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new("open", "open the project in its editor")
+                .needs_project()
+                .param(context_param()),
+            ActionDescriptor::new(
+                "open-file",
+                "open a file of the project in its editor instance",
+            )
+            .needs_project()
+            .param(context_param())
+            .param(
+                ParamDescriptor::positional("file", "the file, relative to the project directory")
+                    .required(),
+            )
+            .param(
+                ParamDescriptor::named("line", "the line to go to")
+                    .kind(ParamKind::Integer)
+                    .min(1),
+            )
+            .param(
+                ParamDescriptor::named("column", "the column to go to")
+                    .kind(ParamKind::Integer)
+                    .min(1),
+            ),
+            ActionDescriptor::new(
+                "server",
+                "print the socket of the neovim server, for `nvim --server`",
+            )
+            .needs_project()
+            .param(context_param()),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        let context = action_arg(arguments, "context").and_then(|c| c.as_str());
+        match action {
+            "open" => self.open(project, context, None, arguments),
+            "open-file" => {
+                let file = action_arg(arguments, "file")
+                    .and_then(|f| f.as_str())
+                    .ok_or("a file is needed".to_string())?;
+                let directory = match context {
+                    Some(context) => project.context(context).map(|c| c.path.clone()),
+                    None => project.properties.get("path").cloned(),
+                };
+                let path = match directory {
+                    Some(directory) => Path::new(&directory).join(file),
+                    None => PathBuf::from(file),
+                };
+                let file = FileTarget {
+                    path,
+                    line: action_arg(arguments, "line").and_then(|l| l.as_i64()),
+                    column: action_arg(arguments, "column").and_then(|c| c.as_i64()),
+                };
+                self.open(project, context, Some(&file), arguments)
+            }
+            "server" => {
+                if self.kind(project, arguments)? != EditorKind::Nvim {
+                    return Err(format!("project {} does not use nvim", project.name));
+                }
+                let socket = self.socket(&context_name(&project.name, context));
+                if !self.nvim_running(&socket) {
+                    return Err(format!(
+                        "no nvim server at {}, start it with `project-pilot plugin run editor open {}`",
+                        socket.display(),
+                        project.name
+                    ));
+                }
+                Ok(socket.to_string_lossy().to_string())
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let status = match self.kind(project, arguments) {
+            Ok(EditorKind::Nvim) => {
+                let running: Vec<String> = Self::owners(project)
+                    .into_iter()
+                    .filter(|owner| self.nvim_running(&self.socket(owner)))
+                    .collect();
+                if running.is_empty() {
+                    "nvim server not running".to_string()
+                } else {
+                    format!("nvim server running for {}", running.join(", "))
+                }
+            }
+            Ok(EditorKind::Code) => "vs code".to_string(),
+            Err(e) => e,
+        };
+        Some(status)
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let (name, binary, fix) = match self.kind {
+            EditorKind::Nvim => (
+                "nvim",
+                &self.nvim,
+                "install neovim, or set its path with `plugin set-config editor nvim <path>`",
+            ),
+            EditorKind::Code => (
+                "code",
+                &self.code,
+                "install the `code` command of vs code, or set its path with `plugin set-config editor code <path>`",
+            ),
+        };
+        vec![check_command(name, binary, &["--version"], fix)]
+    }
+    fn set_request_sender(&mut self, sender: RequestSender) {
+        self.requests = Some(sender);
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        let setting = |name: &str| config.get(name).and_then(|v| v.as_str());
+        self.kind = match setting("kind") {
+            Some(kind) => EditorKind::from_str(kind)
+                .map_err(|_| format!("unknown editor {}, use nvim or code", kind))?,
+            None => EditorKind::Nvim,
+        };
+        self.nvim = setting("nvim").unwrap_or("nvim").to_string();
+        self.code = setting("code").unwrap_or("code").to_string();
+        self.socket_dir = setting("socket-dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_socket_dir);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::{fake_command, wait_for_lines, wait_until};
+    use tempfile::TempDir;
+
+    // a neovim logging its arguments, whose headless server creates its socket and waits
+    fn plugin(dir: &TempDir, config: Value) -> (EditorPlugin, PathBuf) {
+        let log = dir.path().join("nvim.log");
+        let body = format!(
+            r#"echo "$*" >> {}
+case "$1" in
+  --headless) touch "$3"; exec sleep 60 ;;
+  --server) test -e "$2" ;;
+esac"#,
+            log.display()
+        );
+        let nvim = fake_command(dir.path(), "nvim", &body);
+        let mut config = config.as_object().unwrap().clone();
+        config.insert("nvim".to_string(), json!(nvim.display().to_string()));
+        config.insert(
+            "socket-dir".to_string(),
+            json!(dir.path().join("sockets").display().to_string()),
+        );
+        let mut plugin = EditorPlugin::new();
+        plugin.configure(&config).unwrap();
+        (plugin, log)
+    }
+
+    fn project(dir: &TempDir, properties: &[(&str, &str)]) -> Project {
+        let mut project = Project {
+            name: "app".to_string(),
+            ..Project::default()
+        };
+        project.properties.insert("path".to_string(), dir.path().display().to_string());
+        for (key, value) in properties {
+            project.properties.insert(key.to_string(), value.to_string());
+        }
+        project
+    }
+
+    #[test]
+    fn contexts_have_their_own_socket() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, _) = plugin(&dir, json!({}));
+        let sockets = dir.path().join("sockets");
+        assert_eq!(plugin.socket("app"), sockets.join("app.socket"));
+        assert_eq!(plugin.socket("app/wt"), sockets.join("app@wt.socket"));
+    }
+
+    #[test]
+    fn nvim_command_goes_to_the_position() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, _) = plugin(&dir, json!({}));
+        let socket = plugin.socket("app");
+        let nvim = plugin.nvim.clone();
+        let file = FileTarget {
+            path: dir.path().join("main.rs"),
+            line: Some(3),
+            column: None,
+        };
+        assert_eq!(
+            plugin.nvim_command(&socket, Some(&file)),
+            vec![
+                nvim.clone(),
+                "--listen".to_string(),
+                socket.display().to_string(),
+                "+call cursor(3, 1)".to_string(),
+                file.path.display().to_string(),
+            ]
+        );
+        assert_eq!(
+            plugin.nvim_command(&socket, None),
+            vec![nvim, "--listen".to_string(), socket.display().to_string()]
+        );
+    }
+
+    #[test]
+    fn kind_is_chosen_by_the_properties_then_the_config() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, _) = plugin(&dir, json!({"kind": "code"}));
+        let global = json!({"global-properties": {"editor.kind": "nvim"}});
+        let global = global.as_object().unwrap();
+        assert_eq!(plugin.kind(&project(&dir, &[]), &Map::new()), Ok(EditorKind::Code));
+        assert_eq!(plugin.kind(&project(&dir, &[]), global), Ok(EditorKind::Nvim));
+        let code = project(&dir, &[("editor.kind", "code")]);
+        assert_eq!(plugin.kind(&code, global), Ok(EditorKind::Code));
+        let emacs = project(&dir, &[("editor.kind", "emacs")]);
+        assert!(plugin.kind(&emacs, &Map::new()).unwrap_err().contains("unknown editor emacs"));
+    }
+
+    #[test]
+    fn headless_server_opens_files_and_stops_on_disable() {
+        let dir = TempDir::new().unwrap();
+        let (plugin, log) = plugin(&dir, json!({}));
+        let mut project = project(&dir, &[]);
+        let socket = plugin.socket("app");
+
+        plugin.on_event(Event::PluginEnable, &mut project, &Map::new());
+        assert!(wait_until(|| socket.exists()));
+        assert_eq!(
+            plugin.status(&project, &Map::new()),
+            Some("nvim server running for app".to_string())
+        );
+
+        let arguments = json!({"args": {"file": "main.rs", "line": 3, "column": 2}});
+        let arguments = arguments.as_object().unwrap();
+        let result = plugin.run_action("open-file", Some(&mut project), arguments).unwrap();
+        assert!(result.contains("main.rs opened in"));
+        let lines = wait_for_lines(&log, |lines| lines.iter().any(|l| l.contains("cursor")));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("--server") && l.contains("call cursor(3, 2)")));
+
+        plugin.on_event(Event::PluginDisable, &mut project, &Map::new());
+        assert!(wait_until(|| !socket.exists()));
+        assert!(plugin.headless.lock().unwrap().is_empty());
+    }
+}
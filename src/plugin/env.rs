@@ -0,0 +1,234 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::tmux_client::shell_quote;
+use crate::plugin::{properties, ActionDescriptor, ParamDescriptor, ParamKind, Plugin, PluginInfo};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::Command;
+
+// the environment of a project is made of, later ones winning:
+//   - the `.env` file in its directory (`env.file` to use another name)
+//   - `direnv export json` in its directory, when `env.direnv` is true
+//   - the `env.<NAME>` properties, global ones first
+const PREFIX: &str = "env.";
+const DEFAULT_FILE: &str = ".env";
+
+pub type Environment = BTreeMap<String, String>;
+
+pub struct EnvPlugin {}
+
+impl EnvPlugin {
+    pub fn new() -> Self {
+        EnvPlugin {}
+    }
+}
+
+// names that can be exported by a shell: [A-Za-z_][A-Za-z0-9_]*
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `KEY=value` lines, with optional `export` and quotes, `#` starts a comment.
+// Lines with an invalid name are skipped
+pub fn parse_dotenv(content: &str) -> Environment {
+    let mut environment = Environment::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if !is_variable_name(key) {
+            eprintln!("env: skipping invalid variable name {:?}", key);
+            continue;
+        }
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let inner = &value[1..];
+                let end = inner.rfind(quote).unwrap_or(inner.len());
+                let inner = &inner[..end];
+                if quote == '"' {
+                    inner.replace("\\n", "\n").replace("\\\"", "\"")
+                } else {
+                    inner.to_string()
+                }
+            }
+            _ => value
+                .split(" #")
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_string(),
+        };
+        environment.insert(key.to_string(), value);
+    }
+    environment
+}
+
+fn direnv_export(dir: &Path) -> Result<Environment, String> {
+    let output = Command::new("direnv")
+        .args(["export", "json"])
+        .current_dir(dir)
+        .env("DIRENV_LOG_FORMAT", "")
+        .output()
+        .map_err(|e| format!("failed to execute direnv: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "direnv failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // nothing is printed when there is no .envrc
+    if stdout.trim().is_empty() {
+        return Ok(Environment::new());
+    }
+    let exported: HashMap<String, Option<String>> =
+        serde_json::from_str(&stdout).map_err(|e| format!("invalid direnv output: {}", e))?;
+    // unset variables come as null, the DIRENV_ ones are its own bookkeeping
+    Ok(exported
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with("DIRENV_"))
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect())
+}
+
+// the environment of the project, in the given directory or the project `path`
+pub fn project_environment(
+    project: &Project,
+    directory: Option<&str>,
+    arguments: &Map<String, Value>,
+) -> Result<Environment, String> {
+    let properties = properties(project, arguments);
+    let mut environment = Environment::new();
+
+    let directory = directory.or(project.properties.get("path").map(|p| p.as_str()));
+    if let Some(directory) = directory.map(Path::new) {
+        let file_name = properties
+            .get(&format!("{}file", PREFIX))
+            .map(|f| f.as_str())
+            .unwrap_or(DEFAULT_FILE);
+        if let Ok(content) = std::fs::read_to_string(directory.join(file_name)) {
+            environment.extend(parse_dotenv(&content));
+        }
+        if properties.get(&format!("{}direnv", PREFIX)).map(|d| d.as_str()) == Some("true") {
+            environment.extend(direnv_export(directory)?);
+        }
+    }
+
+    let mut names: Vec<&String> = properties
+        .keys()
+        .filter(|key| key.starts_with(PREFIX) && key.len() > PREFIX.len())
+        .filter(|key| !["file", "direnv"].contains(&&key[PREFIX.len()..]))
+        .collect();
+    names.sort();
+    for key in names {
+        let name = &key[PREFIX.len()..];
+        if !is_variable_name(name) {
+            eprintln!("env: skipping invalid variable name {:?}", name);
+            continue;
+        }
+        environment.insert(name.to_string(), properties[key].clone());
+    }
+    Ok(environment)
+}
+
+impl Plugin for EnvPlugin {
+    fn name(&self) -> String {
+        "env".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {}
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "the environment variables of the project, used by tmux sessions"
+                .to_string(),
+            properties: vec![
+                format!("{}<NAME>", PREFIX),
+                format!("{}file", PREFIX),
+                format!("{}direnv", PREFIX),
+                "path".to_string(),
+            ],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![ActionDescriptor::new(
+            "export",
+            "print the project environment as export lines, to eval in a shell",
+        )
+        .needs_project()
+        .param(ParamDescriptor::named(
+            "context",
+            "use the directory of this context of the project",
+        ))
+        .param(ParamDescriptor::named("json", "print it as json").kind(ParamKind::Bool))]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        match action {
+            "export" => {
+                let directory = match action_arg(arguments, "context").and_then(|c| c.as_str()) {
+                    Some(context) => Some(
+                        project
+                            .context(context)
+                            .ok_or(format!(
+                                "context {} not found for project {}",
+                                context, project.name
+                            ))?
+                            .path
+                            .clone(),
+                    ),
+                    None => None,
+                };
+                let environment = project_environment(project, directory.as_deref(), arguments)?;
+                if action_arg(arguments, "json").and_then(|j| j.as_bool()) == Some(true) {
+                    return Ok(serde_json::to_string(&environment).unwrap());
+                }
+                Ok(environment
+                    .iter()
+                    .map(|(key, value)| format!("export {}={}", key, shell_quote(value)))
+                    .collect::<Vec<String>>()
+                    .join("\n"))
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dotenv_reads_values() {
+        let environment = parse_dotenv(
+            "# comment\nexport A=1\nB = \"two\\nlines\" \nC='single # kept'\nD=value # comment\n",
+        );
+        assert_eq!(environment["A"], "1");
+        assert_eq!(environment["B"], "two\nlines");
+        assert_eq!(environment["C"], "single # kept");
+        assert_eq!(environment["D"], "value");
+    }
+
+    #[test]
+    fn parse_dotenv_skips_invalid_names() {
+        let environment = parse_dotenv("OK_1=1\n1BAD=2\nBAD;rm -rf ~=3\nA B=4\n=5\n_=6\n");
+        assert_eq!(environment.keys().collect::<Vec<_>>(), vec!["OK_1", "_"]);
+    }
+}
@@ -0,0 +1,316 @@
+// External plugins are executables named `project-pilot-plugin-<name>`, found on PATH or in
+// the plugin directory. They speak JSON-RPC 2.0 over stdio, one message per line, using the
+// methods described in rpc.rs:
+//
+//   -> {"jsonrpc":"2.0","id":1,"method":"name","params":{}}
+//   <- {"jsonrpc":"2.0","id":1,"result":"my-plugin"}
+//
+// errors are reported with the standard {"error": {"code", "message"}} object.
+use crate::plugin::rpc::{RpcChannel, RpcPlugin};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const EXECUTABLE_PREFIX: &str = "project-pilot-plugin-";
+
+struct PluginProcess {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl PluginProcess {
+    fn spawn(path: &Path) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("failed to start {}: {}", path.display(), e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = channel();
+        // the channel is closed when the plugin closes its stdout, usually because it died
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(PluginProcess {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub type ExternalPlugin = RpcPlugin<ProcessChannel>;
+
+// starts the executable and asks for its name
+pub fn start(path: &Path, timeout: Duration) -> Result<ExternalPlugin, String> {
+    RpcPlugin::new(ProcessChannel {
+        path: path.to_path_buf(),
+        timeout,
+        next_id: AtomicU64::new(1),
+        process: Mutex::new(None),
+        configure: Mutex::new(None),
+    })
+}
+
+pub struct ProcessChannel {
+    path: PathBuf,
+    timeout: Duration,
+    next_id: AtomicU64,
+    process: Mutex<Option<PluginProcess>>,
+    // the params of the last configure, sent again to a restarted plugin
+    configure: Mutex<Option<Value>>,
+}
+
+impl RpcChannel for ProcessChannel {
+    // waiting for the answer blocks, the daemon runtime moves its other tasks to another thread
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        tokio::task::block_in_place(|| self.call_blocking(method, params))
+    }
+}
+
+impl ProcessChannel {
+    fn call_blocking(&self, method: &str, params: Value) -> Result<Value, String> {
+        let mut process = self.process.lock().unwrap();
+        // restart plugins that crashed since the last call, with their configuration
+        if !process.as_mut().map(|p| p.is_alive()).unwrap_or(false) {
+            if let Some(mut dead) = process.take() {
+                eprintln!(
+                    "external plugin {} exited, restarting it",
+                    self.path.display()
+                );
+                dead.kill();
+            }
+            *process = Some(PluginProcess::spawn(&self.path)?);
+            let configure = self.configure.lock().unwrap().clone();
+            if let Some(configure) = configure.filter(|_| method != "configure") {
+                if let Err(e) = self.exchange(&mut process, "configure", configure) {
+                    eprintln!("external plugin {}: {}", self.path.display(), e);
+                }
+            }
+        }
+        if method == "configure" {
+            *self.configure.lock().unwrap() = Some(params.clone());
+        }
+        self.exchange(&mut process, method, params)
+    }
+
+    // sends a request to the running plugin and waits for its answer, a plugin that fails to
+    // answer is killed
+    fn exchange(
+        &self,
+        process: &mut Option<PluginProcess>,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, String> {
+        let running = match process.as_mut() {
+            Some(running) => running,
+            None => return Err(format!("plugin {} is not running", self.path.display())),
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let written = writeln!(running.stdin, "{}", request).and_then(|_| running.stdin.flush());
+        if let Err(e) = written {
+            running.kill();
+            *process = None;
+            return Err(format!(
+                "failed to write to plugin {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match running.lines.recv_timeout(remaining) {
+                Ok(line) => {
+                    let response: Value = match serde_json::from_str(&line) {
+                        Ok(response) => response,
+                        Err(_) => {
+                            // not a protocol message, probably some debug print
+                            eprintln!("external plugin {}: {}", self.path.display(), line);
+                            continue;
+                        }
+                    };
+                    if response.get("id") != Some(&json!(id)) {
+                        continue;
+                    }
+                    if let Some(error) = response.get("error") {
+                        let message = error
+                            .get("message")
+                            .and_then(|m| m.as_str())
+                            .unwrap_or("unknown error");
+                        return Err(message.to_string());
+                    }
+                    return Ok(response.get("result").cloned().unwrap_or(Value::Null));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // a stuck plugin is killed, it will be restarted by the next call
+                    running.kill();
+                    *process = None;
+                    return Err(format!(
+                        "plugin {} did not answer to {} within {:?}",
+                        self.path.display(),
+                        method,
+                        self.timeout
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    running.kill();
+                    *process = None;
+                    return Err(format!(
+                        "plugin {} crashed during {}",
+                        self.path.display(),
+                        method
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ProcessChannel {
+    fn drop(&mut self) {
+        if let Some(process) = self.process.lock().unwrap().as_mut() {
+            process.kill();
+        }
+    }
+}
+
+// executables named project-pilot-plugin-* in the given directories, the first one wins
+pub fn find_executables(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            let executable = path
+                .metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if file_name.starts_with(EXECUTABLE_PREFIX)
+                && executable
+                && !found.iter().any(|f| f.file_name() == path.file_name())
+            {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::fake_command;
+    use crate::plugin::Plugin;
+    use serde_json::Map;
+    use tempfile::TempDir;
+
+    // a plugin logging the methods it receives, the run_action `crash` exits and `hang` never
+    // answers
+    fn fixture(dir: &TempDir) -> PathBuf {
+        let log = dir.path().join("methods.log");
+        let body = format!(
+            r#"while read -r line; do
+  id=$(printf '%s' "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+  method=$(printf '%s' "$line" | sed 's/.*"method":"\([a-z_]*\)".*/\1/')
+  echo "$method $(printf '%s' "$line" | sed 's/.*"params":\(.*\)}}$/\1/')" >> {log}
+  case "$line" in
+    *'"action":"crash"'*) exit 1 ;;
+    *'"action":"hang"'*) sleep 5 ;;
+  esac
+  case "$method" in
+    name) result='"fixture"' ;;
+    configure) result=null ;;
+    run_action) result='{{"output":"done"}}' ;;
+    *) echo "{{\"jsonrpc\":\"2.0\",\"id\":$id,\"error\":{{\"message\":\"unknown\"}}}}"; continue ;;
+  esac
+  echo "{{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":$result}}"
+done"#,
+            log = log.display()
+        );
+        fake_command(dir.path(), "project-pilot-plugin-fixture", &body)
+    }
+
+    fn methods(dir: &TempDir) -> Vec<String> {
+        std::fs::read_to_string(dir.path().join("methods.log"))
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    fn configured(plugin: &mut ExternalPlugin) {
+        let mut config = Map::new();
+        config.insert("greeting".to_string(), json!("hi"));
+        plugin.configure(&config).unwrap();
+    }
+
+    #[test]
+    fn plugins_are_started_and_answer() {
+        let dir = TempDir::new().unwrap();
+        let plugin = start(&fixture(&dir), Duration::from_secs(5)).unwrap();
+        assert_eq!(plugin.name(), "fixture");
+        assert_eq!(plugin.run_action("any", None, &Map::new()), Ok("done".to_string()));
+        assert_eq!(methods(&dir)[0], "name {}");
+    }
+
+    #[test]
+    fn crashed_plugins_are_restarted_with_their_configuration() {
+        let dir = TempDir::new().unwrap();
+        let mut plugin = start(&fixture(&dir), Duration::from_secs(5)).unwrap();
+        configured(&mut plugin);
+        let error = plugin.run_action("crash", None, &Map::new()).unwrap_err();
+        assert!(error.contains("crashed during run_action"), "{}", error);
+        assert_eq!(plugin.run_action("any", None, &Map::new()), Ok("done".to_string()));
+
+        let methods = methods(&dir);
+        let configure = r#"configure {"config":{"greeting":"hi"}}"#;
+        assert_eq!(methods[1], configure);
+        assert!(methods[2].starts_with("run_action"));
+        assert_eq!(methods[3], configure);
+        assert!(methods[4].starts_with("run_action"));
+    }
+
+    #[test]
+    fn stuck_plugins_are_killed() {
+        let dir = TempDir::new().unwrap();
+        let mut plugin = start(&fixture(&dir), Duration::from_millis(300)).unwrap();
+        configured(&mut plugin);
+        let started = Instant::now();
+        let error = plugin.run_action("hang", None, &Map::new()).unwrap_err();
+        assert!(error.contains("did not answer to run_action"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(plugin.run_action("any", None, &Map::new()), Ok("done".to_string()));
+        assert!(methods(&dir)[3].starts_with("configure"));
+    }
+}
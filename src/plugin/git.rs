@@ -0,0 +1,426 @@
+use crate::config::{Context, Project};
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::git_client::{head_path, read_head, GitClient, GitStatus};
+use crate::plugin::health::check_command;
+use crate::plugin::{
+    ActionDescriptor, DaemonRequest, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+    RequestSender,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_POLL_INTERVAL: u64 = 2;
+const DEFAULT_LOG_COUNT: u64 = 10;
+
+struct WatchedHead {
+    head: PathBuf,
+    branch: Option<String>,
+}
+
+// reads the repository in the `path` of each project. The HEAD files are polled, and a
+// project-branch-change event is sent when a branch changes
+pub struct GitPlugin {
+    client: GitClient,
+    watched: Arc<Mutex<HashMap<String, WatchedHead>>>,
+    poll_interval: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl GitPlugin {
+    pub fn new() -> Self {
+        GitPlugin {
+            client: GitClient::new(),
+            watched: Arc::new(Mutex::new(HashMap::new())),
+            poll_interval: Arc::new(AtomicU64::new(DEFAULT_POLL_INTERVAL)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn repository(project: &Project) -> Result<PathBuf, String> {
+        project
+            .properties
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(format!("project {} has no path property", project.name))
+    }
+
+    fn project_status(&self, project: &Project) -> Result<GitStatus, String> {
+        self.client.status(&GitPlugin::repository(project)?)
+    }
+
+    // starts watching the project, or stops when it is not a repository anymore
+    fn watch(&self, project: &Project) -> Result<(), String> {
+        let mut watched = self.watched.lock().unwrap();
+        let head = match GitPlugin::repository(project).map(|path| head_path(&path)) {
+            Ok(Some(head)) => head,
+            Ok(None) => {
+                watched.remove(&project.name);
+                return Err(format!(
+                    "project {}: {} is not a git repository",
+                    project.name,
+                    project.properties["path"]
+                ));
+            }
+            Err(e) => {
+                watched.remove(&project.name);
+                return Err(e);
+            }
+        };
+        if watched.get(&project.name).map(|w| w.head != head).unwrap_or(true) {
+            let branch = read_head(&head);
+            watched.insert(project.name.clone(), WatchedHead { head, branch });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GitPlugin {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn poll_heads(
+    watched: Arc<Mutex<HashMap<String, WatchedHead>>>,
+    poll_interval: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    requests: RequestSender,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_secs(poll_interval.load(Ordering::SeqCst)));
+        let mut changes = Vec::new();
+        for (project, watched) in watched.lock().unwrap().iter_mut() {
+            let branch = read_head(&watched.head);
+            if branch != watched.branch {
+                changes.push((project.clone(), branch.clone(), watched.branch.take()));
+                watched.branch = branch;
+            }
+        }
+        for (project, branch, previous) in changes {
+            let params = json!({
+                "event-name": Event::ProjectBranchChange.to_string(),
+                "project-name": project,
+                "branch": branch.unwrap_or_default(),
+                "previous-branch": previous.unwrap_or_default(),
+            });
+            let request =
+                DaemonRequest::new("event", "trigger", params.as_object().unwrap().clone());
+            if requests.send(request).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// `~/src/repo` gets its worktrees in `~/src/repo-<context>`
+fn default_worktree_path(repository: &Path, context: &str) -> PathBuf {
+    let repository_name = repository
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let directory = format!("{}-{}", repository_name, context.replace('/', "-"));
+    repository.with_file_name(directory)
+}
+
+impl Plugin for GitPlugin {
+    fn name(&self) -> String {
+        "git".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        match event {
+            Event::PluginEnable | Event::ProjectUpdate => {
+                if let Err(e) = self.watch(project) {
+                    eprintln!("git plugin: {}", e);
+                }
+            }
+            Event::PluginDisable => {
+                self.watched.lock().unwrap().remove(&project.name);
+            }
+            _ => {}
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "branch and status of the project repository".to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectUpdate.to_string(),
+            ],
+            properties: vec!["path".to_string()],
+            config: vec![
+                ParamDescriptor::named(
+                    "poll-interval",
+                    "seconds between two checks of the repositories HEAD, 2 by default",
+                )
+                .kind(ParamKind::Integer)
+                .min(1),
+                ParamDescriptor::named("binary", "the git executable"),
+            ],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new("status", "branch, upstream and dirty/ahead/behind counts")
+                .needs_project()
+                .param(
+                    ParamDescriptor::named("json", "print it as json, for scripts")
+                        .kind(ParamKind::Bool),
+                ),
+            ActionDescriptor::new("branch", "print the current branch").needs_project(),
+            ActionDescriptor::new("counts", "print the dirty, ahead and behind counts")
+                .needs_project(),
+            ActionDescriptor::new(
+                "worktree-add",
+                "create a worktree and register it as a context of the project",
+            )
+            .needs_project()
+            .param(ParamDescriptor::positional("context", "name of the context").required())
+            .param(ParamDescriptor::named(
+                "branch",
+                "branch to check out, created if missing, the context name by default",
+            ))
+            .param(ParamDescriptor::named(
+                "path",
+                "where to create it, next to the repository by default",
+            )),
+            ActionDescriptor::new(
+                "worktree-remove",
+                "remove the worktree of a context, and the context",
+            )
+            .needs_project()
+            .param(ParamDescriptor::positional("context", "name of the context").required())
+            .param(
+                ParamDescriptor::named("force", "remove it even with local changes")
+                    .kind(ParamKind::Bool),
+            ),
+            ActionDescriptor::new("worktree-list", "print the contexts with their branch")
+                .needs_project(),
+            ActionDescriptor::new("log", "print the recent commits")
+                .needs_project()
+                .param(
+                    ParamDescriptor::named("count", "how many commits, 10 by default")
+                        .kind(ParamKind::Integer)
+                        .min(1),
+                ),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        match action {
+            "status" => {
+                let status = self.project_status(project)?;
+                if action_arg(arguments, "json").and_then(|j| j.as_bool()) == Some(true) {
+                    return Ok(json!(status).to_string());
+                }
+                Ok(format!(
+                    "branch: {}\nupstream: {}\ndirty: {}\nahead: {}\nbehind: {}",
+                    status.branch,
+                    status.upstream.unwrap_or("none".to_string()),
+                    status.dirty,
+                    status.ahead,
+                    status.behind
+                ))
+            }
+            "branch" => Ok(self.project_status(project)?.branch),
+            "counts" => {
+                let status = self.project_status(project)?;
+                Ok(format!(
+                    "{} {} {}",
+                    status.dirty, status.ahead, status.behind
+                ))
+            }
+            "log" => {
+                let count = action_arg(arguments, "count")
+                    .and_then(|c| c.as_u64())
+                    .unwrap_or(DEFAULT_LOG_COUNT);
+                Ok(self
+                    .client
+                    .recent_commits(&GitPlugin::repository(project)?, count)?
+                    .join("\n"))
+            }
+            "worktree-add" => {
+                let repository = GitPlugin::repository(project)?;
+                let context = action_arg(arguments, "context")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or_default();
+                if project.context(context).is_some() {
+                    return Err(format!(
+                        "project {} already has a context {}",
+                        project.name, context
+                    ));
+                }
+                let branch = action_arg(arguments, "branch")
+                    .and_then(|b| b.as_str())
+                    .unwrap_or(context);
+                let path = match action_arg(arguments, "path").and_then(|p| p.as_str()) {
+                    Some(path) => repository.join(path),
+                    None => default_worktree_path(&repository, context),
+                };
+                self.client.add_worktree(&repository, &path, branch)?;
+                let path = path.canonicalize().unwrap_or(path);
+                project.contexts.push(Context {
+                    name: context.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                });
+                Ok(format!(
+                    "worktree {} created in {}, switch to it with `project-pilot project switch {}/{}`",
+                    branch,
+                    path.display(),
+                    project.name,
+                    context
+                ))
+            }
+            "worktree-remove" => {
+                let repository = GitPlugin::repository(project)?;
+                let context = action_arg(arguments, "context")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or_default();
+                let path = match project.context(context) {
+                    Some(context) => PathBuf::from(&context.path),
+                    None => {
+                        return Err(format!(
+                            "project {} has no context {}",
+                            project.name, context
+                        ))
+                    }
+                };
+                let force = action_arg(arguments, "force").and_then(|f| f.as_bool()) == Some(true);
+                self.client.remove_worktree(&repository, &path, force)?;
+                project.contexts.retain(|c| c.name != context);
+                Ok(format!("worktree {} removed", path.display()))
+            }
+            "worktree-list" => Ok(project
+                .contexts
+                .iter()
+                .map(|context| {
+                    let branch = head_path(Path::new(&context.path))
+                        .and_then(|head| read_head(&head))
+                        .unwrap_or("not a repository".to_string());
+                    format!("{}: {} ({})", context.name, branch, context.path)
+                })
+                .collect::<Vec<String>>()
+                .join("\n")),
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let status = match self.project_status(project) {
+            Ok(status) => status,
+            Err(e) => return Some(e),
+        };
+        Some(format!(
+            "{}, {} dirty, {} ahead, {} behind",
+            status.branch, status.dirty, status.ahead, status.behind
+        ))
+    }
+    // keeps the watched repositories in line with the projects using the plugin
+    fn reconcile(&self, projects: &mut [Project]) -> Vec<String> {
+        let mut report = Vec::new();
+        let enabled: Vec<&Project> = projects
+            .iter()
+            .filter(|p| p.plugins.iter().any(|plugin| plugin == "git"))
+            .collect();
+        self.watched
+            .lock()
+            .unwrap()
+            .retain(|name, _| enabled.iter().any(|p| &p.name == name));
+        for project in enabled {
+            if let Err(e) = self.watch(project) {
+                report.push(e);
+            }
+        }
+        report
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        vec![check_command(
+            "binary",
+            &self.client.binary(),
+            &["--version"],
+            "install git with the system package manager, or set the binary with `plugin set-config git binary <path>`",
+        )]
+    }
+    fn set_request_sender(&mut self, sender: RequestSender) {
+        let watched = Arc::clone(&self.watched);
+        let poll_interval = Arc::clone(&self.poll_interval);
+        let stop = Arc::clone(&self.stop);
+        std::thread::spawn(move || poll_heads(watched, poll_interval, stop, sender));
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        let interval = config
+            .get("poll-interval")
+            .and_then(|i| i.as_i64())
+            .unwrap_or(DEFAULT_POLL_INTERVAL as i64);
+        if interval < 1 {
+            return Err("poll-interval must be at least 1 second".to_string());
+        }
+        self.poll_interval.store(interval as u64, Ordering::SeqCst);
+        self.client = match config.get("binary").and_then(|b| b.as_str()) {
+            Some(binary) => GitClient::with_binary(binary),
+            None => GitClient::new(),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::git_client::tests::{git, repository};
+    use std::time::Instant;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn head_watcher_sends_branch_changes() {
+        let repository = repository();
+        let mut project = Project {
+            name: "watched".to_string(),
+            plugins: vec!["git".to_string()],
+            ..Project::default()
+        };
+        project.properties.insert(
+            "path".to_string(),
+            repository.path().to_string_lossy().to_string(),
+        );
+
+        let mut plugin = GitPlugin::new();
+        plugin.poll_interval.store(1, Ordering::SeqCst);
+        let (sender, mut requests) = unbounded_channel();
+        plugin.set_request_sender(sender);
+        plugin.on_event(Event::PluginEnable, &mut project, &Map::new());
+        git(repository.path(), &["checkout", "-q", "-b", "feature"]);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let request = loop {
+            match requests.try_recv() {
+                Ok(request) => break request,
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(100))
+                }
+                Err(e) => panic!("no branch change: {}", e),
+            }
+        };
+        assert_eq!((request.subject.as_str(), request.command.as_str()), ("event", "trigger"));
+        assert_eq!(
+            Value::Object(request.params),
+            json!({
+                "event-name": "project-branch-change",
+                "project-name": "watched",
+                "branch": "feature",
+                "previous-branch": "main",
+            })
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GitStatus {
+    pub branch: String,
+    pub upstream: Option<String>,
+    // changed, staged or untracked files
+    pub dirty: u32,
+    pub ahead: u32,
+    pub behind: u32,
+}
+
+// runs git in a repository, the binary can be replaced to run against a fake one
+#[derive(Debug, Clone)]
+pub struct GitClient {
+    binary: String,
+}
+
+impl Default for GitClient {
+    fn default() -> Self {
+        GitClient::new()
+    }
+}
+
+impl GitClient {
+    pub fn new() -> Self {
+        GitClient {
+            binary: "git".to_string(),
+        }
+    }
+
+    pub fn with_binary(binary: &str) -> Self {
+        GitClient {
+            binary: binary.to_string(),
+        }
+    }
+
+    pub fn binary(&self) -> String {
+        self.binary.clone()
+    }
+
+    fn run(&self, dir: &Path, args: &[&str]) -> Result<String, String> {
+        let output = Command::new(&self.binary)
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", self.binary, e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!(
+                "git {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    pub fn status(&self, dir: &Path) -> Result<GitStatus, String> {
+        self.run(dir, &["status", "--porcelain=v2", "--branch"])
+            .map(|output| parse_status(&output))
+    }
+
+    pub fn branch_exists(&self, dir: &Path, branch: &str) -> bool {
+        let reference = format!("refs/heads/{}", branch);
+        self.run(dir, &["rev-parse", "--verify", "--quiet", &reference])
+            .is_ok()
+    }
+
+    // checks out the branch in a new worktree, creating the branch if it does not exist
+    pub fn add_worktree(&self, dir: &Path, path: &Path, branch: &str) -> Result<(), String> {
+        let path = path.to_string_lossy();
+        if self.branch_exists(dir, branch) {
+            self.run(dir, &["worktree", "add", &path, branch])?;
+        } else {
+            self.run(dir, &["worktree", "add", "-b", branch, &path])?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_worktree(&self, dir: &Path, path: &Path, force: bool) -> Result<(), String> {
+        let path = path.to_string_lossy();
+        let mut args = vec!["worktree", "remove"];
+        if force {
+            args.push("--force");
+        }
+        args.push(&path);
+        self.run(dir, &args).map(|_| ())
+    }
+
+    // `<short hash> <subject>`, newest first
+    pub fn recent_commits(&self, dir: &Path, count: u64) -> Result<Vec<String>, String> {
+        let count = format!("-{}", count);
+        Ok(self
+            .run(dir, &["log", "--oneline", "--no-decorate", &count])?
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+}
+
+// reads the output of `git status --porcelain=v2 --branch`
+pub fn parse_status(output: &str) -> GitStatus {
+    let mut status = GitStatus::default();
+    let mut oid = String::new();
+    for line in output.lines() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("#"), Some("branch.oid")) => oid = words.next().unwrap_or("").to_string(),
+            (Some("#"), Some("branch.head")) => {
+                status.branch = words.next().unwrap_or("").to_string()
+            }
+            (Some("#"), Some("branch.upstream")) => status.upstream = words.next().map(String::from),
+            (Some("#"), Some("branch.ab")) => {
+                for count in words {
+                    if let Some(ahead) = count.strip_prefix('+') {
+                        status.ahead = ahead.parse().unwrap_or(0);
+                    } else if let Some(behind) = count.strip_prefix('-') {
+                        status.behind = behind.parse().unwrap_or(0);
+                    }
+                }
+            }
+            (Some("#"), _) | (None, _) => {}
+            _ => status.dirty += 1,
+        }
+    }
+    // detached heads are shown by their commit
+    if status.branch == "(detached)" {
+        status.branch = oid.chars().take(7).collect();
+    }
+    status
+}
+
+// the HEAD file of the repository in dir, following the `.git` files of worktrees
+pub fn head_path(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git.join("HEAD"));
+    }
+    let content = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = PathBuf::from(content.strip_prefix("gitdir:")?.trim());
+    Some(dir.join(git_dir).join("HEAD"))
+}
+
+// the branch in a HEAD file, or the short commit when detached
+pub fn read_head(head: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(head).ok()?;
+    let content = content.trim();
+    match content.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        None => Some(content.chars().take(7).collect()),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    pub fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    pub fn commit(dir: &Path, message: &str) {
+        std::fs::write(dir.join("file"), message).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "-q", "-m", message]);
+    }
+
+    // a repository on branch main, with one commit
+    pub fn repository() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        git(dir.path(), &["init", "-q", "-b", "main"]);
+        commit(dir.path(), "first");
+        dir
+    }
+
+    #[test]
+    fn status_reads_branch_and_dirty_files() {
+        let repository = repository();
+        let client = GitClient::new();
+        let status = client.status(repository.path()).unwrap();
+        assert_eq!(status.branch, "main");
+        assert_eq!(status.upstream, None);
+        assert_eq!(status.dirty, 0);
+
+        std::fs::write(repository.path().join("file"), "changed").unwrap();
+        std::fs::write(repository.path().join("untracked"), "new").unwrap();
+        assert_eq!(client.status(repository.path()).unwrap().dirty, 2);
+    }
+
+    #[test]
+    fn status_counts_ahead_and_behind() {
+        let origin = repository();
+        let clone = TempDir::new().unwrap();
+        let source = origin.path().to_str().unwrap();
+        git(clone.path(), &["clone", "-q", source, "."]);
+        commit(clone.path(), "local");
+        commit(origin.path(), "remote one");
+        commit(origin.path(), "remote two");
+        git(clone.path(), &["fetch", "-q"]);
+
+        let status = GitClient::new().status(clone.path()).unwrap();
+        assert_eq!(status.branch, "main");
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (1, 2));
+    }
+
+    #[test]
+    fn detached_head_is_shown_by_its_commit() {
+        let repository = repository();
+        let oid = git(repository.path(), &["rev-parse", "HEAD"]);
+        git(repository.path(), &["checkout", "-q", "--detach"]);
+        let status = GitClient::new().status(repository.path()).unwrap();
+        assert_eq!(status.branch, &oid[..7]);
+        let head = head_path(repository.path()).unwrap();
+        assert_eq!(read_head(&head).unwrap(), &oid[..7]);
+    }
+
+    #[test]
+    fn recent_commits_are_newest_first() {
+        let repository = repository();
+        commit(repository.path(), "second");
+        commit(repository.path(), "third");
+        let client = GitClient::new();
+        let subjects: Vec<String> = client
+            .recent_commits(repository.path(), 2)
+            .unwrap()
+            .iter()
+            .map(|line| line.split_once(' ').unwrap().1.to_string())
+            .collect();
+        assert_eq!(subjects, vec!["third", "second"]);
+        assert_eq!(client.recent_commits(repository.path(), 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn head_of_a_worktree_follows_its_git_file() {
+        let repository = repository();
+        let worktrees = TempDir::new().unwrap();
+        let worktree = worktrees.path().join("feature");
+        let client = GitClient::new();
+        client
+            .add_worktree(repository.path(), &worktree, "feature")
+            .unwrap();
+        assert!(client.branch_exists(repository.path(), "feature"));
+        let head = head_path(&worktree).unwrap();
+        assert_eq!(read_head(&head).as_deref(), Some("feature"));
+        assert_eq!(
+            read_head(&head_path(repository.path()).unwrap()).as_deref(),
+            Some("main")
+        );
+        client
+            .remove_worktree(repository.path(), &worktree, false)
+            .unwrap();
+        assert!(!worktree.exists());
+    }
+
+    #[test]
+    fn status_outside_a_repository_fails() {
+        let dir = TempDir::new().unwrap();
+        let error = GitClient::new().status(dir.path()).unwrap_err();
+        assert!(error.starts_with("git status failed"), "{}", error);
+    }
+}
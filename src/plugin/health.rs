@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+// one line of `project-pilot doctor`, failed checks come with the way to fix them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthCheck {
+    pub name: String,
+    pub ok: bool,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub fix: Option<String>,
+}
+
+impl HealthCheck {
+    pub fn ok(name: &str, message: &str) -> Self {
+        HealthCheck {
+            name: name.to_string(),
+            ok: true,
+            message: message.to_string(),
+            fix: None,
+        }
+    }
+
+    pub fn failed(name: &str, message: &str, fix: &str) -> Self {
+        HealthCheck {
+            name: name.to_string(),
+            ok: false,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+// the checks of a plugin, or of another part of the installation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthReport {
+    pub source: String,
+    pub checks: Vec<HealthCheck>,
+}
+
+// runs a command, usually asking for a version, and answers with the first line it prints
+pub fn check_command(name: &str, program: &str, args: &[&str], fix: &str) -> HealthCheck {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let first_line = stdout.lines().next().unwrap_or("").trim();
+            if first_line.is_empty() {
+                HealthCheck::ok(name, &format!("{} found", program))
+            } else {
+                HealthCheck::ok(name, first_line)
+            }
+        }
+        Ok(output) => HealthCheck::failed(
+            name,
+            &format!(
+                "{} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            fix,
+        ),
+        Err(e) => HealthCheck::failed(name, &format!("{} not found: {}", program, e), fix),
+    }
+}
+
+pub fn check_env(variable: &str, fix: &str) -> HealthCheck {
+    match std::env::var(variable) {
+        Ok(value) if !value.is_empty() => {
+            HealthCheck::ok(variable, &format!("{} is set", variable))
+        }
+        _ => HealthCheck::failed(variable, &format!("{} is not set", variable), fix),
+    }
+}
+
+// compares versions like `3.3a` or `next-3.4` with a minimum like `3.0`, ignoring suffixes
+pub fn version_at_least(version: &str, minimum: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .split('.')
+            .map_while(|part| {
+                let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+                digits.parse().ok()
+            })
+            .collect()
+    };
+    numbers(version) >= numbers(minimum)
+}
@@ -0,0 +1,412 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::health::check_command;
+use crate::plugin::{
+    global_properties, properties, ActionDescriptor, HealthCheck, Plugin, PluginInfo,
+};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+// hooks are properties like `hooks.on_project_switch`, or `hooks.on_project_switch.<priority>`
+// to have many of them: lower priorities run first, the default one is 50
+const PREFIX: &str = "hooks.";
+const DEFAULT_PRIORITY: i64 = 50;
+const DEFAULT_TIMEOUT: u64 = 30;
+// the event argument telling that the global hooks already ran, see `with_global_hooks`
+pub const GLOBAL_HOOKS: &str = "global-hooks";
+
+#[derive(Debug, Clone)]
+struct Hook {
+    key: String,
+    priority: i64,
+    command: String,
+}
+
+// the hooks of an event, waiting for their turn
+struct Job {
+    hooks: Vec<Hook>,
+    event: Event,
+    project: Project,
+    arguments: Map<String, Value>,
+    timeout: Duration,
+}
+
+pub struct HooksPlugin {
+    queue: Sender<Job>,
+}
+
+impl HooksPlugin {
+    // a single worker runs the hooks, so that those of successive events don't overlap
+    pub fn new() -> Self {
+        let (queue, jobs) = channel::<Job>();
+        std::thread::spawn(move || {
+            for job in jobs {
+                for hook in job.hooks {
+                    println!("hook {}: running `{}`", hook.key, hook.command);
+                    if let Err(e) =
+                        run_hook(&hook, job.event, &job.project, &job.arguments, job.timeout)
+                    {
+                        eprintln!("hook {} failed: {}", hook.key, e);
+                    }
+                }
+            }
+        });
+        HooksPlugin { queue }
+    }
+}
+
+// `on_project_switch` for Event::ProjectSwitch
+fn hook_name(event: Event) -> String {
+    format!("on_{}", event.to_string().replace('-', "_"))
+}
+
+fn find_hooks(properties: &HashMap<String, String>, event: Event) -> Vec<Hook> {
+    let name = hook_name(event);
+    properties
+        .iter()
+        .filter_map(|(key, command)| {
+            let rest = key.strip_prefix(PREFIX)?.strip_prefix(&name)?;
+            let priority = match rest {
+                "" => DEFAULT_PRIORITY,
+                rest => rest.strip_prefix('.')?.parse().ok()?,
+            };
+            Some(Hook {
+                key: key.clone(),
+                priority,
+                command: command.clone(),
+            })
+        })
+        .collect()
+}
+
+// an event sent to many projects at once runs the global hooks only with the first one,
+// the others are told so with "global-hooks": false
+fn with_global_hooks(arguments: &Map<String, Value>) -> bool {
+    arguments.get(GLOBAL_HOOKS).and_then(|g| g.as_bool()) != Some(false)
+}
+
+// global hooks first, then the project ones, both sorted by priority
+fn hooks_for(project: &Project, event: Event, arguments: &Map<String, Value>) -> Vec<Hook> {
+    let mut hooks = if with_global_hooks(arguments) {
+        find_hooks(&global_properties(arguments), event)
+    } else {
+        vec![]
+    };
+    hooks.sort_by(|a, b| a.key.cmp(&b.key));
+    let mut project_hooks = find_hooks(&project.properties, event);
+    project_hooks.sort_by(|a, b| a.key.cmp(&b.key));
+    hooks.extend(project_hooks);
+    // stable, so equal priorities keep the order above
+    hooks.sort_by_key(|hook| hook.priority);
+    hooks
+}
+
+fn timeout(project: &Project, arguments: &Map<String, Value>) -> Duration {
+    let key = format!("{}timeout", PREFIX);
+    let seconds = properties(project, arguments)
+        .get(&key)
+        .and_then(|t| t.parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT);
+    Duration::from_secs(seconds)
+}
+
+// forwards the output of the hook to the daemon log, line by line
+fn log_output<R: Read + Send + 'static>(key: String, output: R, is_error: bool) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            if is_error {
+                eprintln!("hook {}: {}", key, line);
+            } else {
+                println!("hook {}: {}", key, line);
+            }
+        }
+    });
+}
+
+fn run_hook(
+    hook: &Hook,
+    event: Event,
+    project: &Project,
+    arguments: &Map<String, Value>,
+    timeout: Duration,
+) -> Result<(), String> {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&hook.command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env("PROJECT_PILOT_EVENT", event.to_string())
+        .env("PROJECT_PILOT_PROJECT", &project.name);
+    // a context of the project has its own directory
+    let path = arguments
+        .get("context-path")
+        .and_then(|p| p.as_str())
+        .or(project.properties.get("path").map(|p| p.as_str()));
+    if let Some(path) = path {
+        command.env("PROJECT_PILOT_PROJECT_PATH", path);
+        if std::path::Path::new(path).is_dir() {
+            command.current_dir(path);
+        }
+    }
+    if let Some(previous) = arguments.get("previous-project").and_then(|p| p.as_str()) {
+        command.env("PROJECT_PILOT_PREVIOUS_PROJECT", previous);
+    }
+    if let Some(context) = arguments.get("context").and_then(|c| c.as_str()) {
+        command.env("PROJECT_PILOT_CONTEXT", context);
+    }
+    if let Some(plugin) = arguments.get("plugin").and_then(|p| p.as_str()) {
+        command.env("PROJECT_PILOT_PLUGIN", plugin);
+    }
+    if let Some(branch) = arguments.get("branch").and_then(|b| b.as_str()) {
+        command.env("PROJECT_PILOT_BRANCH", branch);
+    }
+    if let Some(previous) = arguments.get("previous-branch").and_then(|b| b.as_str()) {
+        command.env("PROJECT_PILOT_PREVIOUS_BRANCH", previous);
+    }
+    if let Some(action) = arguments.get("action").and_then(|a| a.as_str()) {
+        command.env("PROJECT_PILOT_ACTION", action);
+    }
+    if let Some(message) = arguments.get("message").and_then(|m| m.as_str()) {
+        command.env("PROJECT_PILOT_MESSAGE", message);
+    }
+    if let Some(duration) = arguments.get("duration-ms").and_then(|d| d.as_u64()) {
+        command.env("PROJECT_PILOT_DURATION_MS", duration.to_string());
+    }
+
+    let mut child = command.spawn().map_err(|e| e.to_string())?;
+    // not joined: something started in background by the hook may keep the pipes open
+    log_output(hook.key.clone(), child.stdout.take().unwrap(), false);
+    log_output(hook.key.clone(), child.stderr.take().unwrap(), true);
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {:?}", timeout));
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("exited with {}", status))
+    }
+}
+
+impl Plugin for HooksPlugin {
+    fn name(&self) -> String {
+        "hooks".to_string()
+    }
+    // the hooks run one after the other in the background, the daemon doesn't wait for them
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let hooks = hooks_for(project, event, arguments);
+        if hooks.is_empty() {
+            return;
+        }
+        let job = Job {
+            hooks,
+            event,
+            project: project.clone(),
+            arguments: arguments.clone(),
+            timeout: timeout(project, arguments),
+        };
+        if self.queue.send(job).is_err() {
+            eprintln!("hooks of {} not run, their worker stopped", project.name);
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![ActionDescriptor::new(
+            "list",
+            "list the project hooks, global ones included, in the order they run",
+        )
+        .needs_project()]
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "run shell commands when events happen".to_string(),
+            events: <Event as strum::IntoEnumIterator>::iter()
+                .map(|e| e.to_string())
+                .collect(),
+            properties: vec![
+                format!("{}on_<event>[.<priority>]", PREFIX),
+                format!("{}timeout", PREFIX),
+            ],
+            arguments: vec![GLOBAL_HOOKS.to_string()],
+            ..PluginInfo::default()
+        }
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        vec![check_command(
+            "shell",
+            "sh",
+            &["-c", "echo sh found"],
+            "hooks run with `sh -c`, install a POSIX shell",
+        )]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        match action {
+            "list" => {
+                let mut lines = Vec::new();
+                for event in <Event as strum::IntoEnumIterator>::iter() {
+                    for hook in hooks_for(project, event, arguments) {
+                        lines.push(format!(
+                            "{} ({}): {}",
+                            hook.key, hook.priority, hook.command
+                        ));
+                    }
+                }
+                Ok(lines.join("\n"))
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::wait_for_lines;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn project(properties: &[(&str, &str)]) -> Project {
+        let mut project = Project {
+            name: "app".to_string(),
+            ..Project::default()
+        };
+        for (key, value) in properties {
+            project.properties.insert(key.to_string(), value.to_string());
+        }
+        project
+    }
+
+    fn global(properties: Value) -> Map<String, Value> {
+        json!({ "global-properties": properties }).as_object().unwrap().clone()
+    }
+
+    fn keys(hooks: &[Hook]) -> Vec<&str> {
+        hooks.iter().map(|hook| hook.key.as_str()).collect()
+    }
+
+    #[test]
+    fn hooks_are_found_with_their_priority() {
+        let properties: HashMap<String, String> = [
+            ("hooks.on_project_switch", "a"),
+            ("hooks.on_project_switch.10", "b"),
+            ("hooks.on_project_switch.late", "not a priority"),
+            ("hooks.on_project_switch_x", "another name"),
+            ("hooks.on_project_leave", "another event"),
+            ("hooks.timeout", "5"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let mut hooks = find_hooks(&properties, Event::ProjectSwitch);
+        hooks.sort_by_key(|hook| hook.priority);
+        let found: Vec<(&str, i64, &str)> = hooks
+            .iter()
+            .map(|h| (h.key.as_str(), h.priority, h.command.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("hooks.on_project_switch.10", 10, "b"),
+                ("hooks.on_project_switch", DEFAULT_PRIORITY, "a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn global_hooks_run_first_then_by_priority() {
+        let project = project(&[
+            ("hooks.on_project_switch", "project"),
+            ("hooks.on_project_switch.5", "project early"),
+        ]);
+        let arguments = global(json!({
+            "hooks.on_project_switch": "global",
+            "hooks.on_project_switch.90": "global late",
+        }));
+        let hooks = hooks_for(&project, Event::ProjectSwitch, &arguments);
+        assert_eq!(
+            keys(&hooks),
+            [
+                "hooks.on_project_switch.5",
+                "hooks.on_project_switch",
+                "hooks.on_project_switch",
+                "hooks.on_project_switch.90",
+            ]
+        );
+        let commands: Vec<&str> = hooks.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, ["project early", "global", "project", "global late"]);
+
+        let mut without_global = arguments.clone();
+        without_global.insert(GLOBAL_HOOKS.to_string(), json!(false));
+        let hooks = hooks_for(&project, Event::ProjectSwitch, &without_global);
+        let commands: Vec<&str> = hooks.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, ["project early", "project"]);
+    }
+
+    #[test]
+    fn timeout_is_a_property() {
+        assert_eq!(timeout(&project(&[]), &Map::new()), Duration::from_secs(DEFAULT_TIMEOUT));
+        let arguments = global(json!({"hooks.timeout": "7"}));
+        assert_eq!(timeout(&project(&[]), &arguments), Duration::from_secs(7));
+        let project = project(&[("hooks.timeout", "2")]);
+        assert_eq!(timeout(&project, &arguments), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn slow_hooks_are_killed() {
+        let hook = Hook {
+            key: "hooks.on_project_switch".to_string(),
+            priority: DEFAULT_PRIORITY,
+            command: "sleep 10".to_string(),
+        };
+        let started = Instant::now();
+        let result = run_hook(
+            &hook,
+            Event::ProjectSwitch,
+            &project(&[]),
+            &Map::new(),
+            Duration::from_millis(200),
+        );
+        assert!(result.unwrap_err().starts_with("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn hooks_of_successive_events_run_one_after_the_other() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("hooks.log");
+        let plugin = HooksPlugin::new();
+        let switch = format!(
+            "echo switch start >> {0}; sleep 0.3; echo switch end >> {0}",
+            log.display()
+        );
+        let leave = format!("echo $PROJECT_PILOT_EVENT >> {}", log.display());
+        let mut project = project(&[
+            ("hooks.on_project_switch", &switch),
+            ("hooks.on_project_leave", &leave),
+        ]);
+        plugin.on_event(Event::ProjectSwitch, &mut project, &Map::new());
+        plugin.on_event(Event::ProjectLeave, &mut project, &Map::new());
+        let lines = wait_for_lines(&log, |lines| lines.len() == 3);
+        assert_eq!(lines, ["switch start", "switch end", "project-leave"]);
+    }
+}
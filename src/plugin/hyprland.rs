@@ -1,6 +1,6 @@
 use crate::config::Project;
 use crate::event::Event;
-use crate::plugin::Plugin;
+use crate::plugin::{ActionDescriptor, Plugin};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::boxed::Box;
@@ -63,11 +63,11 @@ impl Plugin for HyprlandPlugin {
             _ => {}
         }
     }
-    fn list_actions(&self) -> Vec<String> {
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
         // vec!["gen_init_terminal".to_string()]
         vec![]
     }
-    fn run_action(&self, action: &str, project: &mut Project, arguments: &Map<String,Value>) -> Result<String,String> {
+    fn run_action(&self, action: &str, project: Option<&mut Project>, arguments: &Map<String,Value>) -> Result<String,String> {
         match action {
             // "gen_init_terminal" => {
             //     let session_name = project.name.clone();
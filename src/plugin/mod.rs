@@ -4,33 +4,366 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::boxed::Box;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
 
 pub trait Plugin: Send + Sync {
     fn name(&self) -> String;
     fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String,Value>);
-    fn list_actions(&self) -> Vec<String>;
-    fn run_action(&self, action: &str, project: &mut Project, arguments: &Map<String,Value>) -> Result<String,String>;
+    fn list_actions(&self) -> Vec<ActionDescriptor>;
+    fn info(&self) -> PluginInfo {
+        PluginInfo::default()
+    }
+    // project is given when the action descriptor needs it
+    fn run_action(&self, action: &str, project: Option<&mut Project>, arguments: &Map<String,Value>) -> Result<String,String>;
+    // a short line shown by `project info` for projects using this plugin, arguments hold the
+    // global properties, as "global-properties"
+    fn status(&self, project: &Project, arguments: &Map<String,Value>) -> Option<String> {
+        None
+    }
+    // compare the real world with the configuration, called on a schedule and on demand.
+    // receives all the projects and returns a report, one entry per problem found
+    fn reconcile(&self, projects: &mut [Project]) -> Vec<String> {
+        Vec::new()
+    }
+    // checks run by `project-pilot doctor`: binaries, versions, environment, services
+    fn health_check(&self) -> Vec<HealthCheck> {
+        Vec::new()
+    }
+    // called at registration, for plugins that want to send requests to the daemon
+    fn set_request_sender(&mut self, sender: RequestSender) {}
+    // receives the `[plugins.<name>]` table of the configuration, already checked against
+    // the `config` of the plugin info, at registration and whenever it changes
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// the global properties the daemon passes to the plugins as "global-properties"
+pub fn global_properties(arguments: &Map<String, Value>) -> HashMap<String, String> {
+    arguments
+        .get("global-properties")
+        .and_then(|p| serde_json::from_value(p.clone()).ok())
+        .unwrap_or_default()
+}
+
+// global properties, overridden by the project ones
+pub fn properties(project: &Project, arguments: &Map<String, Value>) -> HashMap<String, String> {
+    let mut properties = global_properties(arguments);
+    properties.extend(project.properties.clone());
+    properties
+}
+
+// what `plugin list` shows about a plugin, besides its actions
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PluginInfo {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    // the events the plugin reacts to, by their kebab-case name
+    #[serde(default)]
+    pub events: Vec<String>,
+    // the project and global properties it reads
+    #[serde(default)]
+    pub properties: Vec<String>,
+    // plugins enabled along with this one, they receive the events first
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    // plugins that receive the events first when both are enabled
+    #[serde(default)]
+    pub after: Vec<String>,
+    // the settings accepted in the `[plugins.<name>]` table
+    #[serde(default)]
+    pub config: Vec<ParamDescriptor>,
+    // the event arguments it reads besides the ones of the events, like "global-hooks"
+    #[serde(default)]
+    pub arguments: Vec<String>,
+}
+
+// a request sent by a plugin, handled by the daemon like the ones coming from the cli
+#[derive(Debug, Clone)]
+pub struct DaemonRequest {
+    pub subject: String,
+    pub command: String,
+    pub params: Map<String, Value>,
+}
+
+impl DaemonRequest {
+    pub fn new(subject: &str, command: &str, params: Map<String, Value>) -> Self {
+        DaemonRequest {
+            subject: subject.to_string(),
+            command: command.to_string(),
+            params,
+        }
+    }
 }
 
+pub type RequestSender = UnboundedSender<DaemonRequest>;
+
 pub struct PluginFactory {
-    map: HashMap<String, Mutex<Box<dyn Plugin>>>
+    // shared, so that a long action can run once the factory is unlocked
+    map: HashMap<String, Arc<Mutex<Box<dyn Plugin>>>>,
+    // where the plugins that are not built in come from
+    sources: HashMap<String, PathBuf>,
+    // asked once at registration, the ordering is needed for every event
+    infos: HashMap<String, PluginInfo>,
+    requests: RequestSender,
 }
 impl PluginFactory {
-    pub fn new() -> PluginFactory {
+    pub fn new(requests: RequestSender) -> PluginFactory {
         PluginFactory {
-            map: HashMap::new()
+            map: HashMap::new(),
+            sources: HashMap::new(),
+            infos: HashMap::new(),
+            requests,
         }
     }
     pub async fn register_plugin(&mut self, plugin: Mutex<Box<dyn Plugin>>) {
-        let name = plugin.lock().await.name().clone();
-        self.map.insert(name, plugin);
+        let (name, info) = {
+            let mut plugin = plugin.lock().await;
+            plugin.set_request_sender(self.requests.clone());
+            (plugin.name(), plugin.info())
+        };
+        self.infos.insert(name.clone(), info);
+        self.map.insert(name, Arc::new(plugin));
+    }
+    // loads a shared library plugin, refusing to shadow an already registered plugin
+    pub async fn load_library_plugin(&mut self, path: &Path) -> Result<String, String> {
+        let plugin = dylib::load(path)?;
+        let name = plugin.name();
+        if self.map.contains_key(&name) {
+            return Err(format!("plugin {} is already registered", name));
+        }
+        self.register_plugin(Mutex::new(Box::new(plugin))).await;
+        self.sources.insert(name.clone(), path.to_path_buf());
+        Ok(name)
+    }
+    pub async fn discover_library_plugins(&mut self, dir: &Path) {
+        for path in dylib::find_libraries(dir) {
+            match self.load_library_plugin(&path).await {
+                Ok(name) => println!("library plugin {} loaded from {}", name, path.display()),
+                Err(e) => eprintln!("library plugin {} not loaded: {}", path.display(), e),
+            }
+        }
+    }
+    pub async fn discover_script_plugins(&mut self, dir: &Path) {
+        for path in script::find_scripts(dir) {
+            match script::ScriptPlugin::load(&path) {
+                Ok(plugin) if self.map.contains_key(&plugin.name()) => {
+                    eprintln!(
+                        "script plugin {} ignored, plugin {} is already registered",
+                        path.display(),
+                        plugin.name()
+                    );
+                }
+                Ok(plugin) => {
+                    let name = plugin.name();
+                    println!("script plugin {} loaded from {}", name, path.display());
+                    self.register_plugin(Mutex::new(Box::new(plugin))).await;
+                    self.sources.insert(name, path);
+                }
+                Err(e) => eprintln!("script plugin {} not loaded: {}", path.display(), e),
+            }
+        }
     }
-    pub fn get_plugin(&self, name: &str) -> Option<&Mutex<Box<dyn Plugin>>> {
+    // only plugins loaded from a file can be removed, the built in ones stay
+    pub fn unregister_plugin(&mut self, name: &str) -> Result<(), String> {
+        if !self.map.contains_key(name) {
+            return Err(format!("plugin {} not found", name));
+        }
+        if self.sources.remove(name).is_none() {
+            return Err(format!("plugin {} is built in", name));
+        }
+        self.map.remove(name);
+        self.infos.remove(name);
+        Ok(())
+    }
+    pub fn get_plugin(&self, name: &str) -> Option<&Arc<Mutex<Box<dyn Plugin>>>> {
         self.map.get(name)
     }
+    // registers the external plugins found in the given directories, skipping the ones
+    // that fail to start or clash with an already registered plugin
+    pub async fn discover_external_plugins(&mut self, dirs: &[PathBuf], timeout: Duration) {
+        for path in external::find_executables(dirs) {
+            match external::start(&path, timeout) {
+                Ok(plugin) if self.map.contains_key(&plugin.name()) => {
+                    eprintln!(
+                        "external plugin {} ignored, plugin {} is already registered",
+                        path.display(),
+                        plugin.name()
+                    );
+                }
+                Ok(plugin) => {
+                    let name = plugin.name();
+                    println!("external plugin {} loaded from {}", name, path.display());
+                    self.register_plugin(Mutex::new(Box::new(plugin))).await;
+                    self.sources.insert(name, path);
+                }
+                Err(e) => eprintln!("external plugin {} not loaded: {}", path.display(), e),
+            }
+        }
+    }
+    pub async fn configure_plugin(&self, name: &str, config: &Map<String, Value>) -> Result<(), String> {
+        let plugin = self
+            .map
+            .get(name)
+            .ok_or(format!("plugin {} not found", name))?;
+        let settings = self
+            .infos
+            .get(name)
+            .map(|info| info.config.clone())
+            .unwrap_or_default();
+        action::validate_params(&settings, config)
+            .map_err(|e| format!("invalid configuration for plugin {}: {}", name, e))?;
+        plugin.lock().await.configure(config)
+    }
+    pub fn plugin_info(&self, name: &str) -> Option<&PluginInfo> {
+        self.infos.get(name)
+    }
+    // all the plugins needed by the given one, directly or not
+    pub fn dependencies(&self, name: &str) -> Result<Vec<String>, String> {
+        let mut found: Vec<String> = Vec::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(current) = pending.pop() {
+            let info = match self.infos.get(&current) {
+                Some(info) => info,
+                None => continue,
+            };
+            for dependency in &info.dependencies {
+                if !self.map.contains_key(dependency) {
+                    return Err(format!(
+                        "plugin {} needed by {} not found",
+                        dependency, current
+                    ));
+                }
+                if dependency != name && !found.contains(dependency) {
+                    found.push(dependency.clone());
+                    pending.push(dependency.clone());
+                }
+            }
+        }
+        Ok(found)
+    }
+    // the given plugins sorted so that each one comes after its dependencies and the plugins
+    // it wants to follow, otherwise keeping their order. cycles are reported and broken
+    pub fn dispatch_order(&self, names: &[String]) -> Vec<String> {
+        let mut pending: Vec<String> = Vec::new();
+        for name in names {
+            if !pending.contains(name) {
+                pending.push(name.clone());
+            }
+        }
+        let mut sorted = Vec::new();
+        while !pending.is_empty() {
+            let waits_for = |name: &String| -> bool {
+                self.infos
+                    .get(name)
+                    .map(|info| {
+                        info.dependencies
+                            .iter()
+                            .chain(info.after.iter())
+                            .any(|before| before != name && pending.contains(before))
+                    })
+                    .unwrap_or(false)
+            };
+            let next = match pending.iter().position(|name| !waits_for(name)) {
+                Some(next) => next,
+                None => {
+                    eprintln!("plugin ordering cycle between {}", pending.join(", "));
+                    0
+                }
+            };
+            sorted.push(pending.remove(next));
+        }
+        sorted
+    }
+    // the file a plugin was loaded from, None for the built in ones
+    pub fn plugin_source(&self, name: &str) -> Option<&PathBuf> {
+        self.sources.get(name)
+    }
+    pub fn plugin_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.map.keys().cloned().collect();
+        names.sort();
+        names
+    }
 }
 
+pub mod action;
+pub mod health;
+pub use crate::plugin::action::{ActionDescriptor, ParamDescriptor, ParamKind};
+pub use crate::plugin::health::{HealthCheck, HealthReport};
+
 // export plugins
+pub mod compose;
+pub mod dylib;
+pub mod editor;
+pub mod env;
+pub mod external;
+pub mod git;
+pub mod git_client;
+pub mod hooks;
+pub mod notify;
+pub mod rpc;
+pub mod script;
+pub mod services;
+pub mod sway;
+pub mod sway_client;
+pub mod terminal;
 pub mod tmux;
+pub mod tmux_client;
+pub use crate::plugin::compose::ComposePlugin;
+pub use crate::plugin::editor::EditorPlugin;
+pub use crate::plugin::env::EnvPlugin;
+pub use crate::plugin::external::ExternalPlugin;
+pub use crate::plugin::git::GitPlugin;
+pub use crate::plugin::hooks::HooksPlugin;
+pub use crate::plugin::notify::NotifyPlugin;
+pub use crate::plugin::script::ScriptPlugin;
+pub use crate::plugin::services::ServicesPlugin;
+pub use crate::plugin::sway::SwayPlugin;
+pub use crate::plugin::terminal::TerminalPlugin;
 pub use crate::plugin::tmux::TmuxPlugin;
+
+#[cfg(test)]
+pub mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    // an executable shell script standing for a real command
+    pub fn fake_command(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    // whether condition held within 5s, for what happens in the background
+    pub fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    // the lines of a file written in the background, once condition holds or after 5s
+    pub fn wait_for_lines(path: &Path, condition: impl Fn(&[String]) -> bool) -> Vec<String> {
+        let read = || -> Vec<String> {
+            std::fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(String::from)
+                .collect()
+        };
+        wait_until(|| condition(&read()));
+        read()
+    }
+}
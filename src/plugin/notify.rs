@@ -0,0 +1,559 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::health::{check_command, check_env};
+use crate::plugin::{
+    ActionDescriptor, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// desktop notifications for some events, sent to org.freedesktop.Notifications on the session
+// bus, or with notify-send when there is no bus.
+// each event has a summary and a body template, where `{name}` is replaced by the event
+// argument with that name, `{project}` by the project and `{duration}` by the seconds an action
+// took, and an urgency. They are set in `[plugins.notify]` as `<event>-summary`, `<event>-body`
+// and `<event>-urgency`
+const DEFAULT_MIN_DURATION: i64 = 10;
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const SETTINGS: [&str; 3] = ["summary", "body", "urgency"];
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    // the values of the urgency hint
+    fn level(&self) -> u8 {
+        match self {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Critical => 2,
+        }
+    }
+}
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "kebab-case")]
+enum Backend {
+    Auto,
+    Dbus,
+    NotifySend,
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    summary: String,
+    body: String,
+    urgency: Urgency,
+}
+
+#[derive(Debug, Clone)]
+struct Notification {
+    summary: String,
+    body: String,
+    urgency: Urgency,
+}
+
+fn default_templates() -> HashMap<Event, Template> {
+    let template = |summary: &str, body: &str, urgency| Template {
+        summary: summary.to_string(),
+        body: body.to_string(),
+        urgency,
+    };
+    HashMap::from([
+        (
+            Event::ProjectSwitch,
+            template(
+                "{project}",
+                "switched from {previous-project}",
+                Urgency::Low,
+            ),
+        ),
+        (
+            Event::PluginError,
+            template("{plugin} failed", "{action}: {message}", Urgency::Critical),
+        ),
+        (
+            Event::ActionComplete,
+            template(
+                "{plugin} {action} done",
+                "{project}, in {duration}s",
+                Urgency::Normal,
+            ),
+        ),
+    ])
+}
+
+// replaces `{name}` with the argument, unknown names with nothing
+fn render(template: &str, values: &HashMap<String, String>) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let name = &rest[start + 1..start + end];
+                rendered.push_str(values.get(name).map(|v| v.as_str()).unwrap_or(""));
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                rendered.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[derive(Debug, Clone)]
+struct Sender {
+    backend: Backend,
+    // the session bus address, DBUS_SESSION_BUS_ADDRESS otherwise
+    bus: Option<String>,
+    notify_send: String,
+    expire_timeout: i32,
+}
+
+impl Sender {
+    fn connect(&self) -> Result<zbus::blocking::Connection, String> {
+        let connection = match &self.bus {
+            Some(address) => zbus::blocking::connection::Builder::address(address.as_str())
+                .and_then(|b| b.build()),
+            None => zbus::blocking::Connection::session(),
+        };
+        connection.map_err(|e| format!("cannot connect to the session bus: {}", e))
+    }
+
+    fn send_dbus(&self, notification: &Notification) -> Result<u32, String> {
+        let connection = self.connect()?;
+        let hints = HashMap::from([(
+            "urgency",
+            zbus::zvariant::Value::U8(notification.urgency.level()),
+        )]);
+        let reply = connection
+            .call_method(
+                Some(NOTIFICATIONS),
+                NOTIFICATIONS_PATH,
+                Some(NOTIFICATIONS),
+                "Notify",
+                &(
+                    "project-pilot",
+                    0u32,
+                    "",
+                    notification.summary.as_str(),
+                    notification.body.as_str(),
+                    Vec::<&str>::new(),
+                    hints,
+                    self.expire_timeout,
+                ),
+            )
+            .map_err(|e| format!("notification not sent: {}", e))?;
+        reply
+            .body()
+            .deserialize::<u32>()
+            .map_err(|e| format!("invalid notification reply: {}", e))
+    }
+
+    fn send_notify_send(&self, notification: &Notification) -> Result<(), String> {
+        let mut command = Command::new(&self.notify_send);
+        command
+            .args(["--app-name", "project-pilot", "--urgency"])
+            .arg(notification.urgency.to_string());
+        if self.expire_timeout >= 0 {
+            command
+                .arg("--expire-time")
+                .arg(self.expire_timeout.to_string());
+        }
+        let output = command
+            .arg("--")
+            .arg(&notification.summary)
+            .arg(&notification.body)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", self.notify_send, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                self.notify_send,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn send(&self, notification: &Notification) -> Result<String, String> {
+        match self.backend {
+            Backend::Dbus => self
+                .send_dbus(notification)
+                .map(|id| format!("notification {} sent", id)),
+            Backend::NotifySend => self
+                .send_notify_send(notification)
+                .map(|_| format!("notification sent with {}", self.notify_send)),
+            Backend::Auto => match self.send_dbus(notification) {
+                Ok(id) => Ok(format!("notification {} sent", id)),
+                Err(dbus_error) => self
+                    .send_notify_send(notification)
+                    .map(|_| format!("notification sent with {}", self.notify_send))
+                    .map_err(|e| format!("{}, and {}", dbus_error, e)),
+            },
+        }
+    }
+
+    // does a notification server own its name on the bus
+    fn check_dbus(&self) -> Result<(), String> {
+        let connection = self.connect()?;
+        let proxy = zbus::blocking::fdo::DBusProxy::new(&connection)
+            .map_err(|e| format!("cannot query the session bus: {}", e))?;
+        let name = zbus::names::BusName::try_from(NOTIFICATIONS).unwrap();
+        match proxy.name_has_owner(name) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("nobody owns {} on the session bus", NOTIFICATIONS)),
+            Err(e) => Err(format!("cannot query the session bus: {}", e)),
+        }
+    }
+}
+
+pub struct NotifyPlugin {
+    templates: HashMap<Event, Template>,
+    min_duration: i64,
+    sender: Arc<Sender>,
+}
+
+impl NotifyPlugin {
+    pub fn new() -> Self {
+        NotifyPlugin {
+            templates: default_templates(),
+            min_duration: DEFAULT_MIN_DURATION,
+            sender: Arc::new(Sender {
+                backend: Backend::Auto,
+                bus: None,
+                notify_send: "notify-send".to_string(),
+                expire_timeout: -1,
+            }),
+        }
+    }
+
+    fn notification(
+        &self,
+        event: Event,
+        project: &Project,
+        arguments: &Map<String, Value>,
+    ) -> Option<Notification> {
+        let template = self.templates.get(&event)?;
+        let mut values: HashMap<String, String> = arguments
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    Value::Number(_) | Value::Bool(_) => value.to_string(),
+                    _ => return None,
+                };
+                Some((key.clone(), value))
+            })
+            .collect();
+        values.insert("project".to_string(), project.name.clone());
+        values.insert("event".to_string(), event.to_string());
+        if let Some(duration) = arguments.get("duration-ms").and_then(|d| d.as_u64()) {
+            values.insert(
+                "duration".to_string(),
+                format!("{:.1}", duration as f64 / 1000.0),
+            );
+        }
+        // the first switch has no project to tell about
+        let body = if template.body.contains("{previous-project}")
+            && !values.contains_key("previous-project")
+        {
+            String::new()
+        } else {
+            render(&template.body, &values)
+        };
+        Some(Notification {
+            summary: render(&template.summary, &values),
+            body,
+            urgency: template.urgency,
+        })
+    }
+
+    // the session bus can be slow to answer, the daemon does not wait for it
+    fn send_in_background(&self, notification: Notification) {
+        let sender = Arc::clone(&self.sender);
+        std::thread::spawn(move || {
+            if let Err(e) = sender.send(&notification) {
+                eprintln!("notify plugin: {}", e);
+            }
+        });
+    }
+}
+
+impl Plugin for NotifyPlugin {
+    fn name(&self) -> String {
+        "notify".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        if event == Event::ActionComplete {
+            // failures are notified as plugin errors, quick actions are not worth it
+            let success = arguments.get("success").and_then(|s| s.as_bool()) == Some(true);
+            let duration = arguments
+                .get("duration-ms")
+                .and_then(|d| d.as_i64())
+                .unwrap_or(0);
+            if !success || duration < self.min_duration * 1000 {
+                return;
+            }
+        }
+        if let Some(notification) = self.notification(event, project, arguments) {
+            self.send_in_background(notification);
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        let mut config: Vec<ParamDescriptor> = default_templates()
+            .keys()
+            .flat_map(|event| {
+                SETTINGS.iter().map(move |setting| {
+                    let description = match *setting {
+                        "urgency" => format!("low, normal or critical, for {}", event),
+                        _ => format!(
+                            "the {} template for {}, empty to turn it off",
+                            setting, event
+                        ),
+                    };
+                    ParamDescriptor::named(&format!("{}-{}", event, setting), &description)
+                })
+            })
+            .collect();
+        config.sort_by(|a, b| a.name.cmp(&b.name));
+        config.extend([
+            ParamDescriptor::named(
+                "min-duration",
+                "the seconds an action takes before its completion is notified, 10 by default",
+            )
+            .kind(ParamKind::Integer)
+            .min(0),
+            ParamDescriptor::named("backend", "auto (the default), dbus or notify-send"),
+            ParamDescriptor::named(
+                "bus",
+                "the session bus address, DBUS_SESSION_BUS_ADDRESS otherwise",
+            ),
+            ParamDescriptor::named("notify-send", "the notify-send binary"),
+            ParamDescriptor::named(
+                "expire-timeout",
+                "milliseconds before notifications expire, the server decides by default",
+            )
+            .kind(ParamKind::Integer),
+        ]);
+        let mut events: Vec<String> = default_templates().keys().map(|e| e.to_string()).collect();
+        events.sort();
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description:
+                "desktop notifications for project switches, plugin errors and long actions"
+                    .to_string(),
+            events,
+            config,
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![ActionDescriptor::new(
+            "test",
+            "send the notification of an event, with made up arguments",
+        )
+        .needs_project()
+        .param(ParamDescriptor::positional(
+            "event",
+            "project-switch, plugin-error or action-complete, project-switch if omitted",
+        ))]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        match action {
+            "test" => {
+                let event = action_arg(arguments, "event")
+                    .and_then(|e| e.as_str())
+                    .unwrap_or("project-switch");
+                let event =
+                    Event::from_str(event).map_err(|_| format!("unknown event {}", event))?;
+                let sample = serde_json::json!({
+                    "previous-project": "previous",
+                    "plugin": "example",
+                    "action": "build",
+                    "message": "something went wrong",
+                    "duration-ms": 42000,
+                    "success": true,
+                });
+                let notification = self
+                    .notification(event, project, sample.as_object().unwrap())
+                    .ok_or(format!("no notification for {}", event))?;
+                self.sender.send(&notification)
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let dbus = self.sender.check_dbus();
+        let notify_send = || {
+            check_command(
+                "notify-send",
+                &self.sender.notify_send,
+                &["--version"],
+                "install libnotify, or set its path with `plugin set-config notify notify-send <path>`",
+            )
+        };
+        let dbus_check = |result: Result<(), String>| {
+            match result {
+            Ok(_) => HealthCheck::ok("dbus", &format!("{} is available", NOTIFICATIONS)),
+            Err(e) => HealthCheck::failed(
+                "dbus",
+                &e,
+                "start a notification daemon like mako or dunst, or set `plugin set-config notify backend notify-send`",
+            ),
+        }
+        };
+        match self.sender.backend {
+            Backend::Dbus if self.sender.bus.is_none() => vec![
+                check_env(
+                    "DBUS_SESSION_BUS_ADDRESS",
+                    "start the daemon from the graphical session, or set `plugin set-config notify bus <address>`",
+                ),
+                dbus_check(dbus),
+            ],
+            Backend::Dbus => vec![dbus_check(dbus)],
+            Backend::NotifySend => vec![notify_send()],
+            // one of them is enough
+            Backend::Auto => match dbus {
+                Ok(_) => vec![dbus_check(dbus)],
+                Err(e) => {
+                    let mut check = notify_send();
+                    check.message = format!("{}, falling back to it: {}", e, check.message);
+                    vec![check]
+                }
+            },
+        }
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        let setting = |name: &str| config.get(name).and_then(|v| v.as_str());
+        let mut templates = default_templates();
+        for (event, template) in templates.iter_mut() {
+            if let Some(summary) = setting(&format!("{}-summary", event)) {
+                template.summary = summary.to_string();
+            }
+            if let Some(body) = setting(&format!("{}-body", event)) {
+                template.body = body.to_string();
+            }
+            if let Some(urgency) = setting(&format!("{}-urgency", event)) {
+                template.urgency = Urgency::from_str(urgency).map_err(|_| {
+                    format!("unknown urgency {}, use low, normal or critical", urgency)
+                })?;
+            }
+        }
+        // an empty summary turns the notification off
+        templates.retain(|_, template| !template.summary.is_empty());
+        let backend = match setting("backend") {
+            Some(backend) => Backend::from_str(backend).map_err(|_| {
+                format!("unknown backend {}, use auto, dbus or notify-send", backend)
+            })?,
+            None => Backend::Auto,
+        };
+        self.templates = templates;
+        self.min_duration = config
+            .get("min-duration")
+            .and_then(|d| d.as_i64())
+            .unwrap_or(DEFAULT_MIN_DURATION);
+        self.sender = Arc::new(Sender {
+            backend,
+            bus: setting("bus").map(String::from),
+            notify_send: setting("notify-send").unwrap_or("notify-send").to_string(),
+            expire_timeout: config
+                .get("expire-timeout")
+                .and_then(|t| t.as_i64())
+                .unwrap_or(-1) as i32,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn values() -> HashMap<String, String> {
+        HashMap::from([("project".to_string(), "web".to_string())])
+    }
+
+    #[test]
+    fn render_replaces_known_names() {
+        assert_eq!(render("{project} switched", &values()), "web switched");
+        assert_eq!(render("{project}{project}", &values()), "webweb");
+    }
+
+    #[test]
+    fn render_drops_unknown_names() {
+        assert_eq!(render("from {previous-project}.", &values()), "from .");
+        assert_eq!(render("{}", &values()), "");
+    }
+
+    #[test]
+    fn render_keeps_unclosed_braces() {
+        assert_eq!(render("{project} {project", &values()), "web {project");
+        assert_eq!(render("{ {project}", &values()), "");
+    }
+
+    #[test]
+    fn empty_summary_turns_a_notification_off() {
+        let mut plugin = NotifyPlugin::new();
+        let config = json!({"project-switch-summary": "", "plugin-error-body": "{message}"});
+        plugin.configure(config.as_object().unwrap()).unwrap();
+        assert!(!plugin.templates.contains_key(&Event::ProjectSwitch));
+        assert_eq!(plugin.templates[&Event::PluginError].body, "{message}");
+        assert!(plugin.templates.contains_key(&Event::ActionComplete));
+
+        let project = Project {
+            name: "web".to_string(),
+            ..Project::default()
+        };
+        assert!(plugin.notification(Event::ProjectSwitch, &project, &Map::new()).is_none());
+    }
+
+    #[test]
+    fn unknown_urgency_is_refused() {
+        let mut plugin = NotifyPlugin::new();
+        let config = json!({"project-switch-urgency": "urgent"});
+        assert!(plugin.configure(config.as_object().unwrap()).is_err());
+    }
+
+    #[test]
+    fn first_switch_has_no_body() {
+        let plugin = NotifyPlugin::new();
+        let project = Project {
+            name: "web".to_string(),
+            ..Project::default()
+        };
+        let notification = plugin
+            .notification(Event::ProjectSwitch, &project, &Map::new())
+            .unwrap();
+        assert_eq!(notification.summary, "web");
+        assert_eq!(notification.body, "");
+
+        let arguments = json!({"previous-project": "api"});
+        let notification = plugin
+            .notification(Event::ProjectSwitch, &project, arguments.as_object().unwrap())
+            .unwrap();
+        assert_eq!(notification.body, "switched from api");
+    }
+}
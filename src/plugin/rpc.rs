@@ -0,0 +1,165 @@
+// Plugins living outside the daemon code are reached through a channel exchanging json:
+// a method name and its params, answered by a result or an error message.
+//
+// methods, mirroring the Plugin trait:
+//   name                                        -> string
+//   on_event    {event, project, arguments}     -> null or {"project": project}
+//   list_actions                                -> [action descriptor]
+//   info (optional)                             -> {version, description, events, ...}
+//   configure   {config}                        -> null, also sent with an empty config and
+//                                                  again when the plugin is restarted
+//   health_check (optional)                     -> [{name, ok, message, fix}]
+//   run_action  {action, project, arguments}    -> {"output": string, "project"?: project}
+//
+// when a project is returned it replaces the one given, so plugins can change properties.
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::{ActionDescriptor, HealthCheck, Plugin, PluginInfo};
+use serde_json::{json, Map, Value};
+
+pub trait RpcChannel: Send + Sync {
+    fn call(&self, method: &str, params: Value) -> Result<Value, String>;
+}
+
+pub struct RpcPlugin<C: RpcChannel> {
+    name: String,
+    channel: C,
+}
+
+impl<C: RpcChannel> RpcPlugin<C> {
+    // asks the plugin for its name, failing if it does not answer properly
+    pub fn new(channel: C) -> Result<Self, String> {
+        let name = match channel.call("name", json!({}))? {
+            Value::String(name) if !name.is_empty() => name,
+            other => return Err(format!("invalid plugin name: {}", other)),
+        };
+        Ok(RpcPlugin { name, channel })
+    }
+
+    fn update_project(project: &mut Project, result: &Value) {
+        if let Some(updated) = result.get("project") {
+            match serde_json::from_value::<Project>(updated.clone()) {
+                Ok(updated) => *project = updated,
+                Err(e) => eprintln!("invalid project returned by plugin: {}", e),
+            }
+        }
+    }
+}
+
+impl<C: RpcChannel> Plugin for RpcPlugin<C> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let params =
+            json!({"event": event.to_string(), "project": project, "arguments": arguments});
+        match self.channel.call("on_event", params) {
+            Ok(result) => RpcPlugin::<C>::update_project(project, &result),
+            Err(e) => eprintln!("plugin {}: {}", self.name, e),
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        match self
+            .channel
+            .call("list_actions", json!({}))
+            .and_then(|result| serde_json::from_value(result).map_err(|e| e.to_string()))
+        {
+            Ok(actions) => actions,
+            Err(e) => {
+                eprintln!("plugin {}: {}", self.name, e);
+                Vec::new()
+            }
+        }
+    }
+    // plugins are not required to answer, they are listed without details then
+    fn info(&self) -> PluginInfo {
+        self.channel
+            .call("info", json!({}))
+            .and_then(|result| serde_json::from_value(result).map_err(|e| e.to_string()))
+            .unwrap_or_default()
+    }
+    // a plugin that doesn't answer at all is reported, its own checks are optional
+    fn health_check(&self) -> Vec<HealthCheck> {
+        if let Err(e) = self.channel.call("name", json!({})) {
+            return vec![HealthCheck::failed(
+                "responding",
+                &e,
+                "check the plugin output in the daemon log",
+            )];
+        }
+        let mut checks = vec![HealthCheck::ok("responding", "answers to requests")];
+        if let Ok(result) = self.channel.call("health_check", json!({})) {
+            match serde_json::from_value::<Vec<HealthCheck>>(result) {
+                Ok(own) => checks.extend(own),
+                Err(e) => eprintln!("plugin {}: invalid health checks: {}", self.name, e),
+            }
+        }
+        checks
+    }
+    // sent even when empty, so that removed settings are dropped too. Plugins without settings
+    // don't have to answer it
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        match self.channel.call("configure", json!({ "config": config })) {
+            Err(_) if config.is_empty() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let params = json!({"action": action, "project": project, "arguments": arguments});
+        let result = self.channel.call("run_action", params)?;
+        if let Some(project) = project {
+            RpcPlugin::<C>::update_project(project, &result);
+        }
+        Ok(result
+            .get("output")
+            .and_then(|o| o.as_str())
+            .unwrap_or("")
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // answers its name, fails the other methods, and remembers the calls
+    struct RecordingChannel {
+        calls: Mutex<Vec<(String, Value)>>,
+    }
+
+    impl RpcChannel for RecordingChannel {
+        fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+            self.calls.lock().unwrap().push((method.to_string(), params));
+            match method {
+                "name" => Ok(json!("recording")),
+                _ => Err("method not found".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn configure_is_sent_even_when_empty() {
+        let channel = RecordingChannel {
+            calls: Mutex::new(vec![]),
+        };
+        let mut plugin = RpcPlugin::new(channel).unwrap();
+        assert_eq!(plugin.configure(&Map::new()), Ok(()));
+        let mut config = Map::new();
+        config.insert("key".to_string(), json!("value"));
+        assert_eq!(plugin.configure(&config), Err("method not found".to_string()));
+        let calls = plugin.channel.calls.lock().unwrap();
+        assert_eq!(
+            calls[1..],
+            [
+                ("configure".to_string(), json!({"config": {}})),
+                ("configure".to_string(), json!({"config": {"key": "value"}})),
+            ]
+        );
+    }
+}
@@ -0,0 +1,383 @@
+// Plugins written in Rhai, one per `<name>.rhai` file in the scripts directory. A script can
+// define any of these functions, `this` being the project:
+//
+//   fn on_event(event, args) { ... }        // changes to `this` are saved
+//   fn actions() { [#{name: "open", description: "...", needs_project: true}] }
+//   fn run_action(action, args) { ... }     // returns the output, `this` is () without project
+//   fn info() { #{version: "1.0", description: "...", events: ["project-switch"], properties: []} }
+//   fn health_check() { [#{name: "binary", ok: false, message: "...", fix: "..."}] }
+//
+// scripts can't import modules and their number of operations is limited. `run` and `shell`
+// execute anything the daemon user can, they fail unless `allow-commands` is set in the
+// `[plugins.<name>]` table of the script. The api:
+//
+//   run(program, [args])   shell(command)     -> #{code, stdout, stderr}
+//   switch_project(name)   send_event(event)  send_event(event, project)   log(message)
+//   config()               -> the `[plugins.<name>]` table
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::{
+    ActionDescriptor, DaemonRequest, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+    RequestSender,
+};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{
+    Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map as RhaiMap, Scope, AST,
+};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+const MAX_OPERATIONS: u64 = 10_000_000;
+const ALLOW_COMMANDS: &str = "allow-commands";
+
+type SharedSender = Arc<Mutex<Option<RequestSender>>>;
+type SharedConfig = Arc<Mutex<Map<String, Value>>>;
+
+pub struct ScriptPlugin {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    requests: SharedSender,
+    config: SharedConfig,
+}
+
+fn send_request(requests: &SharedSender, subject: &str, command: &str, params: Value) {
+    let params = params.as_object().cloned().unwrap_or_default();
+    match requests.lock().unwrap().as_ref() {
+        Some(sender) => {
+            let _ = sender.send(DaemonRequest::new(subject, command, params));
+        }
+        None => eprintln!("script request {} {} dropped, no daemon", subject, command),
+    }
+}
+
+fn command_result(output: std::io::Result<std::process::Output>) -> RhaiMap {
+    let mut result = RhaiMap::new();
+    match output {
+        Ok(output) => {
+            result.insert("code".into(), (output.status.code().unwrap_or(-1) as i64).into());
+            result.insert(
+                "stdout".into(),
+                String::from_utf8_lossy(&output.stdout).to_string().into(),
+            );
+            result.insert(
+                "stderr".into(),
+                String::from_utf8_lossy(&output.stderr).to_string().into(),
+            );
+        }
+        Err(e) => {
+            result.insert("code".into(), (-1_i64).into());
+            result.insert("stdout".into(), "".into());
+            result.insert("stderr".into(), e.to_string().into());
+        }
+    }
+    result
+}
+
+fn limited_engine(name: &str, requests: &SharedSender, config: &SharedConfig) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(64)
+        .set_max_string_size(1 << 20)
+        .set_max_array_size(100_000)
+        .set_max_map_size(10_000);
+    engine.disable_symbol("eval");
+
+    let log_name = name.to_string();
+    engine.on_print(move |message| println!("script {}: {}", log_name, message));
+    let log_name = name.to_string();
+    engine.register_fn("log", move |message: &str| {
+        println!("script {}: {}", log_name, message)
+    });
+
+    let allowed = {
+        let config = Arc::clone(config);
+        let name = name.to_string();
+        move |function: &str| -> Result<(), Box<EvalAltResult>> {
+            match config.lock().unwrap().get(ALLOW_COMMANDS).and_then(|a| a.as_bool()) {
+                Some(true) => Ok(()),
+                _ => Err(format!(
+                    "{} is not allowed, enable it with `plugin set-config {} {} true`",
+                    function, name, ALLOW_COMMANDS
+                )
+                .into()),
+            }
+        }
+    };
+    let allowed_run = allowed.clone();
+    engine.register_fn("run", move |program: &str, args: Array| {
+        allowed_run("run")?;
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Ok::<_, Box<EvalAltResult>>(command_result(Command::new(program).args(args).output()))
+    });
+    engine.register_fn("shell", move |command: &str| {
+        allowed("shell")?;
+        Ok::<_, Box<EvalAltResult>>(command_result(
+            Command::new("sh").arg("-c").arg(command).output(),
+        ))
+    });
+
+    let config = Arc::clone(config);
+    engine.register_fn("config", move || {
+        to_dynamic(&*config.lock().unwrap()).unwrap_or_default()
+    });
+
+    let sender = Arc::clone(requests);
+    engine.register_fn("switch_project", move |project: &str| {
+        send_request(&sender, "project", "switch", json!({"project-name": project}));
+    });
+    let sender = Arc::clone(requests);
+    engine.register_fn("send_event", move |event: &str| {
+        send_request(&sender, "event", "trigger", json!({"event-name": event}));
+    });
+    let sender = Arc::clone(requests);
+    engine.register_fn("send_event", move |event: &str, project: &str| {
+        send_request(
+            &sender,
+            "event",
+            "trigger",
+            json!({"event-name": event, "project-name": project}),
+        );
+    });
+    engine
+}
+
+impl ScriptPlugin {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .ok_or(format!("invalid script name {}", path.display()))?;
+        let requests: SharedSender = Arc::new(Mutex::new(None));
+        let config: SharedConfig = Arc::new(Mutex::new(Map::new()));
+        let engine = limited_engine(&name, &requests, &config);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(ScriptPlugin {
+            name,
+            engine,
+            ast,
+            requests,
+            config,
+        })
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == function)
+    }
+
+    // top level statements are not evaluated, scripts are made of functions
+    fn call(
+        &self,
+        function: &str,
+        this: &mut Dynamic,
+        args: impl FuncArgs,
+    ) -> Result<Dynamic, String> {
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, function, args)
+            .map_err(|e| format!("script {}: {}", self.name, e))
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        if !self.defines("on_event") {
+            return;
+        }
+        let result = to_dynamic(&*project)
+            .and_then(|this| Ok((this, to_dynamic(arguments)?)))
+            .map_err(|e| e.to_string())
+            .and_then(|(mut this, args)| {
+                let _ = self.call("on_event", &mut this, (event.to_string(), args))?;
+                from_dynamic::<Project>(&this).map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(updated) => *project = updated,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        if !self.defines("actions") {
+            return Vec::new();
+        }
+        match self
+            .call("actions", &mut Dynamic::from(()), ())
+            .and_then(|actions| from_dynamic(&actions).map_err(|e| e.to_string()))
+        {
+            Ok(actions) => actions,
+            Err(e) => {
+                eprintln!("script {}: invalid actions: {}", self.name, e);
+                Vec::new()
+            }
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        let mut info = if self.defines("info") {
+            match self
+                .call("info", &mut Dynamic::from(()), ())
+                .and_then(|info| from_dynamic(&info).map_err(|e| e.to_string()))
+            {
+                Ok(info) => info,
+                Err(e) => {
+                    eprintln!("script {}: invalid info: {}", self.name, e);
+                    PluginInfo::default()
+                }
+            }
+        } else {
+            PluginInfo::default()
+        };
+        // every script has this setting, commands are refused without it
+        if !info.config.iter().any(|setting| setting.name == ALLOW_COMMANDS) {
+            info.config.push(
+                ParamDescriptor::named(ALLOW_COMMANDS, "let `run` and `shell` execute commands")
+                    .kind(ParamKind::Bool),
+            );
+        }
+        info
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        if !self.defines("health_check") {
+            return Vec::new();
+        }
+        match self
+            .call("health_check", &mut Dynamic::from(()), ())
+            .and_then(|checks| from_dynamic(&checks).map_err(|e| e.to_string()))
+        {
+            Ok(checks) => checks,
+            Err(e) => vec![HealthCheck::failed(
+                "script",
+                &e,
+                "fix the health_check function of the script",
+            )],
+        }
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        if !self.defines("run_action") {
+            return Err(format!("unknown action: {}", action));
+        }
+        let mut this = match &project {
+            Some(project) => to_dynamic(&**project).map_err(|e| e.to_string())?,
+            None => Dynamic::UNIT,
+        };
+        let args = to_dynamic(arguments).map_err(|e| e.to_string())?;
+        let output = self.call("run_action", &mut this, (action.to_string(), args))?;
+        if let Some(project) = project {
+            *project = from_dynamic(&this).map_err(|e| e.to_string())?;
+        }
+        Ok(if output.is_unit() {
+            String::new()
+        } else {
+            output.to_string()
+        })
+    }
+    fn set_request_sender(&mut self, sender: RequestSender) {
+        *self.requests.lock().unwrap() = Some(sender);
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        *self.config.lock().unwrap() = config.clone();
+        Ok(())
+    }
+}
+
+// the .rhai files in the scripts directory
+pub fn find_scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map(|e| e == "rhai").unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SCRIPT: &str = r#"
+        fn on_event(event, args) {
+            this.properties["last"] = event + " " + args["previous-project"];
+        }
+        fn run_action(action, args) {
+            switch action {
+                "rename" => { this.properties["renamed"] = args["to"]; "renamed" }
+                "loop" => { loop {} }
+                "shell" => { let output = shell("echo hi").stdout; output.trim(); output }
+                _ => throw "unknown action " + action,
+            }
+        }
+    "#;
+
+    fn load(dir: &TempDir) -> ScriptPlugin {
+        let path = dir.path().join("demo.rhai");
+        std::fs::write(&path, SCRIPT).unwrap();
+        ScriptPlugin::load(&path).unwrap()
+    }
+
+    fn project() -> Project {
+        Project {
+            name: "web".to_string(),
+            ..Project::default()
+        }
+    }
+
+    fn arguments(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn events_and_actions_change_the_project() {
+        let dir = TempDir::new().unwrap();
+        let plugin = load(&dir);
+        assert_eq!(plugin.name(), "demo");
+        let mut project = project();
+        let switched = arguments(json!({"previous-project": "api"}));
+        plugin.on_event(Event::ProjectSwitch, &mut project, &switched);
+        assert_eq!(project.properties["last"], "project-switch api");
+
+        let rename = arguments(json!({"to": "site"}));
+        let output = plugin.run_action("rename", Some(&mut project), &rename);
+        assert_eq!(output, Ok("renamed".to_string()));
+        assert_eq!(project.properties["renamed"], "site");
+        assert!(plugin.run_action("other", Some(&mut project), &Map::new()).is_err());
+    }
+
+    #[test]
+    fn operations_are_limited() {
+        let dir = TempDir::new().unwrap();
+        let plugin = load(&dir);
+        let error = plugin.run_action("loop", Some(&mut project()), &Map::new()).unwrap_err();
+        assert!(error.contains("Too many operations"), "{}", error);
+    }
+
+    #[test]
+    fn commands_need_to_be_allowed() {
+        let dir = TempDir::new().unwrap();
+        let mut plugin = load(&dir);
+        let error = plugin.run_action("shell", None, &Map::new()).unwrap_err();
+        assert!(error.contains("shell is not allowed"), "{}", error);
+        assert!(plugin.info().config.iter().any(|s| s.name == ALLOW_COMMANDS));
+
+        plugin.configure(&arguments(json!({ALLOW_COMMANDS: true}))).unwrap();
+        assert_eq!(plugin.run_action("shell", None, &Map::new()), Ok("hi".to_string()));
+    }
+}
@@ -0,0 +1,873 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::env::project_environment;
+use crate::plugin::health::check_command;
+use crate::plugin::{
+    ActionDescriptor, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// a project declares its services in properties:
+//   services.<name>          the shell command, run in the project `path`
+//   services.<name>.restart  always, on-failure (the default) or never
+// their output goes to <log-dir>/<project>/<name>.log
+const PREFIX: &str = "services.";
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// a stopped service gets this long to exit before being killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+// restarts wait longer and longer, back to the minimum once a run lasted long enough
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const BACKOFF_RESET: Duration = Duration::from_secs(30);
+const DEFAULT_LOG_LINES: i64 = 50;
+
+// the process groups of the running services, for `terminate_all` when the daemon is
+// interrupted and plugins are not dropped
+static RUNNING_GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq, Default)]
+#[strum(serialize_all = "kebab-case")]
+enum Restart {
+    Always,
+    #[default]
+    OnFailure,
+    Never,
+}
+
+impl Restart {
+    fn should_restart(&self, status: &ExitStatus) -> bool {
+        match self {
+            Restart::Always => true,
+            Restart::OnFailure => !status.success(),
+            Restart::Never => false,
+        }
+    }
+}
+
+// a service as declared by the project
+#[derive(Debug, Clone, PartialEq)]
+struct ServiceSpec {
+    project: String,
+    name: String,
+    command: String,
+    directory: Option<PathBuf>,
+    environment: Vec<(String, String)>,
+    restart: Restart,
+    log: PathBuf,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct ServiceStatus {
+    name: String,
+    command: String,
+    // running, restarting, stopped, exited or crashed
+    state: String,
+    pid: Option<u32>,
+    restarts: u32,
+    last_exit: Option<i32>,
+    log: PathBuf,
+}
+
+impl ServiceStatus {
+    fn line(&self) -> String {
+        let mut line = format!("{}: {}", self.name, self.state);
+        if let Some(pid) = self.pid {
+            line = format!("{} (pid {})", line, pid);
+        }
+        if let Some(code) = self.last_exit {
+            line = format!("{}, last exit code {}", line, code);
+        }
+        if self.restarts > 0 {
+            line = format!("{}, {} restarts", line, self.restarts);
+        }
+        line
+    }
+}
+
+// a supervised service, its thread owns the child process
+struct Service {
+    // what it was started with, a changed declaration restarts it
+    spec: ServiceSpec,
+    status: Arc<Mutex<ServiceStatus>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Service {
+    fn start(spec: ServiceSpec) -> Self {
+        let status = Arc::new(Mutex::new(ServiceStatus {
+            name: spec.name.clone(),
+            command: spec.command.clone(),
+            state: "running".to_string(),
+            pid: None,
+            restarts: 0,
+            last_exit: None,
+            log: spec.log.clone(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let spec = spec.clone();
+            let status = Arc::clone(&status);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || supervise(spec, status, stop))
+        };
+        Service {
+            spec,
+            status,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    // asks the supervisor to stop, joining it is left to the caller
+    fn signal_stop(&mut self) -> Option<JoinHandle<()>> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.take()
+    }
+
+    fn status(&self) -> ServiceStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn spawn(spec: &ServiceSpec) -> Result<Child, String> {
+    if let Some(parent) = spec.log.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&spec.log)
+        .map_err(|e| format!("cannot open {}: {}", spec.log.display(), e))?;
+    let _ = writeln!(log, "--- {} started at {}", spec.command, timestamp());
+    let stderr = log
+        .try_clone()
+        .map_err(|e| format!("cannot open {}: {}", spec.log.display(), e))?;
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&spec.command)
+        .envs(spec.environment.iter().cloned())
+        .env("PROJECT_PILOT_PROJECT", &spec.project)
+        .env("PROJECT_PILOT_SERVICE", &spec.name)
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(stderr)
+        // its own process group, so that stopping it reaches the children of the shell
+        .process_group(0);
+    if let Some(directory) = &spec.directory {
+        command.current_dir(directory);
+    }
+    command
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", spec.command, e))
+}
+
+pub fn signal_group(group: u32, signal: &str) {
+    let _ = Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg("--")
+        .arg(format!("-{}", group))
+        .stderr(Stdio::null())
+        .status();
+}
+
+// stops every running service without waiting for them
+pub fn terminate_all() {
+    for group in RUNNING_GROUPS.lock().unwrap().iter() {
+        signal_group(*group, "TERM");
+    }
+}
+
+fn terminate(child: &mut Child) {
+    signal_group(child.id(), "TERM");
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    signal_group(child.id(), "KILL");
+    let _ = child.wait();
+}
+
+// the wait before a restart, doubling after each quick exit, back to the minimum after the
+// first one or a run long enough
+fn restart_delay(previous: Option<Duration>, run: Duration) -> Duration {
+    match previous {
+        Some(previous) if run <= BACKOFF_RESET => (previous * 2).min(MAX_BACKOFF),
+        _ => MIN_BACKOFF,
+    }
+}
+
+// sleeps in small steps, returning early when the service is stopped
+fn wait_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline && !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// runs the service until it is stopped or its restart policy gives up
+fn supervise(spec: ServiceSpec, status: Arc<Mutex<ServiceStatus>>, stop: Arc<AtomicBool>) {
+    let set_state = |state: &str, pid: Option<u32>| {
+        let mut status = status.lock().unwrap();
+        status.state = state.to_string();
+        status.pid = pid;
+    };
+    let mut delay = None;
+    while !stop.load(Ordering::SeqCst) {
+        let started = Instant::now();
+        let exit = match spawn(&spec) {
+            Ok(mut child) => {
+                set_state("running", Some(child.id()));
+                RUNNING_GROUPS.lock().unwrap().insert(child.id());
+                let exit = loop {
+                    if stop.load(Ordering::SeqCst) {
+                        terminate(&mut child);
+                        break None;
+                    }
+                    match child.try_wait() {
+                        Ok(Some(exit)) => break Some(exit),
+                        Ok(None) => std::thread::sleep(POLL_INTERVAL),
+                        Err(e) => {
+                            eprintln!("service {} of project {}: {}", spec.name, spec.project, e);
+                            terminate(&mut child);
+                            break None;
+                        }
+                    }
+                };
+                RUNNING_GROUPS.lock().unwrap().remove(&child.id());
+                exit
+            }
+            Err(e) => {
+                eprintln!("service {} of project {}: {}", spec.name, spec.project, e);
+                set_state("crashed", None);
+                break;
+            }
+        };
+        let exit = match exit {
+            Some(exit) => exit,
+            None => break,
+        };
+        status.lock().unwrap().last_exit = exit.code();
+        if !spec.restart.should_restart(&exit) {
+            set_state(if exit.success() { "exited" } else { "crashed" }, None);
+            return;
+        }
+        let wait = restart_delay(delay, started.elapsed());
+        delay = Some(wait);
+        println!(
+            "service {} of project {} exited, restarting in {}s",
+            spec.name,
+            spec.project,
+            wait.as_secs()
+        );
+        set_state("restarting", None);
+        status.lock().unwrap().restarts += 1;
+        wait_unless_stopped(wait, &stop);
+    }
+    if stop.load(Ordering::SeqCst) {
+        set_state("stopped", None);
+    }
+}
+
+// the last lines of a file
+fn tail(path: &Path, lines: usize) -> Result<String, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let all: Vec<&str> = content.lines().collect();
+    Ok(all[all.len().saturating_sub(lines)..].join("\n"))
+}
+
+pub struct ServicesPlugin {
+    log_dir: PathBuf,
+    // by project, then by service name
+    services: Mutex<BTreeMap<String, BTreeMap<String, Service>>>,
+}
+
+impl ServicesPlugin {
+    pub fn new() -> Self {
+        ServicesPlugin {
+            log_dir: default_log_dir(),
+            services: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // the declared services with the environment they would start with, finding it can run
+    // `direnv export json`
+    fn specs(&self, project: &Project, arguments: &Map<String, Value>) -> Vec<ServiceSpec> {
+        with_environment(self.declared(project), project, arguments)
+    }
+
+    // the declared services, without their environment
+    fn declared(&self, project: &Project) -> Vec<ServiceSpec> {
+        let mut specs: Vec<ServiceSpec> = project
+            .properties
+            .iter()
+            .filter_map(|(key, command)| {
+                let name = key.strip_prefix(PREFIX)?;
+                if name.is_empty() || name.contains('.') {
+                    return None;
+                }
+                let restart_key = format!("{}{}.restart", PREFIX, name);
+                let restart = match project.properties.get(&restart_key) {
+                    Some(restart) => Restart::from_str(restart).unwrap_or_else(|_| {
+                        eprintln!(
+                            "project {}: invalid {} {}, using {}",
+                            project.name,
+                            restart_key,
+                            restart,
+                            Restart::default()
+                        );
+                        Restart::default()
+                    }),
+                    None => Restart::default(),
+                };
+                Some(ServiceSpec {
+                    project: project.name.clone(),
+                    name: name.to_string(),
+                    command: command.clone(),
+                    directory: project.properties.get("path").map(PathBuf::from),
+                    environment: Vec::new(),
+                    restart,
+                    log: self.log_dir.join(&project.name).join(format!("{}.log", name)),
+                })
+            })
+            .collect();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        specs
+    }
+
+    // the declared services, or the one asked for
+    fn selected(
+        &self,
+        project: &Project,
+        service: Option<&str>,
+        arguments: &Map<String, Value>,
+    ) -> Result<Vec<ServiceSpec>, String> {
+        let specs = self.specs(project, arguments);
+        match service {
+            Some(service) => match specs.into_iter().find(|s| s.name == service) {
+                Some(spec) => Ok(vec![spec]),
+                None => Err(format!(
+                    "service {} not found for project {}, declare it with the {}{} property",
+                    service, project.name, PREFIX, service
+                )),
+            },
+            None => Ok(specs),
+        }
+    }
+
+    fn start(&self, specs: Vec<ServiceSpec>) -> Vec<String> {
+        let mut services = self.services.lock().unwrap();
+        specs
+            .into_iter()
+            .map(|spec| {
+                let project = services.entry(spec.project.clone()).or_default();
+                match project.get(&spec.name) {
+                    Some(service) if service.is_active() => {
+                        format!("service {} already running", spec.name)
+                    }
+                    _ => {
+                        let name = spec.name.clone();
+                        project.insert(name.clone(), Service::start(spec));
+                        format!("service {} started", name)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // the services stop together, each can take up to STOP_TIMEOUT, and they are waited for
+    // once the services are unlocked
+    fn stop(&self, project: &str, names: &[String]) -> Vec<String> {
+        let (report, handles): (Vec<String>, Vec<JoinHandle<()>>) = {
+            let mut services = self.services.lock().unwrap();
+            let project = match services.get_mut(project) {
+                Some(project) => project,
+                None => return Vec::new(),
+            };
+            names
+                .iter()
+                .filter_map(|name| {
+                    let service = project.get_mut(name)?;
+                    if !service.is_active() {
+                        return None;
+                    }
+                    let handle = service.signal_stop()?;
+                    Some((format!("service {} stopped", name), handle))
+                })
+                .unzip()
+        };
+        for handle in handles {
+            let _ = handle.join();
+        }
+        report
+    }
+
+    fn stop_all(&self, project: &str) -> Vec<String> {
+        let names: Vec<String> = match self.services.lock().unwrap().get(project) {
+            Some(services) => services.keys().cloned().collect(),
+            None => return Vec::new(),
+        };
+        self.stop(project, &names)
+    }
+
+    // starts the declared services never started, forgets the ones not declared anymore and,
+    // with restart_changed, restarts the ones whose declaration changed
+    fn sync(
+        &self,
+        project: &Project,
+        arguments: &Map<String, Value>,
+        restart_changed: bool,
+    ) -> Vec<String> {
+        // the environment is only needed to compare the declarations, or to start new services
+        let specs = if restart_changed {
+            self.specs(project, arguments)
+        } else {
+            self.declared(project)
+        };
+        let known: BTreeMap<String, ServiceSpec> = self
+            .services
+            .lock()
+            .unwrap()
+            .get(&project.name)
+            .map(|services| {
+                services
+                    .iter()
+                    .map(|(name, service)| (name.clone(), service.spec.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let removed: Vec<String> = known
+            .keys()
+            .filter(|name| !specs.iter().any(|s| &&s.name == name))
+            .cloned()
+            .collect();
+        let changed: Vec<ServiceSpec> = specs
+            .iter()
+            .filter(|spec| restart_changed && known.get(&spec.name).is_some_and(|k| k != *spec))
+            .cloned()
+            .collect();
+        let changed_names: Vec<String> = changed.iter().map(|s| s.name.clone()).collect();
+        let mut report = self.stop(&project.name, &removed);
+        self.stop(&project.name, &changed_names);
+        if let Some(services) = self.services.lock().unwrap().get_mut(&project.name) {
+            services.retain(|name, _| !removed.contains(name) && !changed_names.contains(name));
+        }
+        self.start(changed);
+        report.extend(
+            changed_names
+                .iter()
+                .map(|name| format!("service {} restarted, its declaration changed", name)),
+        );
+        let new: Vec<ServiceSpec> = specs
+            .into_iter()
+            .filter(|s| !known.contains_key(&s.name))
+            .collect();
+        let new = if restart_changed { new } else { with_environment(new, project, arguments) };
+        report.extend(self.start(new));
+        report
+    }
+
+    // the started services report their own status, the environment is not needed
+    fn statuses(&self, project: &Project) -> Vec<ServiceStatus> {
+        let services = self.services.lock().unwrap();
+        let running = services.get(&project.name);
+        self.declared(project)
+            .into_iter()
+            .map(|spec| match running.and_then(|r| r.get(&spec.name)) {
+                Some(service) => service.status(),
+                None => ServiceStatus {
+                    name: spec.name,
+                    command: spec.command,
+                    state: "stopped".to_string(),
+                    pid: None,
+                    restarts: 0,
+                    last_exit: None,
+                    log: spec.log,
+                },
+            })
+            .collect()
+    }
+}
+
+// the environment of the project, from the env plugin when it is enabled
+fn with_environment(
+    mut specs: Vec<ServiceSpec>,
+    project: &Project,
+    arguments: &Map<String, Value>,
+) -> Vec<ServiceSpec> {
+    if specs.is_empty() || !project.plugins.iter().any(|p| p == "env") {
+        return specs;
+    }
+    let environment: Vec<(String, String)> = match project_environment(project, None, arguments) {
+        Ok(environment) => environment.into_iter().collect(),
+        Err(e) => {
+            eprintln!("environment of project {} not loaded: {}", project.name, e);
+            return specs;
+        }
+    };
+    for spec in specs.iter_mut() {
+        spec.environment = environment.clone();
+    }
+    specs
+}
+
+fn default_log_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".cache/project-pilot/services")
+}
+
+fn service_param() -> ParamDescriptor {
+    ParamDescriptor::positional("service", "only this service, all of them if omitted")
+}
+
+impl Drop for ServicesPlugin {
+    // the services don't outlive the daemon
+    fn drop(&mut self) {
+        let projects: Vec<String> = self.services.lock().unwrap().keys().cloned().collect();
+        for project in projects {
+            self.stop_all(&project);
+        }
+    }
+}
+
+impl Plugin for ServicesPlugin {
+    fn name(&self) -> String {
+        "services".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let report = match event {
+            Event::PluginEnable => self.start(self.specs(project, arguments)),
+            Event::ProjectUpdate => self.sync(project, arguments, true),
+            Event::PluginDisable => {
+                let report = self.stop_all(&project.name);
+                self.services.lock().unwrap().remove(&project.name);
+                report
+            }
+            _ => Vec::new(),
+        };
+        for line in report {
+            println!("project {}: {}", project.name, line);
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "runs and restarts the background commands of a project".to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectUpdate.to_string(),
+            ],
+            properties: vec![
+                format!("{}<NAME>", PREFIX),
+                format!("{}<NAME>.restart", PREFIX),
+                "path".to_string(),
+            ],
+            after: vec!["env".to_string()],
+            config: vec![ParamDescriptor::named(
+                "log-dir",
+                "where the service logs are written, ~/.cache/project-pilot/services by default",
+            )],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new("start", "start the services")
+                .needs_project()
+                .param(service_param()),
+            ActionDescriptor::new("stop", "stop the services")
+                .needs_project()
+                .param(service_param()),
+            ActionDescriptor::new("restart", "restart the services")
+                .needs_project()
+                .param(service_param()),
+            ActionDescriptor::new("status", "show the state of the services")
+                .needs_project()
+                .param(ParamDescriptor::named("json", "print it as json").kind(ParamKind::Bool)),
+            ActionDescriptor::new("logs", "print the last lines of the log of a service")
+                .needs_project()
+                .param(ParamDescriptor::positional("service", "the service").required())
+                .param(
+                    ParamDescriptor::named("lines", "how many lines")
+                        .kind(ParamKind::Integer)
+                        .min(0),
+                ),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        let service = action_arg(arguments, "service").and_then(|s| s.as_str());
+        let report = match action {
+            "start" => self.start(self.selected(project, service, arguments)?),
+            "stop" => {
+                let names: Vec<String> = self
+                    .selected(project, service, arguments)?
+                    .into_iter()
+                    .map(|s| s.name)
+                    .collect();
+                self.stop(&project.name, &names)
+            }
+            "restart" => {
+                let specs = self.selected(project, service, arguments)?;
+                let names: Vec<String> = specs.iter().map(|s| s.name.clone()).collect();
+                self.stop(&project.name, &names);
+                self.start(specs)
+            }
+            "status" => {
+                let statuses = self.statuses(project);
+                if action_arg(arguments, "json").and_then(|j| j.as_bool()) == Some(true) {
+                    return Ok(serde_json::to_string(&statuses).unwrap());
+                }
+                if statuses.is_empty() {
+                    return Ok(format!("no services declared for project {}", project.name));
+                }
+                return Ok(statuses
+                    .iter()
+                    .map(|s| s.line())
+                    .collect::<Vec<String>>()
+                    .join("\n"));
+            }
+            "logs" => {
+                let spec = self
+                    .selected(project, service, arguments)?
+                    .pop()
+                    .ok_or("a service is needed".to_string())?;
+                let lines = action_arg(arguments, "lines")
+                    .and_then(|l| l.as_i64())
+                    .unwrap_or(DEFAULT_LOG_LINES);
+                return tail(&spec.log, lines.max(0) as usize);
+            }
+            _ => return Err(format!("unknown action: {}", action)),
+        };
+        if report.is_empty() {
+            return Ok(format!("no services changed for project {}", project.name));
+        }
+        Ok(report.join("\n"))
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let statuses = self.statuses(project);
+        if statuses.is_empty() {
+            return Some(format!("no {}<NAME> properties", PREFIX));
+        }
+        let running = statuses.iter().filter(|s| s.state == "running").count();
+        let mut status = format!("{}/{} running", running, statuses.len());
+        let others: Vec<String> = statuses
+            .iter()
+            .filter(|s| s.state != "running")
+            .map(|s| format!("{} {}", s.name, s.state))
+            .collect();
+        if !others.is_empty() {
+            status = format!("{}, {}", status, others.join(", "));
+        }
+        Some(status)
+    }
+    // starts the services of the enabled projects that were never started, after a daemon
+    // restart, and reports the crashed ones
+    fn reconcile(&self, projects: &mut [Project]) -> Vec<String> {
+        let mut report = Vec::new();
+        let enabled: Vec<&Project> = projects
+            .iter()
+            .filter(|p| p.plugins.contains(&"services".to_string()))
+            .collect();
+        // without the global properties the environment may differ, nothing is restarted
+        for project in &enabled {
+            for line in self.sync(project, &Map::new(), false) {
+                report.push(format!("project {}: {}", project.name, line));
+            }
+            for status in self.statuses(project) {
+                if status.state == "crashed" {
+                    report.push(format!(
+                        "service {} of project {} crashed, see `project-pilot plugin run services logs {} {}`",
+                        status.name, project.name, project.name, status.name
+                    ));
+                }
+            }
+        }
+        let gone: Vec<String> = self
+            .services
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|name| !enabled.iter().any(|p| &&p.name == name))
+            .cloned()
+            .collect();
+        for project in gone {
+            for line in self.stop_all(&project) {
+                report.push(format!("project {}: {}", project, line));
+            }
+            self.services.lock().unwrap().remove(&project);
+        }
+        report
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let shell = check_command("shell", "sh", &["-c", "echo sh found"], "install a posix sh");
+        let log_dir = match std::fs::create_dir_all(&self.log_dir) {
+            Ok(_) => HealthCheck::ok("log-dir", &self.log_dir.to_string_lossy()),
+            Err(e) => HealthCheck::failed(
+                "log-dir",
+                &format!("cannot create {}: {}", self.log_dir.display(), e),
+                "set another directory with `plugin set-config services log-dir <dir>`",
+            ),
+        };
+        vec![shell, log_dir]
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        self.log_dir = match config.get("log-dir").and_then(|d| d.as_str()) {
+            Some(dir) => PathBuf::from(dir),
+            None => default_log_dir(),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::wait_until;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn plugin(dir: &TempDir) -> ServicesPlugin {
+        let mut plugin = ServicesPlugin::new();
+        let config = json!({"log-dir": dir.path().display().to_string()});
+        plugin.configure(config.as_object().unwrap()).unwrap();
+        plugin
+    }
+
+    fn project(services: &[(&str, &str)]) -> Project {
+        let mut project = Project {
+            name: "web".to_string(),
+            plugins: vec!["services".to_string()],
+            ..Project::default()
+        };
+        for (key, value) in services {
+            project.properties.insert(format!("{}{}", PREFIX, key), value.to_string());
+        }
+        project
+    }
+
+    fn status(plugin: &ServicesPlugin, project: &Project, name: &str) -> ServiceStatus {
+        plugin.statuses(project).into_iter().find(|s| s.name == name).unwrap()
+    }
+
+    #[test]
+    fn restart_policies() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir);
+        let mut project = project(&[
+            ("done", "exit 0"),
+            ("failed", "exit 3"),
+            ("never", "exit 3"),
+            ("never.restart", "never"),
+            ("always", "exit 0"),
+            ("always.restart", "always"),
+        ]);
+        plugin.on_event(Event::PluginEnable, &mut project, &Map::new());
+
+        assert!(wait_until(|| status(&plugin, &project, "done").state == "exited"));
+        assert!(wait_until(|| status(&plugin, &project, "never").state == "crashed"));
+        let never = status(&plugin, &project, "never");
+        assert_eq!((never.last_exit, never.restarts), (Some(3), 0));
+        assert!(wait_until(|| status(&plugin, &project, "failed").restarts >= 1));
+        assert!(wait_until(|| status(&plugin, &project, "always").restarts >= 1));
+        assert_eq!(status(&plugin, &project, "done").restarts, 0);
+
+        plugin.on_event(Event::PluginDisable, &mut project, &Map::new());
+        assert_eq!(status(&plugin, &project, "failed").state, "stopped");
+    }
+
+    #[test]
+    fn restarts_back_off() {
+        let quick = Duration::from_secs(1);
+        assert_eq!(restart_delay(None, quick), MIN_BACKOFF);
+        assert_eq!(restart_delay(Some(MIN_BACKOFF), quick), MIN_BACKOFF * 2);
+        assert_eq!(restart_delay(Some(Duration::from_secs(40)), quick), MAX_BACKOFF);
+        let long = BACKOFF_RESET + quick;
+        assert_eq!(restart_delay(Some(Duration::from_secs(40)), long), MIN_BACKOFF);
+    }
+
+    #[test]
+    fn output_goes_to_the_log() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir);
+        let mut project = project(&[
+            ("talk", "echo hello $PROJECT_PILOT_SERVICE; echo oops >&2"),
+            ("talk.restart", "never"),
+        ]);
+        plugin.on_event(Event::PluginEnable, &mut project, &Map::new());
+        assert!(wait_until(|| status(&plugin, &project, "talk").state == "exited"));
+        assert_eq!(
+            status(&plugin, &project, "talk").log,
+            dir.path().join("web").join("talk.log")
+        );
+        let arguments = json!({"args": {"service": "talk"}});
+        let log = plugin
+            .run_action("logs", Some(&mut project), arguments.as_object().unwrap())
+            .unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines[0].starts_with("--- echo hello"), "{}", log);
+        assert_eq!(lines[1..], ["hello talk", "oops"]);
+    }
+
+    #[test]
+    fn project_update_restarts_the_changed_services() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir);
+        let mut project = project(&[("a", "exec sleep 30"), ("b", "exec sleep 30")]);
+        plugin.on_event(Event::PluginEnable, &mut project, &Map::new());
+        let pid = |name: &str| status(&plugin, &project, name).pid;
+        assert!(wait_until(|| pid("a").is_some() && pid("b").is_some()));
+        let (a, b) = (pid("a"), pid("b"));
+
+        project.properties.insert("services.a".to_string(), "exec sleep 31".to_string());
+        project.properties.insert("services.c".to_string(), "exec sleep 30".to_string());
+        let report = plugin.sync(&project, &Map::new(), true);
+        assert_eq!(
+            report,
+            ["service a restarted, its declaration changed", "service c started"]
+        );
+        let pid = |name: &str| status(&plugin, &project, name).pid;
+        assert!(wait_until(|| pid("a").is_some_and(|pid| Some(pid) != a)));
+        assert_eq!(pid("b"), b);
+
+        project.properties.remove("services.b");
+        assert_eq!(plugin.sync(&project, &Map::new(), true), ["service b stopped"]);
+        plugin.on_event(Event::PluginDisable, &mut project, &Map::new());
+    }
+}
@@ -0,0 +1,409 @@
+use crate::config::Project;
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::health::check_env;
+use crate::plugin::sway_client::{
+    quote, read_message, set_poll_interval, SwayClient, WORKSPACE_EVENT,
+};
+use crate::plugin::{
+    ActionDescriptor, DaemonRequest, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo,
+    RequestSender,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// a workspace per project in sway or i3, named after the project or its `sway.workspace`
+// property. Focusing the workspace of a project makes it the current one, and windows marked
+// `project:<name>` are moved to the workspace of their project
+const PREFIX: &str = "sway.";
+const MARK_PREFIX: &str = "project:";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct SwayPlugin {
+    client: Arc<Mutex<SwayClient>>,
+    // the workspace of each enabled project
+    workspaces: Arc<Mutex<HashMap<String, String>>>,
+    current: Arc<Mutex<Option<String>>>,
+    follow_focus: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl SwayPlugin {
+    pub fn new() -> Self {
+        SwayPlugin {
+            client: Arc::new(Mutex::new(SwayClient::new())),
+            workspaces: Arc::new(Mutex::new(HashMap::new())),
+            current: Arc::new(Mutex::new(None)),
+            follow_focus: Arc::new(AtomicBool::new(true)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn client(&self) -> SwayClient {
+        self.client.lock().unwrap().clone()
+    }
+
+    fn track(&self, project: &Project) {
+        self.workspaces
+            .lock()
+            .unwrap()
+            .retain(|_, owner| owner != &project.name);
+        self.workspaces
+            .lock()
+            .unwrap()
+            .insert(workspace_name(project), project.name.clone());
+    }
+
+    // moves the marked windows of the project to its workspace, answering how many
+    fn gather(&self, project: &Project) -> Result<usize, String> {
+        let client = self.client();
+        let mark = mark_name(&project.name);
+        let marked = client.marks()?.iter().filter(|m| **m == mark).count();
+        if marked > 0 {
+            client.run_command(&format!(
+                "[con_mark={}] move container to workspace {}",
+                quote(&format!("^{}$", regex_escape(&mark))),
+                quote(&workspace_name(project))
+            ))?;
+        }
+        Ok(marked)
+    }
+
+    fn focus(&self, project: &Project) -> Result<String, String> {
+        let workspace = workspace_name(project);
+        let gathered = self.gather(project)?;
+        // without auto back and forth, focusing the focused workspace again keeps it
+        self.client().run_command(&format!(
+            "workspace --no-auto-back-and-forth {}",
+            quote(&workspace)
+        ))?;
+        Ok(format!(
+            "workspace {} focused, {} marked windows moved there",
+            workspace, gathered
+        ))
+    }
+}
+
+impl Drop for SwayPlugin {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn workspace_name(project: &Project) -> String {
+    project
+        .properties
+        .get(&format!("{}workspace", PREFIX))
+        .cloned()
+        .unwrap_or(project.name.clone())
+}
+
+fn mark_name(project_name: &str) -> String {
+    format!("{}{}", MARK_PREFIX, project_name)
+}
+
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            let escaped = "\\^$.|?*+()[]{}".contains(c);
+            escaped.then_some('\\').into_iter().chain(std::iter::once(c))
+        })
+        .collect()
+}
+
+// follows the workspace focus, switching to the project owning the focused workspace
+fn follow_workspaces(
+    client: Arc<Mutex<SwayClient>>,
+    workspaces: Arc<Mutex<HashMap<String, String>>>,
+    current: Arc<Mutex<Option<String>>>,
+    follow_focus: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    requests: RequestSender,
+) {
+    let mut last_error = String::new();
+    while !stop.load(Ordering::SeqCst) {
+        let subscribed = client.lock().unwrap().clone();
+        let mut stream = match subscribed
+            .subscribe(&["workspace"])
+            .and_then(|stream| set_poll_interval(&stream, POLL_INTERVAL).map(|_| stream))
+        {
+            Ok(stream) => stream,
+            Err(e) => {
+                // the window manager may just not be running, it is said once
+                if e != last_error {
+                    eprintln!("sway plugin: {}", e);
+                    last_error = e;
+                }
+                // unless a new socket is configured meanwhile
+                let deadline = Instant::now() + RECONNECT_DELAY;
+                while Instant::now() < deadline
+                    && !stop.load(Ordering::SeqCst)
+                    && subscribed.socket() == client.lock().unwrap().socket()
+                {
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                continue;
+            }
+        };
+        last_error.clear();
+        loop {
+            // a new socket in the configuration means a new connection
+            if subscribed.socket() != client.lock().unwrap().socket() {
+                break;
+            }
+            let (message_type, event) = match read_message(&mut stream, &stop) {
+                Ok(Some(message)) => message,
+                Ok(None) if stop.load(Ordering::SeqCst) => return,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("sway plugin: {}", e);
+                    break;
+                }
+            };
+            if message_type != WORKSPACE_EVENT
+                || event["change"].as_str() != Some("focus")
+                || !follow_focus.load(Ordering::SeqCst)
+            {
+                continue;
+            }
+            let workspace = match event["current"]["name"].as_str() {
+                Some(workspace) => workspace,
+                None => continue,
+            };
+            let project = match workspaces.lock().unwrap().get(workspace) {
+                Some(project) => project.clone(),
+                None => continue,
+            };
+            if current.lock().unwrap().as_ref() == Some(&project) {
+                continue;
+            }
+            *current.lock().unwrap() = Some(project.clone());
+            let params = json!({ "project-name": project });
+            let request = DaemonRequest::new("project", "switch", params.as_object().unwrap().clone());
+            if requests.send(request).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Plugin for SwayPlugin {
+    fn name(&self) -> String {
+        "sway".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let result = match event {
+            Event::PluginEnable => {
+                self.track(project);
+                self.gather(project)
+                    .map(|gathered| format!("{} marked windows moved", gathered))
+            }
+            Event::ProjectUpdate => {
+                self.track(project);
+                Ok(format!("workspace {}", workspace_name(project)))
+            }
+            Event::PluginDisable => {
+                self.workspaces
+                    .lock()
+                    .unwrap()
+                    .retain(|_, owner| owner != &project.name);
+                Ok("workspace forgotten".to_string())
+            }
+            // focusing its workspace again has to switch back to it
+            Event::ProjectLeave => {
+                *self.current.lock().unwrap() = None;
+                return;
+            }
+            Event::ProjectSwitch => {
+                self.track(project);
+                *self.current.lock().unwrap() = Some(project.name.clone());
+                self.focus(project)
+            }
+            _ => return,
+        };
+        match result {
+            Ok(result) => println!("sway plugin: project {}: {}", project.name, result),
+            Err(e) => eprintln!("sway plugin: project {}: {}", project.name, e),
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "a sway or i3 workspace per project, focusing it switches the project"
+                .to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectSwitch.to_string(),
+                Event::ProjectLeave.to_string(),
+                Event::ProjectUpdate.to_string(),
+            ],
+            properties: vec![format!("{}workspace", PREFIX)],
+            config: vec![
+                ParamDescriptor::named(
+                    "socket",
+                    "the ipc socket of the window manager, SWAYSOCK or I3SOCK otherwise",
+                ),
+                ParamDescriptor::named(
+                    "follow-focus",
+                    "switch to the project of a focused workspace, true by default",
+                )
+                .kind(ParamKind::Bool),
+            ],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new(
+                "focus",
+                "focus the project workspace, moving its marked windows there",
+            )
+            .needs_project(),
+            ActionDescriptor::new(
+                "mark",
+                "mark the focused window, or the one with this con_id, as part of the project",
+            )
+            .needs_project()
+            .param(
+                ParamDescriptor::named("con-id", "the window")
+                    .kind(ParamKind::Integer)
+                    .min(0),
+            ),
+            ActionDescriptor::new("unmark", "remove the project mark from the focused window")
+                .needs_project(),
+            ActionDescriptor::new("workspaces", "list the workspaces and their projects"),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        let client = self.client();
+        if action == "workspaces" {
+            let owners = self.workspaces.lock().unwrap().clone();
+            return Ok(client
+                .workspaces()?
+                .iter()
+                .map(|workspace| {
+                    let mut line = format!("{} on {}", workspace.name, workspace.output);
+                    if let Some(owner) = owners.get(&workspace.name) {
+                        line = format!("{}, project {}", line, owner);
+                    }
+                    if workspace.focused {
+                        line = format!("{}, focused", line);
+                    }
+                    line
+                })
+                .collect::<Vec<String>>()
+                .join("\n"));
+        }
+        let project = project.ok_or("a project is needed".to_string())?;
+        let mark = mark_name(&project.name);
+        match action {
+            "focus" => {
+                self.track(project);
+                self.focus(project)
+            }
+            "mark" => {
+                let criteria = match action_arg(arguments, "con-id").and_then(|c| c.as_i64()) {
+                    Some(con_id) => format!("[con_id={}] ", con_id),
+                    None => String::new(),
+                };
+                client.run_command(&format!("{}mark --add {}", criteria, quote(&mark)))?;
+                Ok(format!("window marked {}", mark))
+            }
+            "unmark" => {
+                client.run_command(&format!("unmark {}", quote(&mark)))?;
+                Ok(format!("mark {} removed", mark))
+            }
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let client = self.client();
+        let workspace = workspace_name(project);
+        let status = match client.workspaces() {
+            Ok(workspaces) => {
+                let state = match workspaces.iter().find(|w| w.name == workspace) {
+                    Some(w) if w.focused => "focused",
+                    Some(_) => "open",
+                    None => "not open",
+                };
+                let marked = client
+                    .marks()
+                    .map(|marks| marks.iter().filter(|m| **m == mark_name(&project.name)).count())
+                    .unwrap_or(0);
+                format!("workspace {} {}, {} marked windows", workspace, state, marked)
+            }
+            Err(e) => e,
+        };
+        Some(status)
+    }
+    fn reconcile(&self, projects: &mut [Project]) -> Vec<String> {
+        let enabled: Vec<&Project> = projects
+            .iter()
+            .filter(|p| p.plugins.iter().any(|plugin| plugin == "sway"))
+            .collect();
+        let mut workspaces = self.workspaces.lock().unwrap();
+        workspaces.clear();
+        let mut report = Vec::new();
+        for project in enabled {
+            let workspace = workspace_name(project);
+            if let Some(other) = workspaces.insert(workspace.clone(), project.name.clone()) {
+                report.push(format!(
+                    "projects {} and {} share the workspace {}, set sway.workspace on one of them",
+                    other, project.name, workspace
+                ));
+            }
+        }
+        report
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let client = self.client();
+        let fix = "start sway or i3, or set the socket with `plugin set-config sway socket <path>`";
+        let mut checks = vec![];
+        if !client.has_socket() {
+            // i3 only sets I3SOCK
+            let variable = match (std::env::var_os("SWAYSOCK"), std::env::var_os("I3SOCK")) {
+                (None, Some(_)) => "I3SOCK",
+                _ => "SWAYSOCK",
+            };
+            checks.push(check_env(variable, fix));
+        }
+        checks.push(match client.version() {
+            Ok(version) => HealthCheck::ok("ipc", &version),
+            Err(e) => HealthCheck::failed("ipc", &e, fix),
+        });
+        checks
+    }
+    fn set_request_sender(&mut self, sender: RequestSender) {
+        let client = Arc::clone(&self.client);
+        let workspaces = Arc::clone(&self.workspaces);
+        let current = Arc::clone(&self.current);
+        let follow_focus = Arc::clone(&self.follow_focus);
+        let stop = Arc::clone(&self.stop);
+        std::thread::spawn(move || {
+            follow_workspaces(client, workspaces, current, follow_focus, stop, sender)
+        });
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        *self.client.lock().unwrap() = match config.get("socket").and_then(|s| s.as_str()) {
+            Some(socket) => SwayClient::with_socket(&PathBuf::from(socket)),
+            None => SwayClient::new(),
+        };
+        let follow_focus = config
+            .get("follow-focus")
+            .and_then(|f| f.as_bool())
+            .unwrap_or(true);
+        self.follow_focus.store(follow_focus, Ordering::SeqCst);
+        Ok(())
+    }
+}
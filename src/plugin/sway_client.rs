@@ -0,0 +1,314 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// the i3 ipc protocol, spoken by sway and i3: a magic string, the payload length and the
+// message type as native endian u32, then a json payload
+const MAGIC: &[u8] = b"i3-ipc";
+const HEADER_LENGTH: usize = 14;
+pub const RUN_COMMAND: u32 = 0;
+pub const GET_WORKSPACES: u32 = 1;
+pub const SUBSCRIBE: u32 = 2;
+pub const GET_MARKS: u32 = 5;
+pub const GET_VERSION: u32 = 7;
+// events have the highest bit set, the workspace one is the first
+pub const WORKSPACE_EVENT: u32 = 0x8000_0000;
+// a window manager that stopped answering must not hang the daemon
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Workspace {
+    pub name: String,
+    #[serde(default)]
+    pub focused: bool,
+    #[serde(default)]
+    pub output: String,
+}
+
+// talks to the window manager, on the given socket or the one of $SWAYSOCK or $I3SOCK
+#[derive(Debug, Clone, Default)]
+pub struct SwayClient {
+    socket: Option<PathBuf>,
+}
+
+impl SwayClient {
+    pub fn new() -> Self {
+        SwayClient { socket: None }
+    }
+
+    pub fn with_socket(socket: &Path) -> Self {
+        SwayClient {
+            socket: Some(socket.to_path_buf()),
+        }
+    }
+
+    // whether the socket was given, instead of taken from the environment
+    pub fn has_socket(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    pub fn socket(&self) -> Result<PathBuf, String> {
+        if let Some(socket) = &self.socket {
+            return Ok(socket.clone());
+        }
+        ["SWAYSOCK", "I3SOCK"]
+            .iter()
+            .find_map(|variable| std::env::var(variable).ok().filter(|v| !v.is_empty()))
+            .map(PathBuf::from)
+            .ok_or("neither SWAYSOCK nor I3SOCK is set".to_string())
+    }
+
+    pub fn connect(&self) -> Result<UnixStream, String> {
+        let socket = self.socket()?;
+        let stream = UnixStream::connect(&socket)
+            .map_err(|e| format!("cannot connect to {}: {}", socket.display(), e))?;
+        stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(REQUEST_TIMEOUT)))
+            .map_err(|e| format!("cannot configure the window manager connection: {}", e))?;
+        Ok(stream)
+    }
+
+    fn request(&self, message_type: u32, payload: &str) -> Result<Value, String> {
+        let mut stream = self.connect()?;
+        send_message(&mut stream, message_type, payload)?;
+        read_reply(&mut stream)
+    }
+
+    // runs a command, like `workspace 1`, failing with the error of the first one that failed
+    pub fn run_command(&self, command: &str) -> Result<(), String> {
+        let reply = self.request(RUN_COMMAND, command)?;
+        for outcome in reply.as_array().into_iter().flatten() {
+            if outcome["success"].as_bool() != Some(true) {
+                return Err(format!(
+                    "`{}` failed: {}",
+                    command,
+                    outcome["error"].as_str().unwrap_or("unknown error")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn workspaces(&self) -> Result<Vec<Workspace>, String> {
+        serde_json::from_value(self.request(GET_WORKSPACES, "")?)
+            .map_err(|e| format!("invalid workspaces: {}", e))
+    }
+
+    pub fn marks(&self) -> Result<Vec<String>, String> {
+        serde_json::from_value(self.request(GET_MARKS, "")?)
+            .map_err(|e| format!("invalid marks: {}", e))
+    }
+
+    pub fn version(&self) -> Result<String, String> {
+        let reply = self.request(GET_VERSION, "")?;
+        Ok(reply["human_readable"].as_str().unwrap_or("unknown").to_string())
+    }
+
+    // a connection receiving the given events, read them with `read_message`
+    pub fn subscribe(&self, events: &[&str]) -> Result<UnixStream, String> {
+        let mut stream = self.connect()?;
+        let payload = serde_json::to_string(events).unwrap();
+        send_message(&mut stream, SUBSCRIBE, &payload)?;
+        match read_reply(&mut stream)? {
+            reply if reply["success"].as_bool() == Some(true) => Ok(stream),
+            _ => Err("subscription refused".to_string()),
+        }
+    }
+}
+
+pub fn send_message(stream: &mut UnixStream, message_type: u32, payload: &str) -> Result<(), String> {
+    let mut message = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream
+        .write_all(&message)
+        .map_err(|e| format!("cannot write to the window manager: {}", e))
+}
+
+// fills the buffer, waiting through read timeouts unless nothing was read and stop is set,
+// without stop a read timeout is an error
+fn read_full(
+    stream: &mut UnixStream,
+    buffer: &mut [u8],
+    stop: Option<&AtomicBool>,
+) -> Result<bool, String> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match stream.read(&mut buffer[read..]) {
+            Ok(0) => return Err("the window manager closed the connection".to_string()),
+            Ok(count) => read += count,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                match stop {
+                    Some(stop) if read == 0 && stop.load(Ordering::SeqCst) => return Ok(false),
+                    Some(_) => {}
+                    None => return Err("the window manager did not answer in time".to_string()),
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("cannot read from the window manager: {}", e)),
+        }
+    }
+    Ok(true)
+}
+
+// the next message, None when stop was set while waiting for it
+pub fn read_message(
+    stream: &mut UnixStream,
+    stop: &AtomicBool,
+) -> Result<Option<(u32, Value)>, String> {
+    read_framed(stream, Some(stop))
+}
+
+// the reply to a request, within the read timeout of the stream
+fn read_reply(stream: &mut UnixStream) -> Result<Value, String> {
+    match read_framed(stream, None)? {
+        Some((_, reply)) => Ok(reply),
+        None => Err("no reply from the window manager".to_string()),
+    }
+}
+
+fn read_framed(
+    stream: &mut UnixStream,
+    stop: Option<&AtomicBool>,
+) -> Result<Option<(u32, Value)>, String> {
+    let mut header = [0u8; HEADER_LENGTH];
+    if !read_full(stream, &mut header, stop)? {
+        return Ok(None);
+    }
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err("invalid message from the window manager".to_string());
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0u8; length];
+    // the rest of a started message is waited for
+    let never = AtomicBool::new(false);
+    read_full(stream, &mut payload, stop.map(|_| &never))?;
+    let payload = serde_json::from_slice(&payload)
+        .map_err(|e| format!("invalid message from the window manager: {}", e))?;
+    Ok(Some((message_type, payload)))
+}
+
+// sets how often a subscription wakes up to check if it should stop
+pub fn set_poll_interval(stream: &UnixStream, interval: Duration) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(interval))
+        .map_err(|e| format!("cannot configure the window manager connection: {}", e))
+}
+
+// `"name"` with quotes and backslashes escaped, for command arguments and criteria
+pub fn quote(word: &str) -> String {
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+    use tempfile::TempDir;
+
+    // a window manager answering each request with the given replies, on a socket in dir
+    fn serve(dir: &TempDir, replies: Vec<Value>) -> (SwayClient, JoinHandle<Vec<(u32, Value)>>) {
+        let socket = dir.path().join("sway.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0u8; HEADER_LENGTH];
+                stream.read_exact(&mut header).unwrap();
+                let length = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+                let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+                let mut payload = vec![0u8; length];
+                stream.read_exact(&mut payload).unwrap();
+                let payload = String::from_utf8(payload).unwrap();
+                requests.push((message_type, Value::String(payload)));
+                send_message(&mut stream, message_type, &reply.to_string()).unwrap();
+            }
+            requests
+        });
+        (SwayClient::with_socket(&socket), server)
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let (mut left, mut right) = UnixStream::pair().unwrap();
+        send_message(&mut left, GET_WORKSPACES, r#"{"name":"1"}"#).unwrap();
+        send_message(&mut left, WORKSPACE_EVENT, "[]").unwrap();
+        let never = AtomicBool::new(false);
+        let message = read_message(&mut right, &never).unwrap();
+        assert_eq!(message, Some((GET_WORKSPACES, json!({"name": "1"}))));
+        let message = read_message(&mut right, &never).unwrap();
+        assert_eq!(message, Some((WORKSPACE_EVENT, json!([]))));
+    }
+
+    #[test]
+    fn invalid_messages_are_errors() {
+        let (mut left, mut right) = UnixStream::pair().unwrap();
+        left.write_all(b"not-i3\0\0\0\0\0\0\0\0").unwrap();
+        let never = AtomicBool::new(false);
+        assert!(read_message(&mut right, &never).is_err());
+
+        drop(left);
+        let error = read_message(&mut right, &never).unwrap_err();
+        assert!(error.contains("closed the connection"), "{}", error);
+    }
+
+    #[test]
+    fn stopped_reads_return_nothing() {
+        let (_left, mut right) = UnixStream::pair().unwrap();
+        set_poll_interval(&right, Duration::from_millis(10)).unwrap();
+        let stop = AtomicBool::new(true);
+        assert_eq!(read_message(&mut right, &stop).unwrap(), None);
+    }
+
+    #[test]
+    fn silent_window_manager_times_out() {
+        let (_left, mut right) = UnixStream::pair().unwrap();
+        right.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let error = read_reply(&mut right).unwrap_err();
+        assert!(error.contains("did not answer"), "{}", error);
+    }
+
+    #[test]
+    fn run_command_reports_the_first_failure() {
+        let dir = TempDir::new().unwrap();
+        let (client, server) = serve(
+            &dir,
+            vec![
+                json!([{"success": true}]),
+                json!([{"success": true}, {"success": false, "error": "no such workspace"}]),
+                json!([{"success": false}]),
+            ],
+        );
+        assert_eq!(client.run_command("workspace 1"), Ok(()));
+        assert_eq!(
+            client.run_command("workspace 1; move to mark x"),
+            Err("`workspace 1; move to mark x` failed: no such workspace".to_string())
+        );
+        assert_eq!(
+            client.run_command("nop"),
+            Err("`nop` failed: unknown error".to_string())
+        );
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0], (RUN_COMMAND, json!("workspace 1")));
+        assert_eq!(requests[2], (RUN_COMMAND, json!("nop")));
+    }
+
+    #[test]
+    fn missing_socket_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        let client = SwayClient::with_socket(&dir.path().join("missing.sock"));
+        let error = client.run_command("nop").unwrap_err();
+        assert!(error.starts_with("cannot connect to"), "{}", error);
+    }
+}
@@ -0,0 +1,519 @@
+use crate::config::{context_name, split_context, Project};
+use crate::event::Event;
+use crate::plugin::action::action_arg;
+use crate::plugin::health::{check_command, check_env};
+use crate::plugin::{ActionDescriptor, HealthCheck, ParamDescriptor, Plugin, PluginInfo};
+use serde_json::{Map, Value};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+// a terminal window, or tab, per project attached to its tmux session, driven by the remote
+// control of kitty (`kitty @`, enable it with `allow_remote_control` and `listen_on`) or
+// wezterm (`wezterm cli`).
+// kitty windows are found by the user variable below, wezterm ones by the prefix of their
+// tab title, other tabs may have a title of their own
+const KITTY_VAR: &str = "project-pilot";
+const WEZTERM_TITLE_PREFIX: &str = "project-pilot:";
+// the attach command comes from the tmux plugin, asking the daemon once the terminal runs
+const ATTACH_SCRIPT: &str = r#"command=$("$0" plugin run tmux gen_init_terminal "$@") && sh -c "$command" || { echo "$command"; read -r _; }"#;
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+enum TerminalKind {
+    Kitty,
+    Wezterm,
+}
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "kebab-case")]
+enum WindowKind {
+    OsWindow,
+    Tab,
+}
+
+pub struct TerminalPlugin {
+    kind: TerminalKind,
+    window: WindowKind,
+    kitty: String,
+    // the `--to` address of kitty, `KITTY_LISTEN_ON` is used otherwise
+    kitty_socket: Option<String>,
+    wezterm: String,
+    pilot: String,
+}
+
+impl TerminalPlugin {
+    pub fn new() -> Self {
+        TerminalPlugin {
+            kind: TerminalKind::Kitty,
+            window: WindowKind::OsWindow,
+            kitty: "kitty".to_string(),
+            kitty_socket: None,
+            wezterm: "wezterm".to_string(),
+            pilot: default_pilot(),
+        }
+    }
+
+    fn remote(&self, args: &[&str]) -> Result<String, String> {
+        let mut command = match self.kind {
+            TerminalKind::Kitty => {
+                let mut command = Command::new(&self.kitty);
+                command.arg("@");
+                if let Some(socket) = &self.kitty_socket {
+                    command.arg("--to").arg(socket);
+                }
+                command
+            }
+            TerminalKind::Wezterm => {
+                let mut command = Command::new(&self.wezterm);
+                command.arg("cli");
+                command
+            }
+        };
+        let binary = command.get_program().to_string_lossy().to_string();
+        let output = command
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to execute {}: {}", binary, e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            Err(format!(
+                "{} {} failed: {}",
+                binary,
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    // the owners of the windows we opened, with the kitty window or wezterm pane to address
+    // them
+    fn windows(&self) -> Result<Vec<(String, String)>, String> {
+        let listing = match self.kind {
+            TerminalKind::Kitty => self.remote(&["ls"])?,
+            TerminalKind::Wezterm => self.remote(&["list", "--format", "json"])?,
+        };
+        parse_windows(self.kind, &listing)
+    }
+
+    fn find(&self, owner: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .windows()?
+            .into_iter()
+            .find(|(window_owner, _)| window_owner == owner)
+            .map(|(_, id)| id))
+    }
+
+    // the command run in the window, attaching to the tmux session of the project
+    fn attach_command(&self, project_name: &str, context: Option<&str>) -> Vec<String> {
+        let mut command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            ATTACH_SCRIPT.to_string(),
+            self.pilot.clone(),
+            project_name.to_string(),
+        ];
+        if let Some(context) = context {
+            command.push("--context".to_string());
+            command.push(context.to_string());
+        }
+        command
+    }
+
+    // the arguments of `kitty @ launch` or `wezterm cli spawn` opening the window of owner
+    fn launch_args(
+        &self,
+        owner: &str,
+        directory: Option<&str>,
+        command: Vec<String>,
+    ) -> Vec<String> {
+        let mut args: Vec<String> = match self.kind {
+            TerminalKind::Kitty => vec![
+                "launch".to_string(),
+                format!("--type={}", self.window),
+                "--title".to_string(),
+                owner.to_string(),
+                "--tab-title".to_string(),
+                owner.to_string(),
+                "--var".to_string(),
+                format!("{}={}", KITTY_VAR, owner),
+            ],
+            TerminalKind::Wezterm if self.window == WindowKind::OsWindow => {
+                vec!["spawn".to_string(), "--new-window".to_string()]
+            }
+            TerminalKind::Wezterm => vec!["spawn".to_string()],
+        };
+        if let Some(directory) = directory {
+            args.push("--cwd".to_string());
+            args.push(directory.to_string());
+        }
+        if self.kind == TerminalKind::Wezterm {
+            args.push("--".to_string());
+        }
+        args.extend(command);
+        args
+    }
+
+    fn launch(&self, project: &Project, context: Option<&str>) -> Result<String, String> {
+        let owner = context_name(&project.name, context);
+        let directory = match context {
+            Some(context) => project.context(context).map(|c| c.path.clone()),
+            None => project.properties.get("path").cloned(),
+        };
+        let command = self.attach_command(&project.name, context);
+        let args = self.launch_args(&owner, directory.as_deref(), command);
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        match self.kind {
+            TerminalKind::Kitty => {
+                self.remote(&args)?;
+            }
+            TerminalKind::Wezterm => {
+                let pane = self.remote(&args)?;
+                let title = wezterm_title(&owner);
+                self.remote(&["set-tab-title", "--pane-id", &pane, &title])?;
+                if self.window == WindowKind::OsWindow {
+                    self.remote(&["set-window-title", "--pane-id", &pane, &title])?;
+                }
+            }
+        }
+        Ok(format!("{} {} opened for {}", self.kind, self.window, owner))
+    }
+
+    fn focus(&self, owner: &str) -> Result<bool, String> {
+        let id = match self.find(owner)? {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        match self.kind {
+            TerminalKind::Kitty => {
+                self.remote(&["focus-window", "--match", &format!("id:{}", id)])?
+            }
+            TerminalKind::Wezterm => self.remote(&["activate-pane", "--pane-id", &id])?,
+        };
+        Ok(true)
+    }
+
+    // focuses the window of the project, opening it first if needed
+    fn open(&self, project: &Project, context: Option<&str>) -> Result<String, String> {
+        let owner = context_name(&project.name, context);
+        if self.focus(&owner)? {
+            return Ok(format!("{} {} of {} focused", self.kind, self.window, owner));
+        }
+        self.launch(project, context)
+    }
+
+    fn close(&self, project_name: &str) -> Result<Vec<String>, String> {
+        let mut closed = Vec::new();
+        for (owner, id) in self.windows()? {
+            if split_context(&owner).0 != project_name {
+                continue;
+            }
+            match self.kind {
+                TerminalKind::Kitty => {
+                    self.remote(&["close-window", "--match", &format!("id:{}", id)])?
+                }
+                TerminalKind::Wezterm => self.remote(&["kill-pane", "--pane-id", &id])?,
+            };
+            closed.push(owner);
+        }
+        Ok(closed)
+    }
+}
+
+// the title of the wezterm tabs we opened
+fn wezterm_title(owner: &str) -> String {
+    format!("{}{}", WEZTERM_TITLE_PREFIX, owner)
+}
+
+// the windows with an owner in the output of `kitty @ ls` or `wezterm cli list --format json`
+fn parse_windows(kind: TerminalKind, listing: &str) -> Result<Vec<(String, String)>, String> {
+    let listing: Value = serde_json::from_str(listing)
+        .map_err(|e| format!("invalid {} window list: {}", kind, e))?;
+    let windows = listing.as_array().into_iter().flatten();
+    let windows = match kind {
+        TerminalKind::Kitty => windows
+            .flat_map(|os_window| os_window["tabs"].as_array().into_iter().flatten())
+            .flat_map(|tab| tab["windows"].as_array().into_iter().flatten())
+            .filter_map(|window| {
+                let owner = window["user_vars"][KITTY_VAR].as_str()?;
+                Some((owner.to_string(), window["id"].to_string()))
+            })
+            .collect(),
+        TerminalKind::Wezterm => windows
+            .filter_map(|pane| {
+                let owner = pane["tab_title"]
+                    .as_str()?
+                    .strip_prefix(WEZTERM_TITLE_PREFIX)
+                    .filter(|owner| !owner.is_empty())?;
+                Some((owner.to_string(), pane["pane_id"].to_string()))
+            })
+            .collect(),
+    };
+    Ok(windows)
+}
+
+fn default_pilot() -> String {
+    std::env::current_exe()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or("project-pilot".to_string())
+}
+
+fn context_param() -> ParamDescriptor {
+    ParamDescriptor::named("context", "use the window of this context of the project")
+}
+
+impl Plugin for TerminalPlugin {
+    fn name(&self) -> String {
+        "terminal".to_string()
+    }
+    fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String, Value>) {
+        let result = match event {
+            Event::PluginEnable => self.open(project, None),
+            Event::ProjectSwitch => {
+                self.open(project, arguments.get("context").and_then(|c| c.as_str()))
+            }
+            Event::PluginDisable => self
+                .close(&project.name)
+                .map(|closed| format!("terminal windows closed: {}", closed.join(", "))),
+            _ => return,
+        };
+        match result {
+            Ok(result) => println!("{}", result),
+            Err(e) => eprintln!("terminal plugin: {}", e),
+        }
+    }
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "a kitty or wezterm window per project, attached to its tmux session"
+                .to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectSwitch.to_string(),
+            ],
+            properties: vec!["path".to_string()],
+            dependencies: vec!["tmux".to_string()],
+            config: vec![
+                ParamDescriptor::named("kind", "the terminal, kitty or wezterm"),
+                ParamDescriptor::named("window", "what to open, os-window or tab"),
+                ParamDescriptor::named("kitty", "the kitty binary"),
+                ParamDescriptor::named(
+                    "kitty-socket",
+                    "where kitty listens, like unix:/tmp/kitty, KITTY_LISTEN_ON is used otherwise",
+                ),
+                ParamDescriptor::named("wezterm", "the wezterm binary"),
+                ParamDescriptor::named(
+                    "pilot",
+                    "the project-pilot binary run by the terminal, the daemon one by default",
+                ),
+            ],
+            ..PluginInfo::default()
+        }
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new("open", "focus the project window, opening it if needed")
+                .needs_project()
+                .param(context_param()),
+            ActionDescriptor::new("close", "close the windows of the project").needs_project(),
+            ActionDescriptor::new("list", "list the project windows and their owners"),
+        ]
+    }
+    fn run_action(
+        &self,
+        action: &str,
+        project: Option<&mut Project>,
+        arguments: &Map<String, Value>,
+    ) -> Result<String, String> {
+        match action {
+            "open" => {
+                let project = project.ok_or("a project is needed".to_string())?;
+                let context = action_arg(arguments, "context").and_then(|c| c.as_str());
+                if let Some(context) = context {
+                    project.context(context).ok_or(format!(
+                        "context {} not found for project {}",
+                        context, project.name
+                    ))?;
+                }
+                self.open(project, context)
+            }
+            "close" => {
+                let project = project.ok_or("a project is needed".to_string())?;
+                let closed = self.close(&project.name)?;
+                if closed.is_empty() {
+                    return Ok(format!("no terminal windows for project {}", project.name));
+                }
+                Ok(format!("closed: {}", closed.join(", ")))
+            }
+            "list" => Ok(self
+                .windows()?
+                .iter()
+                .map(|(owner, id)| format!("{}: {} {}", owner, self.kind, id))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let status = match self.find(&project.name) {
+            Ok(Some(_)) => format!("{} {} open", self.kind, self.window),
+            Ok(None) => format!("no {} {}", self.kind, self.window),
+            Err(e) => e,
+        };
+        Some(status)
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        match self.kind {
+            TerminalKind::Kitty => {
+                let mut checks = vec![check_command(
+                    "kitty",
+                    &self.kitty,
+                    &["--version"],
+                    "install kitty, or set its path with `plugin set-config terminal kitty <path>`",
+                )];
+                if self.kitty_socket.is_none() {
+                    checks.push(check_env(
+                        "KITTY_LISTEN_ON",
+                        "start the daemon from kitty, or set `plugin set-config terminal kitty-socket <address>`",
+                    ));
+                }
+                checks.push(match self.remote(&["ls"]) {
+                    Ok(_) => HealthCheck::ok("remote-control", "kitty answers"),
+                    Err(e) => HealthCheck::failed(
+                        "remote-control",
+                        &e,
+                        "enable `allow_remote_control` and `listen_on` in kitty.conf, and set `plugin set-config terminal kitty-socket <address>`",
+                    ),
+                });
+                checks
+            }
+            TerminalKind::Wezterm => vec![check_command(
+                "wezterm",
+                &self.wezterm,
+                &["cli", "list"],
+                "start wezterm, or set its path with `plugin set-config terminal wezterm <path>`",
+            )],
+        }
+    }
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        let setting = |name: &str| config.get(name).and_then(|v| v.as_str());
+        self.kind = match setting("kind") {
+            Some(kind) => TerminalKind::from_str(kind)
+                .map_err(|_| format!("unknown terminal {}, use kitty or wezterm", kind))?,
+            None => TerminalKind::Kitty,
+        };
+        self.window = match setting("window") {
+            Some(window) => WindowKind::from_str(window)
+                .map_err(|_| format!("unknown window {}, use os-window or tab", window))?,
+            None => WindowKind::OsWindow,
+        };
+        self.kitty = setting("kitty").unwrap_or("kitty").to_string();
+        self.kitty_socket = setting("kitty-socket").map(String::from);
+        self.wezterm = setting("wezterm").unwrap_or("wezterm").to_string();
+        self.pilot = setting("pilot").map(String::from).unwrap_or_else(default_pilot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn plugin(config: Value) -> TerminalPlugin {
+        let mut config = config.as_object().unwrap().clone();
+        config.insert("pilot".to_string(), json!("pp"));
+        let mut plugin = TerminalPlugin::new();
+        plugin.configure(&config).unwrap();
+        plugin
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn attach_command_asks_the_daemon() {
+        let plugin = plugin(json!({}));
+        assert_eq!(
+            plugin.attach_command("app", Some("wt")),
+            strings(&["sh", "-c", ATTACH_SCRIPT, "pp", "app", "--context", "wt"])
+        );
+    }
+
+    #[test]
+    fn kitty_windows_are_launched_with_their_owner() {
+        let plugin = plugin(json!({"window": "tab"}));
+        let args = plugin.launch_args("app/wt", Some("/src/wt"), strings(&["sh"]));
+        assert_eq!(
+            args,
+            strings(&[
+                "launch",
+                "--type=tab",
+                "--title",
+                "app/wt",
+                "--tab-title",
+                "app/wt",
+                "--var",
+                "project-pilot=app/wt",
+                "--cwd",
+                "/src/wt",
+                "sh",
+            ])
+        );
+    }
+
+    #[test]
+    fn wezterm_windows_are_spawned() {
+        let os_window = plugin(json!({"kind": "wezterm"}));
+        let args = os_window.launch_args("app", Some("/src/app"), strings(&["sh"]));
+        assert_eq!(args, strings(&["spawn", "--new-window", "--cwd", "/src/app", "--", "sh"]));
+        let tab = plugin(json!({"kind": "wezterm", "window": "tab"}));
+        let args = tab.launch_args("app", None, strings(&["sh"]));
+        assert_eq!(args, strings(&["spawn", "--", "sh"]));
+        assert_eq!(wezterm_title("app/wt"), "project-pilot:app/wt");
+    }
+
+    #[test]
+    fn kitty_windows_are_found_by_their_variable() {
+        let listing = json!([{
+            "tabs": [
+                {"windows": [
+                    {"id": 1, "user_vars": {"project-pilot": "app"}},
+                    {"id": 2, "user_vars": {}},
+                ]},
+                {"windows": [{"id": 3, "user_vars": {"project-pilot": "app/wt"}}]},
+            ],
+        }]);
+        let windows = parse_windows(TerminalKind::Kitty, &listing.to_string()).unwrap();
+        assert_eq!(
+            windows,
+            [
+                ("app".to_string(), "1".to_string()),
+                ("app/wt".to_string(), "3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn wezterm_panes_are_found_by_their_tab_title() {
+        let listing = json!([
+            {"pane_id": 4, "tab_title": "project-pilot:app"},
+            {"pane_id": 5, "tab_title": "project-pilot:"},
+            {"pane_id": 6, "tab_title": "notes"},
+            {"pane_id": 7, "tab_title": "project-pilot:api/fix"},
+        ]);
+        let windows = parse_windows(TerminalKind::Wezterm, &listing.to_string()).unwrap();
+        assert_eq!(
+            windows,
+            [
+                ("app".to_string(), "4".to_string()),
+                ("api/fix".to_string(), "7".to_string()),
+            ]
+        );
+        let error = parse_windows(TerminalKind::Wezterm, "not json").unwrap_err();
+        assert!(error.starts_with("invalid wezterm window list"));
+    }
+}
@@ -1,24 +1,129 @@
-use crate::config::Project;
+use crate::config::{context_name, split_context, Project};
 use crate::event::Event;
-use crate::plugin::Plugin;
+use crate::plugin::tmux_client::{
+    exact_target, pane_target, session_name, TmuxClient, TmuxSession, PROJECT_OPTION,
+};
+use crate::plugin::action::{action_arg, action_arg_list};
+use crate::plugin::env::project_environment;
+use crate::plugin::health::{check_command, version_at_least};
+use crate::plugin::{ActionDescriptor, HealthCheck, ParamDescriptor, ParamKind, Plugin, PluginInfo};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::boxed::Box;
 use std::collections::HashMap;
 use tokio::sync::Mutex;
 
+const DEFAULT_CAPTURE_LINES: u64 = 50;
+
+// user options and `=name:` targets need a recent tmux, `new-session -e` needs 3.2
+const MIN_TMUX_VERSION: &str = "3.2";
+
 pub struct TmuxPlugin {
+    client: TmuxClient,
+}
+
+impl TmuxPlugin {
+    pub fn new() -> Self {
+        TmuxPlugin {
+            client: TmuxClient::new(),
+        }
+    }
+
+    // the session belonging to the project: the one tagged with its name, even if it was
+    // renamed, or an untagged one with the expected name
+    fn find_session(&self, project_name: &str) -> Option<TmuxSession> {
+        let sessions = self.client.list_sessions().unwrap_or_default();
+        let expected = session_name(project_name);
+        sessions
+            .iter()
+            .find(|s| s.project.as_deref() == Some(project_name))
+            .or_else(|| sessions.iter().find(|s| s.project.is_none() && s.name == expected))
+            .cloned()
+    }
+
+    // sessions belong to a project, or to one of its contexts as `project/context`
+    fn running_session(&self, owner: &str) -> Result<TmuxSession, String> {
+        let sync = match split_context(owner) {
+            (project, Some(context)) => format!("{} --context {}", project, context),
+            (project, None) => project.to_string(),
+        };
+        self.find_session(owner).ok_or(format!(
+            "tmux session for {} is missing, recreate it with `project-pilot plugin run tmux sync {}`",
+            owner, sync
+        ))
+    }
+
+    fn create_session(
+        &self,
+        owner: &str,
+        path: Option<&str>,
+        environment: &[(String, String)],
+    ) -> Result<String, String> {
+        let session_name = session_name(owner);
+        // owners like "a.b" and "a_b" get the same session name
+        if let Some(taken) = self
+            .client
+            .list_sessions()?
+            .into_iter()
+            .find(|s| s.name == session_name)
+        {
+            return Err(format!(
+                "cannot create tmux session {} for {}, the name is taken by {}",
+                session_name,
+                owner,
+                taken.project.as_deref().unwrap_or("a session created by hand")
+            ));
+        }
+        let created = self.client.new_session(&session_name, path, environment)?;
+        self.client
+            .set_session_option(&session_name, PROJECT_OPTION, owner)?;
+        Ok(created)
+    }
+
+    // the variables of the env plugin, when the project uses it
+    fn environment(
+        project: &Project,
+        path: Option<&str>,
+        arguments: &Map<String, Value>,
+    ) -> Vec<(String, String)> {
+        if !project.plugins.iter().any(|p| p == "env") {
+            return Vec::new();
+        }
+        match project_environment(project, path, arguments) {
+            Ok(environment) => environment.into_iter().collect(),
+            Err(e) => {
+                eprintln!("environment of project {} not loaded: {}", project.name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    // the owner of the session the action works on, and its directory: the project, or the
+    // context given with --context
+    fn action_target(
+        project: &Project,
+        arguments: &Map<String, Value>,
+    ) -> Result<(String, Option<String>), String> {
+        match action_arg(arguments, "context").and_then(|c| c.as_str()) {
+            Some(context) => {
+                let context = project.context(context).ok_or(format!(
+                    "context {} not found for project {}",
+                    context, project.name
+                ))?;
+                Ok((
+                    context_name(&project.name, Some(&context.name)),
+                    Some(context.path.clone()),
+                ))
+            }
+            None => Ok((project.name.clone(), project.properties.get("path").cloned())),
+        }
+    }
 }
 
-fn tmux_has_session(session_name: &str) -> bool {
-    std::process::Command::new("tmux")
-        .arg("has-session")
-        .arg("-t")
-        .arg(session_name)
-        .output()
-        .expect("failed to execute process")
-        .status.success()
+fn context_param() -> ParamDescriptor {
+    ParamDescriptor::named("context", "use the session of this context of the project")
 }
+
 impl Plugin for TmuxPlugin {
     fn name(&self) -> String {
         "tmux".to_string()
@@ -26,60 +131,351 @@ impl Plugin for TmuxPlugin {
     fn on_event(&self, event: Event, project: &mut Project, arguments: &Map<String,Value>) {
         println!("tmux plugin: {} project: {}", event,project.name);
         match event {
-            Event::PluginEnable => {
-                let session_name = project.name.clone();
-                if !tmux_has_session(&session_name) {
-                    let session_path = if let Some(project_path)=project.properties.get("path") {
-                        format!("-c {}",project_path.as_str()).to_string()
-                    } else {
-                        "".to_string()
-                    };
-                    let tmux_command = format!("tmux new-session -d -s {} {} -P", session_name, session_path);
-                    let output = std::process::Command::new("sh")
-                        .arg("-c")
-                        .arg(&tmux_command)
-                        .output()
-                        .expect("failed to execute process");
-                    println!("tmux command: {}", &tmux_command);
-                    if !output.status.success() {
-                        eprintln!("failed to execute tmux command: {}", String::from_utf8_lossy(&output.stderr));
+            Event::PluginEnable => match self.find_session(&project.name) {
+                Some(session) if session.project.is_none() => {
+                    // adopt a session that was created by hand
+                    if let Err(e) = self
+                        .client
+                        .set_session_option(&session.name, PROJECT_OPTION, &project.name)
+                    {
+                        eprintln!("failed to tag tmux session: {}", e);
                     }
                 }
-            }
+                Some(_) => {}
+                None => match self.create_session(
+                    &project.name,
+                    project.properties.get("path").map(|p| p.as_str()),
+                    &TmuxPlugin::environment(project, None, arguments),
+                ) {
+                    Ok(created) => println!("tmux session created: {}", created),
+                    Err(e) => eprintln!("failed to create tmux session: {}", e),
+                },
+            },
             Event::PluginDisable => {
-                let session_name = project.name.clone();
-                if tmux_has_session(&session_name) {
-                    let tmux_command = format!("tmux kill-session -t {}", session_name);
-                    let output = std::process::Command::new("sh")
-                        .arg("-c")
-                        .arg(&tmux_command)
-                        .output()
-                        .expect("failed to execute process");
-                    println!("tmux command: {}", &tmux_command);
-                    if !output.status.success() {
-                        eprintln!("failed to execute tmux command: {}", String::from_utf8_lossy(&output.stderr));
+                let prefix = format!("{}/", project.name);
+                let mut sessions: Vec<TmuxSession> = self.find_session(&project.name).into_iter().collect();
+                // the sessions of the contexts go too
+                sessions.extend(
+                    self.client
+                        .list_sessions()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|s| s.project.as_deref().map(|p| p.starts_with(&prefix)).unwrap_or(false)),
+                );
+                for session in sessions {
+                    match self.client.kill_session(&session.name) {
+                        Ok(()) => println!("tmux session killed: {}", session.name),
+                        Err(e) => eprintln!("failed to kill tmux session: {}", e),
+                    }
+                }
+            }
+            // the session of a context is created the first time we switch to it
+            Event::ProjectSwitch => {
+                let context = arguments.get("context").and_then(|c| c.as_str());
+                let path = arguments.get("context-path").and_then(|p| p.as_str());
+                if let Some(context) = context {
+                    let owner = context_name(&project.name, Some(context));
+                    if self.find_session(&owner).is_none() {
+                        let environment = TmuxPlugin::environment(project, path, arguments);
+                        match self.create_session(&owner, path, &environment) {
+                            Ok(created) => println!("tmux session created: {}", created),
+                            Err(e) => eprintln!("failed to create tmux session: {}", e),
+                        }
                     }
                 }
             }
             _ => {}
         }
     }
-    fn list_actions(&self) -> Vec<String> {
-        vec!["gen_init_terminal".to_string()]
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            description: "a tmux session for each project".to_string(),
+            events: vec![
+                Event::PluginEnable.to_string(),
+                Event::PluginDisable.to_string(),
+                Event::ProjectSwitch.to_string(),
+            ],
+            properties: vec!["path".to_string()],
+            // sessions get the environment of the env plugin
+            after: vec!["env".to_string()],
+            config: vec![ParamDescriptor::named(
+                "socket",
+                "name of the tmux server socket, like `tmux -L`",
+            )],
+            ..PluginInfo::default()
+        }
+    }
+    fn health_check(&self) -> Vec<HealthCheck> {
+        let binary = check_command(
+            "binary",
+            "tmux",
+            &["-V"],
+            "install tmux with the system package manager",
+        );
+        if !binary.ok {
+            return vec![binary];
+        }
+        // `tmux 3.4`
+        let version = if version_at_least(&binary.message, MIN_TMUX_VERSION) {
+            HealthCheck::ok("version", &format!("{} or newer", MIN_TMUX_VERSION))
+        } else {
+            HealthCheck::failed(
+                "version",
+                &format!("{} is too old, {} is needed", binary.message, MIN_TMUX_VERSION),
+                &format!("upgrade tmux to {} or newer", MIN_TMUX_VERSION),
+            )
+        };
+        let server = match self.client.list_sessions() {
+            Ok(sessions) => HealthCheck::ok("server", &format!("{} sessions", sessions.len())),
+            Err(e) => HealthCheck::failed(
+                "server",
+                &e,
+                "check TMUX_TMPDIR and the permissions of the tmux socket directory",
+            ),
+        };
+        vec![binary, version, server]
     }
-    fn run_action(&self, action: &str, project: &mut Project, arguments: &Map<String,Value>) -> Result<String,String> {
+    fn configure(&mut self, config: &Map<String, Value>) -> Result<(), String> {
+        self.client = match config.get("socket").and_then(|s| s.as_str()) {
+            Some(socket) => TmuxClient::with_socket(socket),
+            None => TmuxClient::new(),
+        };
+        Ok(())
+    }
+    fn list_actions(&self) -> Vec<ActionDescriptor> {
+        vec![
+            ActionDescriptor::new(
+                "gen_init_terminal",
+                "print the command attaching a terminal to the project session",
+            )
+            .needs_project()
+            .param(context_param()),
+            ActionDescriptor::new("sync", "recreate the project session if it is missing")
+                .needs_project()
+                .param(context_param()),
+            ActionDescriptor::new(
+                "attach",
+                "print the command to attach to the project session, or to switch to it from inside tmux",
+            )
+            .needs_project()
+            .param(context_param()),
+            ActionDescriptor::new(
+                "switch-client",
+                "move the tmux client we are called from to the project session",
+            )
+            .needs_project()
+            .param(context_param()),
+            ActionDescriptor::new("send-keys", "send keys to a window or pane of the project session")
+                .needs_project()
+                .param(context_param())
+                .param(
+                    ParamDescriptor::positional("target", "window or pane, like editor or editor.1")
+                        .required(),
+                )
+                .param(
+                    ParamDescriptor::positional("keys", "tmux keys, like \"cargo build\" Enter or C-c")
+                        .required()
+                        .multiple(),
+                ),
+            ActionDescriptor::new("capture-pane", "print the recent output of a pane")
+                .needs_project()
+                .param(context_param())
+                .param(ParamDescriptor::positional(
+                    "target",
+                    "window or pane, the current one if omitted",
+                ))
+                .param(
+                    ParamDescriptor::named("lines", "how many lines of history to include")
+                        .kind(ParamKind::Integer)
+                        .min(0),
+                ),
+            ActionDescriptor::new("run", "run a command in a new window of the project session")
+                .needs_project()
+                .param(context_param())
+                .param(
+                    ParamDescriptor::positional("command", "the command and its arguments")
+                        .required()
+                        .multiple(),
+                )
+                .param(ParamDescriptor::named(
+                    "name",
+                    "window name, the command name if omitted",
+                )),
+        ]
+    }
+    fn run_action(&self, action: &str, project: Option<&mut Project>, arguments: &Map<String,Value>) -> Result<String,String> {
+        let project = project.ok_or("a project is needed".to_string())?;
+        if !project.plugins.contains(&"tmux".to_string()) {
+            return Err("tmux plugin is not enabled".to_string());
+        }
+        let (owner, path) = TmuxPlugin::action_target(project, arguments)?;
         match action {
             "gen_init_terminal" => {
-                let session_name = project.name.clone();
-                if project.plugins.contains(&"tmux".to_string()) {
-                    Ok(format!("tmux attach-session -t {}", session_name))
+                let session = self.running_session(&owner)?;
+                Ok(self
+                    .client
+                    .shell_command(&["attach-session", "-t", &exact_target(&session.name)]))
+            }
+            "attach" => {
+                // prints the command to run in the user terminal: inside tmux we cannot nest
+                // sessions, so we move the client instead
+                let session = self.running_session(&owner)?;
+                let command = if arguments.contains_key("client-tmux") {
+                    "switch-client"
                 } else {
-                    Err("tmux plugin is not enabled".to_string())
-                }
+                    "attach-session"
+                };
+                Ok(self
+                    .client
+                    .shell_command(&[command, "-t", &exact_target(&session.name)]))
+            }
+            "switch-client" => {
+                let session = self.running_session(&owner)?;
+                // the pane we were called from, forwarded by the cli
+                let pane = arguments
+                    .get("client-pane")
+                    .and_then(|p| p.as_str())
+                    .ok_or("switch-client must be run from inside tmux".to_string())?;
+                let client = self.client.pane_client(pane)?;
+                self.client
+                    .switch_client(&client, &exact_target(&session.name))?;
+                Ok(format!("switched to tmux session {}", session.name))
+            }
+            "send-keys" => {
+                let session = self.running_session(&owner)?;
+                let target = action_arg(arguments, "target")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("");
+                let keys = action_arg_list(arguments, "keys");
+                let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+                self.client
+                    .send_keys(&pane_target(&session.name, target), &keys)?;
+                Ok(format!("keys sent to {}:{}", session.name, target))
+            }
+            "capture-pane" => {
+                let session = self.running_session(&owner)?;
+                let target = action_arg(arguments, "target")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("");
+                let lines = action_arg(arguments, "lines")
+                    .and_then(|l| l.as_u64())
+                    .unwrap_or(DEFAULT_CAPTURE_LINES);
+                self.client
+                    .capture_pane(&pane_target(&session.name, target), lines)
+            }
+            "run" => {
+                let session = self.running_session(&owner)?;
+                let command = action_arg_list(arguments, "command");
+                let command: Vec<&str> = command.iter().map(|c| c.as_str()).collect();
+                let window_name = action_arg(arguments, "name")
+                    .and_then(|n| n.as_str())
+                    .or(command.first().copied())
+                    .ok_or("a command is needed".to_string())?;
+                let environment = TmuxPlugin::environment(project, path.as_deref(), arguments);
+                let pane = self.client.new_window(
+                    &session.name,
+                    window_name,
+                    path.as_deref(),
+                    &environment,
+                    &command,
+                )?;
+                Ok(format!("command started in {}", pane))
             }
+            "sync" => match self.find_session(&owner) {
+                Some(session) => Ok(format!("tmux session {} is running", session.name)),
+                None => self
+                    .create_session(
+                        &owner,
+                        path.as_deref(),
+                        &TmuxPlugin::environment(project, path.as_deref(), arguments),
+                    )
+                    .map(|created| format!("tmux session recreated: {}", created)),
+            },
             _ => {
                 Err(format!("unknown action: {}", action))
             }
         }
     }
+    fn status(&self, project: &Project, arguments: &Map<String, Value>) -> Option<String> {
+        let status = match self.find_session(&project.name) {
+            Some(session) if session.name != session_name(&project.name) => {
+                format!("session renamed to {}", session.name)
+            }
+            Some(session) if session.attached => format!("session {} attached", session.name),
+            Some(session) => format!("session {} detached", session.name),
+            None => "session missing".to_string(),
+        };
+        Some(status)
+    }
+    fn reconcile(&self, projects: &mut [Project]) -> Vec<String> {
+        let sessions = match self.client.list_sessions() {
+            Ok(sessions) => sessions,
+            Err(e) => return vec![e],
+        };
+        let mut report = Vec::new();
+        let enabled: Vec<&Project> = projects
+            .iter()
+            .filter(|p| p.plugins.contains(&"tmux".to_string()))
+            .collect();
+        for project in &enabled {
+            let expected = session_name(&project.name);
+            let tagged = sessions
+                .iter()
+                .find(|s| s.project.as_deref() == Some(project.name.as_str()));
+            match tagged {
+                Some(session) if session.name != expected => report.push(format!(
+                    "tmux session of project {} was renamed from {} to {}",
+                    project.name, expected, session.name
+                )),
+                Some(_) => {}
+                None if sessions.iter().any(|s| s.project.is_none() && s.name == expected) => {}
+                None => report.push(format!(
+                    "tmux session of project {} is missing, recreate it with `project-pilot plugin run tmux sync {}`",
+                    project.name, project.name
+                )),
+            }
+        }
+        for session in &sessions {
+            let owner = match &session.project {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let (project_name, context) = split_context(owner);
+            match enabled.iter().find(|p| p.name == project_name) {
+                None => report.push(format!(
+                    "tmux session {} is orphaned, project {} does not use tmux anymore",
+                    session.name, project_name
+                )),
+                Some(project) => {
+                    if let Some(context) = context.filter(|c| project.context(c).is_none()) {
+                        report.push(format!(
+                            "tmux session {} is orphaned, project {} has no context {} anymore",
+                            session.name, project_name, context
+                        ));
+                    }
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tmux_client::tests::TestServer;
+
+    #[test]
+    fn create_session_detects_name_collisions() {
+        let server = TestServer::start("collision");
+        let plugin = TmuxPlugin {
+            client: server.client.clone(),
+        };
+        plugin.create_session("a.b", None, &[]).unwrap();
+        let error = plugin.create_session("a_b", None, &[]).unwrap_err();
+        assert!(error.contains("taken by a.b"), "{}", error);
+        assert_eq!(plugin.find_session("a.b").unwrap().name, "a_b");
+        assert!(plugin.find_session("a_b").is_none());
+    }
 }
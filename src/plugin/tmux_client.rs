@@ -0,0 +1,364 @@
+use std::process::{Command, Output};
+
+// user option used to remember which project a session belongs to, it survives renames
+pub const PROJECT_OPTION: &str = "@project-pilot-project";
+
+// the fields we read back from `tmux list-sessions -F`, separated by tabs
+fn session_format() -> String {
+    format!(
+        "#{{session_id}}\t#{{session_windows}}\t#{{session_attached}}\t#{{{}}}\t#{{session_path}}\t#{{session_name}}",
+        PROJECT_OPTION
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TmuxSession {
+    pub id: String,
+    pub name: String,
+    pub windows: u32,
+    pub attached: bool,
+    pub project: Option<String>,
+    pub path: String,
+}
+
+// talks to a tmux server passing every argument directly, without going through a shell
+#[derive(Debug, Clone, Default)]
+pub struct TmuxClient {
+    socket_name: Option<String>,
+}
+
+impl TmuxClient {
+    pub fn new() -> Self {
+        TmuxClient { socket_name: None }
+    }
+
+    // use an isolated server, like `tmux -L <socket_name>`
+    pub fn with_socket(socket_name: &str) -> Self {
+        TmuxClient {
+            socket_name: Some(socket_name.to_string()),
+        }
+    }
+
+    fn base_args(&self) -> Vec<String> {
+        match &self.socket_name {
+            Some(socket_name) => vec!["-L".to_string(), socket_name.clone()],
+            None => vec![],
+        }
+    }
+
+    // -u: without it, and without a UTF-8 locale or $TMUX, tmux prints the tabs of our
+    // formats as '_'
+    fn output(&self, args: &[&str]) -> Result<Output, String> {
+        Command::new("tmux")
+            .arg("-u")
+            .args(self.base_args())
+            .args(args)
+            .output()
+            .map_err(|e| format!("failed to execute tmux: {}", e))
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, String> {
+        let output = self.output(args)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!(
+                "tmux {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    pub fn has_session(&self, name: &str) -> bool {
+        match self.output(&["has-session", "-t", &exact_target(name)]) {
+            Ok(output) => output.status.success(),
+            Err(_) => false,
+        }
+    }
+
+    // creates a detached session and returns what tmux prints with -P
+    // the environment is given to every pane of the session
+    pub fn new_session(
+        &self,
+        name: &str,
+        start_directory: Option<&str>,
+        environment: &[(String, String)],
+    ) -> Result<String, String> {
+        let mut args = vec!["new-session", "-d", "-P", "-s", name];
+        if let Some(start_directory) = start_directory {
+            args.push("-c");
+            args.push(start_directory);
+        }
+        let variables = environment_args(environment);
+        args.extend(variables.iter().map(|v| v.as_str()));
+        self.run(&args).map(|out| out.trim().to_string())
+    }
+
+    pub fn kill_session(&self, name: &str) -> Result<(), String> {
+        self.run(&["kill-session", "-t", &exact_target(name)])
+            .map(|_| ())
+    }
+
+    pub fn set_session_option(&self, name: &str, option: &str, value: &str) -> Result<(), String> {
+        // set-option takes a pane target, the trailing ':' selects the session itself
+        let target = format!("{}:", exact_target(name));
+        self.run(&["set-option", "-t", &target, option, value])
+            .map(|_| ())
+    }
+
+    // the tty of the client showing the pane, as `switch-client -c` wants it
+    pub fn pane_client(&self, pane: &str) -> Result<String, String> {
+        let tty = self.run(&["display-message", "-p", "-t", pane, "#{client_tty}"])?;
+        match tty.trim() {
+            "" => Err(format!("no tmux client is showing pane {}", pane)),
+            tty => Ok(tty.to_string()),
+        }
+    }
+
+    pub fn switch_client(&self, client: &str, target: &str) -> Result<(), String> {
+        self.run(&["switch-client", "-c", client, "-t", target])
+            .map(|_| ())
+    }
+
+    pub fn send_keys(&self, target: &str, keys: &[&str]) -> Result<(), String> {
+        let mut args = vec!["send-keys", "-t", target];
+        args.extend(keys);
+        self.run(&args).map(|_| ())
+    }
+
+    // the last `lines` lines of the pane history, plus what is visible
+    pub fn capture_pane(&self, target: &str, lines: u64) -> Result<String, String> {
+        let start = format!("-{}", lines);
+        self.run(&["capture-pane", "-p", "-J", "-t", target, "-S", &start])
+    }
+
+    // opens a window in the session running `command`, returns the new pane target.
+    // a single word is run by the shell, more words are executed directly
+    pub fn new_window(
+        &self,
+        session_name: &str,
+        window_name: &str,
+        start_directory: Option<&str>,
+        environment: &[(String, String)],
+        command: &[&str],
+    ) -> Result<String, String> {
+        let target = format!("{}:", exact_target(session_name));
+        let mut args = vec!["new-window", "-d", "-P", "-t", &target, "-n", window_name];
+        if let Some(start_directory) = start_directory {
+            args.push("-c");
+            args.push(start_directory);
+        }
+        let variables = environment_args(environment);
+        args.extend(variables.iter().map(|v| v.as_str()));
+        args.extend(command);
+        self.run(&args).map(|out| out.trim().to_string())
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<TmuxSession>, String> {
+        let output = self.output(&["list-sessions", "-F", &session_format()])?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // no server simply means no sessions
+            if stderr.contains("no server running") || stderr.contains("error connecting to") {
+                return Ok(vec![]);
+            }
+            return Err(format!("tmux list-sessions failed: {}", stderr.trim()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_session)
+            .collect())
+    }
+
+    // renders a tmux invocation as a line that can be pasted into a shell
+    pub fn shell_command(&self, args: &[&str]) -> String {
+        let mut words = vec!["tmux".to_string()];
+        words.extend(self.base_args().iter().map(|arg| shell_quote(arg)));
+        words.extend(args.iter().map(|arg| shell_quote(arg)));
+        words.join(" ")
+    }
+}
+
+fn parse_session(line: &str) -> Option<TmuxSession> {
+    let mut fields = line.splitn(6, '\t');
+    let id = fields.next()?.to_string();
+    let windows = fields.next()?.parse().ok()?;
+    let attached = fields.next()? != "0";
+    let project = Some(fields.next()?.to_string()).filter(|p| !p.is_empty());
+    let path = fields.next()?.to_string();
+    let name = fields.next()?.to_string();
+    Some(TmuxSession {
+        id,
+        name,
+        windows,
+        attached,
+        project,
+        path,
+    })
+}
+
+// "=name" makes tmux match the session name exactly instead of by prefix
+pub fn exact_target(name: &str) -> String {
+    format!("={}", name)
+}
+
+// a window or pane of a session, like "editor" or "editor.1"; empty means the current window
+pub fn pane_target(session_name: &str, target: &str) -> String {
+    format!("{}:{}", exact_target(session_name), target)
+}
+
+// tmux does not allow '.' and ':' in session names, they are target separators
+pub fn session_name(project_name: &str) -> String {
+    project_name
+        .chars()
+        .map(|c| match c {
+            '.' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// `-e NAME=value` for each variable
+fn environment_args(environment: &[(String, String)]) -> Vec<String> {
+    environment
+        .iter()
+        .flat_map(|(name, value)| ["-e".to_string(), format!("{}={}", name, value)])
+        .collect()
+}
+
+pub fn shell_quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=@%+,".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // an isolated tmux server, killed with the guard
+    pub struct TestServer {
+        pub client: TmuxClient,
+        socket_name: String,
+    }
+
+    impl TestServer {
+        pub fn start(name: &str) -> Self {
+            let socket_name = format!("project-pilot-test-{}-{}", std::process::id(), name);
+            TestServer {
+                client: TmuxClient::with_socket(&socket_name),
+                socket_name,
+            }
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = self.client.output(&["kill-server"]);
+            // tmux leaves the socket file behind, in $TMUX_TMPDIR/tmux-<uid>
+            let uid = Command::new("id").arg("-u").output();
+            if let Ok(uid) = uid {
+                let directory = std::env::var("TMUX_TMPDIR").unwrap_or("/tmp".to_string());
+                let _ = std::fs::remove_file(format!(
+                    "{}/tmux-{}/{}",
+                    directory,
+                    String::from_utf8_lossy(&uid.stdout).trim(),
+                    self.socket_name
+                ));
+            }
+        }
+    }
+
+    const AWKWARD_NAME: &str = "it's a \"project\"; with spaces";
+
+    #[test]
+    fn session_name_replaces_target_separators() {
+        assert_eq!(session_name("a.b:c"), "a_b_c");
+        assert_eq!(session_name("tab\there"), "tab_here");
+        assert_eq!(session_name(AWKWARD_NAME), AWKWARD_NAME);
+        assert_eq!(session_name("project/context"), "project/context");
+    }
+
+    #[test]
+    fn parse_session_keeps_tabs_in_the_name() {
+        let line = format!("$1\t2\t1\t{}\t/tmp/a b\tname\twith tab", AWKWARD_NAME);
+        let session = parse_session(&line).unwrap();
+        assert_eq!(session.id, "$1");
+        assert_eq!(session.windows, 2);
+        assert!(session.attached);
+        assert_eq!(session.project.as_deref(), Some(AWKWARD_NAME));
+        assert_eq!(session.path, "/tmp/a b");
+        assert_eq!(session.name, "name\twith tab");
+        assert_eq!(parse_session("$1\t2\t0\t\t/tmp\tname").unwrap().project, None);
+        assert_eq!(parse_session("$1\tnot a number\t0\t\t/tmp\tname"), None);
+    }
+
+    #[test]
+    fn list_sessions_without_server_is_empty() {
+        let server = TestServer::start("empty");
+        assert_eq!(server.client.list_sessions(), Ok(vec![]));
+    }
+
+    #[test]
+    fn new_session_on_isolated_server() {
+        let server = TestServer::start("new");
+        let client = &server.client;
+        let directory = std::env::temp_dir();
+        let directory = directory.to_str().unwrap();
+        let environment = vec![("PROJECT_PILOT_TEST".to_string(), "a; b".to_string())];
+        client
+            .new_session(AWKWARD_NAME, Some(directory), &environment)
+            .unwrap();
+        assert!(client.has_session(AWKWARD_NAME));
+        // exact targets, no prefix matching
+        assert!(!client.has_session("it's"));
+        client
+            .set_session_option(AWKWARD_NAME, PROJECT_OPTION, AWKWARD_NAME)
+            .unwrap();
+
+        let sessions = client.list_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.name, AWKWARD_NAME);
+        assert_eq!(session.project.as_deref(), Some(AWKWARD_NAME));
+        assert_eq!(session.windows, 1);
+        assert!(!session.attached);
+        assert_eq!(
+            std::fs::canonicalize(&session.path).unwrap(),
+            std::fs::canonicalize(directory).unwrap()
+        );
+        let shown = client
+            .run(&["show-environment", "-t", &exact_target(AWKWARD_NAME), "PROJECT_PILOT_TEST"])
+            .unwrap();
+        assert_eq!(shown.trim(), "PROJECT_PILOT_TEST=a; b");
+
+        client.kill_session(AWKWARD_NAME).unwrap();
+        assert!(!client.has_session(AWKWARD_NAME));
+    }
+
+    #[test]
+    fn pane_client_needs_an_attached_client() {
+        let server = TestServer::start("client");
+        let client = &server.client;
+        client.new_session("detached", None, &[]).unwrap();
+        let pane = client
+            .run(&["display-message", "-p", "-t", "=detached:", "#{pane_id}"])
+            .unwrap();
+        let error = client.pane_client(pane.trim()).unwrap_err();
+        assert!(error.contains("no tmux client"), "{}", error);
+    }
+
+    #[test]
+    fn shell_command_quotes_awkward_words() {
+        let client = TmuxClient::with_socket("sock et");
+        assert_eq!(
+            client.shell_command(&["new-session", "-s", AWKWARD_NAME]),
+            "tmux -L 'sock et' new-session -s 'it'\\''s a \"project\"; with spaces'"
+        );
+    }
+}
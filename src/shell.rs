@@ -0,0 +1,275 @@
+use crate::event::Event;
+use clap::{Arg, Command};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use strum::IntoEnumIterator;
+
+// snippets printed by `shell-init`, to be evaluated by the shell at startup.
+// Before each prompt they ask the daemon for the current project, put in $PROJECT_PILOT_PROJECT,
+// and after a cd for the project of the new directory, put in $PROJECT_PILOT_DIRECTORY_PROJECT.
+// Both are empty when there is none, or when the daemon is not running
+const BASH: &str = r#"__project_pilot_bin=@BIN@
+__project_pilot_hook() {
+    local status=$?
+    if [ "$PWD" != "$__project_pilot_directory" ]; then
+        __project_pilot_directory=$PWD
+        PROJECT_PILOT_DIRECTORY_PROJECT=$("$__project_pilot_bin" project resolve @SWITCH@"$PWD" 2>/dev/null)
+        case "$PROJECT_PILOT_DIRECTORY_PROJECT" in "No project "*) PROJECT_PILOT_DIRECTORY_PROJECT= ;; esac
+    fi
+    PROJECT_PILOT_PROJECT=$("$__project_pilot_bin" project current 2>/dev/null)
+    case "$PROJECT_PILOT_PROJECT" in "No current project") PROJECT_PILOT_PROJECT= ;; esac
+    return $status
+}
+__project_pilot_prompt() {
+    [ -n "$PROJECT_PILOT_PROJECT" ] && printf '(%s) ' "$PROJECT_PILOT_PROJECT"
+}
+case ";$PROMPT_COMMAND;" in
+    *";__project_pilot_hook;"*) ;;
+    *)
+        PROMPT_COMMAND="__project_pilot_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+        @PROMPT@PS1='$(__project_pilot_prompt)'"$PS1"
+        ;;
+esac
+"#;
+
+const ZSH: &str = r#"__project_pilot_bin=@BIN@
+__project_pilot_hook() {
+    if [[ "$PWD" != "$__project_pilot_directory" ]]; then
+        __project_pilot_directory=$PWD
+        PROJECT_PILOT_DIRECTORY_PROJECT=$("$__project_pilot_bin" project resolve @SWITCH@"$PWD" 2>/dev/null)
+        [[ "$PROJECT_PILOT_DIRECTORY_PROJECT" == "No project "* ]] && PROJECT_PILOT_DIRECTORY_PROJECT=
+    fi
+    PROJECT_PILOT_PROJECT=$("$__project_pilot_bin" project current 2>/dev/null)
+    [[ "$PROJECT_PILOT_PROJECT" == "No current project" ]] && PROJECT_PILOT_PROJECT=
+}
+__project_pilot_prompt() {
+    [[ -n "$PROJECT_PILOT_PROJECT" ]] && print -rn -- "(${PROJECT_PILOT_PROJECT//\%/%%}) "
+}
+autoload -Uz add-zsh-hook
+if (( ! ${precmd_functions[(I)__project_pilot_hook]} )); then
+    add-zsh-hook precmd __project_pilot_hook
+    @PROMPT@setopt prompt_subst; PROMPT='$(__project_pilot_prompt)'"$PROMPT"
+fi
+"#;
+
+const FISH: &str = r#"set -g __project_pilot_bin @BIN@
+function __project_pilot_hook --on-event fish_prompt
+    if test "$PWD" != "$__project_pilot_directory"
+        set -g __project_pilot_directory $PWD
+        set -g PROJECT_PILOT_DIRECTORY_PROJECT ($__project_pilot_bin project resolve @SWITCH@$PWD 2>/dev/null)
+        string match -q 'No project *' -- "$PROJECT_PILOT_DIRECTORY_PROJECT"; and set -g PROJECT_PILOT_DIRECTORY_PROJECT
+    end
+    set -g PROJECT_PILOT_PROJECT ($__project_pilot_bin project current 2>/dev/null)
+    test "$PROJECT_PILOT_PROJECT" = 'No current project'; and set -g PROJECT_PILOT_PROJECT
+end
+function __project_pilot_prompt
+    test -n "$PROJECT_PILOT_PROJECT"; and printf '(%s) ' $PROJECT_PILOT_PROJECT
+end
+@PROMPT@if not functions -q __project_pilot_original_prompt
+@PROMPT@    functions -c fish_prompt __project_pilot_original_prompt
+@PROMPT@    function fish_prompt
+@PROMPT@        __project_pilot_prompt
+@PROMPT@        __project_pilot_original_prompt
+@PROMPT@    end
+@PROMPT@end
+"#;
+
+// a single quoted word, `'` is closed, escaped and reopened
+fn quote(word: &str, shell: &str) -> String {
+    match shell {
+        "fish" => format!("'{}'", word.replace('\\', "\\\\").replace('\'', "\\'")),
+        _ => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+pub fn init_script(shell: &str, binary: &str, auto_switch: bool, prompt: bool) -> String {
+    let template = match shell {
+        "zsh" => ZSH,
+        "fish" => FISH,
+        _ => BASH,
+    };
+    let script = template
+        .replace("@BIN@", &quote(binary, shell))
+        .replace("@SWITCH@", if auto_switch { "--switch " } else { "" });
+    // the prompt lines are marked, and left out with --no-prompt
+    script
+        .lines()
+        .filter_map(|line| match line.trim_start().strip_prefix("@PROMPT@") {
+            Some(_) if !prompt => None,
+            Some(_) => Some(line.replacen("@PROMPT@", "", 1)),
+            None => Some(line.to_string()),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+// registered by `completions`, they hand the words typed so far to `__complete`, which prints
+// the candidates for the last one, and fall back to file names when there are none
+const BASH_COMPLETION: &str = r#"__project_pilot_complete() {
+    local IFS=$'\n'
+    COMPREPLY=($(@BIN@ __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F __project_pilot_complete @NAME@
+"#;
+
+const ZSH_COMPLETION: &str = r#"__project_pilot_complete() {
+    local -a candidates
+    candidates=("${(@f)$(@BIN@ __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    candidates=(${candidates:#})
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _files
+    fi
+}
+(( $+functions[compdef] )) && compdef __project_pilot_complete @NAME@
+"#;
+
+const FISH_COMPLETION: &str = r#"function __project_pilot_complete
+    set -l words (commandline -opc)
+    set -l current (commandline -ct)
+    set -l candidates (@BIN@ __complete -- $words[2..-1] "$current" 2>/dev/null)
+    if test (count $candidates) -eq 0
+        __fish_complete_path "$current"
+    else
+        printf '%s\n' $candidates
+    end
+end
+complete -c @NAME@ -f -a '(__project_pilot_complete)'
+"#;
+
+pub fn completion_script(shell: &str, binary: &str) -> String {
+    let template = match shell {
+        "zsh" => ZSH_COMPLETION,
+        "fish" => FISH_COMPLETION,
+        _ => BASH_COMPLETION,
+    };
+    let name = Path::new(binary)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or("project-pilot".to_string());
+    template
+        .replace("@BIN@", &quote(binary, shell))
+        .replace("@NAME@", &quote(&name, shell))
+}
+
+// what the word being completed can be
+pub enum Completion {
+    Words(Vec<String>),
+    // values known by the daemon, asked with `complete <kind>`
+    Daemon(&'static str, Map<String, Value>),
+    // the arguments of `plugin run <plugin> <action>`, described by the plugin
+    ActionArgs {
+        plugin: String,
+        action: String,
+        words: Vec<String>,
+    },
+    Files,
+}
+
+// follows the clap command tree along the words, the last one being completed
+pub fn completion_for(root: &Command, words: &[String]) -> Completion {
+    let (current, typed) = words.split_last().map(|(c, t)| (c.as_str(), t)).unwrap_or(("", &[]));
+    let mut command = root;
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut trailing: Vec<String> = vec![];
+    let mut positional = 0;
+    let mut flag_value: Option<&Arg> = None;
+    for word in typed {
+        if flag_value.take().is_some() {
+            continue;
+        }
+        // everything after `plugin run <plugin> <action>` belongs to the action
+        let positionals: Vec<&Arg> = command.get_positionals().collect();
+        if positionals.get(positional).is_some_and(|a| a.is_trailing_var_arg_set()) {
+            trailing.push(word.clone());
+            continue;
+        }
+        if let Some(long) = word.strip_prefix("--") {
+            flag_value = command
+                .get_arguments()
+                .find(|a| a.get_long() == Some(long))
+                .filter(|a| a.get_action().takes_values());
+            continue;
+        }
+        if positional == 0 {
+            if let Some(subcommand) = command.find_subcommand(word) {
+                command = subcommand;
+                continue;
+            }
+        }
+        if let Some(arg) = positionals.get(positional) {
+            values.insert(arg.get_id().to_string(), word.clone());
+            positional += 1;
+        }
+    }
+
+    let possible_values = |arg: &Arg| -> Vec<String> {
+        arg.get_possible_values()
+            .iter()
+            .map(|v| v.get_name().to_string())
+            .collect()
+    };
+    if let Some(arg) = flag_value {
+        let values = possible_values(arg);
+        return if values.is_empty() { Completion::Files } else { Completion::Words(values) };
+    }
+    let arg = command.get_positionals().nth(positional);
+    let in_trailing = arg.is_some_and(|a| a.is_trailing_var_arg_set());
+    if current.starts_with('-') && !in_trailing {
+        let flags = command
+            .get_arguments()
+            .filter(|a| !a.is_hide_set())
+            .filter_map(|a| a.get_long())
+            .map(|long| format!("--{}", long))
+            .chain(std::iter::once("--help".to_string()))
+            .collect();
+        return Completion::Words(flags);
+    }
+    if positional == 0 && command.has_subcommands() {
+        let names = command
+            .get_subcommands()
+            .filter(|c| !c.is_hide_set())
+            .map(|c| c.get_name().to_string())
+            .collect();
+        return Completion::Words(names);
+    }
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return Completion::Words(vec![]),
+    };
+    if !possible_values(arg).is_empty() {
+        return Completion::Words(possible_values(arg));
+    }
+    let mut params = Map::new();
+    let value = |name: &str| values.get(name).cloned();
+    if let Some(project) = value("project-name") {
+        params.insert("project-name".to_string(), json!(project));
+    }
+    if let Some(plugin) = value("plugin") {
+        params.insert("plugin".to_string(), json!(plugin));
+    }
+    match (command.get_name(), arg.get_id().as_str()) {
+        ("run", "args") => Completion::ActionArgs {
+            plugin: value("plugin").unwrap_or_default(),
+            action: value("action").unwrap_or_default(),
+            words: trailing,
+        },
+        ("switch", "project-name") => {
+            params.insert("with-contexts".to_string(), json!(true));
+            Completion::Daemon("projects", params)
+        }
+        (_, "project-name") => Completion::Daemon("projects", params),
+        // only the enabled ones can be disabled
+        ("disable-plugin", "plugin") => Completion::Daemon("plugins", params),
+        (_, "plugin") => Completion::Daemon("plugins", Map::new()),
+        (_, "property") => Completion::Daemon("properties", params),
+        (_, "context") => Completion::Daemon("contexts", params),
+        (_, "action") => Completion::Daemon("actions", params),
+        (_, "key") => Completion::Daemon("settings", params),
+        (_, "event-name") => Completion::Words(Event::iter().map(|e| e.to_string()).collect()),
+        (_, "path") => Completion::Files,
+        _ => Completion::Words(vec![]),
+    }
+}
@@ -0,0 +1,655 @@
+use crate::cli::{action_arguments, action_command};
+use crate::config::Context;
+use crate::plugin::ActionDescriptor;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+// `project-pilot tui`: the projects with their plugins, properties and status, kept up to date
+// by the events the daemon streams, and a periodic refresh for what happens outside of it,
+// like a tmux session being killed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const EVENT_LOG_LENGTH: usize = 5;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ProjectView {
+    name: String,
+    current: bool,
+    plugins: Vec<String>,
+    properties: BTreeMap<String, String>,
+    contexts: Vec<Context>,
+    status: BTreeMap<String, String>,
+    #[serde(rename = "time-today")]
+    time_today: i64,
+}
+
+// what the background threads report
+enum Update {
+    Overview(Result<Vec<ProjectView>, String>),
+    Plugins(Vec<String>),
+    Actions(Vec<(String, ActionDescriptor)>),
+    Reply(String),
+    Event(Value),
+    Disconnected(String),
+}
+
+enum Purpose {
+    SetProperty(String),
+    NewProperty,
+    ActionArgs(String, ActionDescriptor),
+}
+
+enum Mode {
+    Projects,
+    Plugins(ListState),
+    Properties(ListState),
+    Actions(ListState),
+    Input {
+        prompt: String,
+        buffer: String,
+        purpose: Purpose,
+    },
+}
+
+struct Dashboard {
+    socket_path: String,
+    updates: Sender<Update>,
+    projects: Vec<ProjectView>,
+    plugins: Vec<String>,
+    actions: Vec<(String, ActionDescriptor)>,
+    selected: ListState,
+    mode: Mode,
+    message: String,
+    // the output of the last action
+    output: Option<String>,
+    events: Vec<String>,
+    refreshing: bool,
+    stale: bool,
+    last_refresh: Instant,
+    quit: bool,
+}
+
+fn send_frame(stream: &mut UnixStream, message: &Value) -> Result<(), String> {
+    let payload = message.to_string();
+    stream
+        .write_all(&(payload.len() as u32).to_be_bytes())
+        .and_then(|_| stream.write_all(payload.as_bytes()))
+        .map_err(|e| format!("cannot write to the daemon: {}", e))
+}
+
+fn read_frame(stream: &mut UnixStream) -> Result<Value, String> {
+    let mut length = [0u8; 4];
+    stream
+        .read_exact(&mut length)
+        .map_err(|e| format!("the daemon closed the connection: {}", e))?;
+    let mut payload = vec![0u8; u32::from_be_bytes(length) as usize];
+    stream
+        .read_exact(&mut payload)
+        .map_err(|e| format!("cannot read from the daemon: {}", e))?;
+    serde_json::from_slice(&payload).map_err(|e| format!("invalid message from the daemon: {}", e))
+}
+
+// a request on its own connection, the dashboard does not wait for the daemon
+fn request(socket_path: &str, subject: &str, command: &str, params: Value) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket_path)
+        .map_err(|e| format!("cannot connect to the daemon: {}", e))?;
+    send_frame(
+        &mut stream,
+        &json!({"subject": subject, "command": command, "params": params}),
+    )?;
+    let reply = read_frame(&mut stream)?;
+    Ok(reply["lines"].as_str().unwrap_or_default().to_string())
+}
+
+fn subscribe(socket_path: String, updates: Sender<Update>) {
+    std::thread::spawn(move || {
+        let result = UnixStream::connect(&socket_path)
+            .map_err(|e| format!("cannot connect to the daemon: {}", e))
+            .and_then(|mut stream| {
+                send_frame(
+                    &mut stream,
+                    &json!({"subject": "event", "command": "subscribe", "params": {}}),
+                )?;
+                loop {
+                    let message = read_frame(&mut stream)?;
+                    let event = serde_json::from_str(message["lines"].as_str().unwrap_or("null"))
+                        .unwrap_or(Value::Null);
+                    if updates.send(Update::Event(event)).is_err() {
+                        return Ok(());
+                    }
+                }
+            });
+        if let Err(e) = result {
+            let _ = updates.send(Update::Disconnected(e));
+        }
+    });
+}
+
+fn format_duration(seconds: i64) -> String {
+    match seconds {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}m", s / 60),
+    }
+}
+
+fn move_selection(state: &mut ListState, length: usize, delta: i64) {
+    if length == 0 {
+        state.select(None);
+        return;
+    }
+    let selected = state.selected().unwrap_or(0) as i64 + delta;
+    state.select(Some(selected.clamp(0, length as i64 - 1) as usize));
+}
+
+impl Dashboard {
+    fn new(socket_path: &str, updates: Sender<Update>) -> Self {
+        Dashboard {
+            socket_path: socket_path.to_string(),
+            updates,
+            projects: vec![],
+            plugins: vec![],
+            actions: vec![],
+            selected: ListState::default(),
+            mode: Mode::Projects,
+            message: String::new(),
+            output: None,
+            events: vec![],
+            refreshing: false,
+            stale: false,
+            last_refresh: Instant::now(),
+            quit: false,
+        }
+    }
+
+    fn project(&self) -> Option<&ProjectView> {
+        self.selected.selected().and_then(|i| self.projects.get(i))
+    }
+
+    fn project_name(&self) -> String {
+        self.project().map(|p| p.name.clone()).unwrap_or_default()
+    }
+
+    // runs a request in the background, its answer comes back as an update
+    fn spawn<F>(&self, work: F)
+    where
+        F: FnOnce(&str) -> Update + Send + 'static,
+    {
+        let socket_path = self.socket_path.clone();
+        let updates = self.updates.clone();
+        std::thread::spawn(move || {
+            let _ = updates.send(work(&socket_path));
+        });
+    }
+
+    fn refresh(&mut self) {
+        self.last_refresh = Instant::now();
+        if self.refreshing {
+            self.stale = true;
+            return;
+        }
+        self.refreshing = true;
+        // only the selected project shows the status of its plugins
+        let params = json!({"status-of": self.project_name()});
+        self.spawn(move |socket_path| {
+            Update::Overview(
+                request(socket_path, "project", "overview", params).and_then(|overview| {
+                    serde_json::from_str(&overview).map_err(|_| overview.to_string())
+                }),
+            )
+        });
+    }
+
+    // a command changing something, the overview is refreshed after it
+    fn send(&mut self, subject: &'static str, command: &'static str, params: Value) {
+        self.message = format!("{} {}...", subject, command);
+        self.spawn(move |socket_path| {
+            Update::Reply(request(socket_path, subject, command, params).unwrap_or_else(|e| e))
+        });
+    }
+
+    fn load_plugins(&self) {
+        self.spawn(|socket_path| {
+            let plugins = request(socket_path, "complete", "plugins", json!({})).unwrap_or_default();
+            Update::Plugins(plugins.lines().map(String::from).collect())
+        });
+    }
+
+    fn load_actions(&self, plugins: Vec<String>) {
+        self.spawn(move |socket_path| {
+            let mut actions = vec![];
+            for plugin in plugins {
+                let params = json!({"plugin": plugin});
+                let names = request(socket_path, "complete", "actions", params).unwrap_or_default();
+                for action in names.lines() {
+                    let params = json!({"plugin": plugin, "action": action});
+                    let descriptor = request(socket_path, "plugin", "describe-action", params)
+                        .ok()
+                        .and_then(|d| serde_json::from_str::<ActionDescriptor>(&d).ok());
+                    if let Some(descriptor) = descriptor {
+                        actions.push((plugin.clone(), descriptor));
+                    }
+                }
+            }
+            Update::Actions(actions)
+        });
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Overview(Ok(projects)) => {
+                // keeps the selection on the same project
+                let name = self.project_name();
+                self.projects = projects;
+                let index = self.projects.iter().position(|p| p.name == name).unwrap_or(0);
+                self.selected.select((!self.projects.is_empty()).then_some(index));
+                self.refreshing = false;
+                // nothing was selected to ask the status of
+                if name.is_empty() && !self.projects.is_empty() {
+                    self.stale = true;
+                }
+                if std::mem::take(&mut self.stale) {
+                    self.refresh();
+                }
+            }
+            Update::Overview(Err(e)) => {
+                self.refreshing = false;
+                self.message = e;
+            }
+            Update::Plugins(plugins) => self.plugins = plugins,
+            Update::Actions(actions) => {
+                self.actions = actions;
+                if let Mode::Actions(state) = &mut self.mode {
+                    state.select((!self.actions.is_empty()).then_some(0));
+                }
+            }
+            Update::Reply(reply) => {
+                let mut lines = reply.lines();
+                self.message = lines.next().unwrap_or_default().to_string();
+                if lines.next().is_some() {
+                    self.output = Some(reply);
+                }
+                self.refresh();
+            }
+            Update::Event(event) => {
+                let time = chrono::Local::now().format("%H:%M:%S");
+                let line = format!(
+                    "{} {} {}",
+                    time,
+                    event["event"].as_str().unwrap_or("?"),
+                    event["project-name"].as_str().unwrap_or_default()
+                );
+                self.events.push(line);
+                if self.events.len() > EVENT_LOG_LENGTH {
+                    self.events.remove(0);
+                }
+                self.refresh();
+            }
+            Update::Disconnected(e) => self.message = format!("no more live updates, {}", e),
+        }
+    }
+
+    fn on_key(&mut self, key: KeyCode) {
+        let view = self.project().cloned().unwrap_or_default();
+        let project = view.name.clone();
+        match &mut self.mode {
+            Mode::Input {
+                buffer,
+                purpose,
+                ..
+            } => match key {
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Char(c) => buffer.push(c),
+                KeyCode::Esc | KeyCode::Enter => {
+                    let buffer = std::mem::take(buffer);
+                    let purpose = std::mem::replace(purpose, Purpose::NewProperty);
+                    // back to where the input was asked
+                    self.mode = match purpose {
+                        Purpose::ActionArgs(..) => Mode::Projects,
+                        _ => Mode::Properties(ListState::default().with_selected(Some(0))),
+                    };
+                    if key == KeyCode::Enter {
+                        self.submit(&project, purpose, buffer);
+                    }
+                }
+                _ => {}
+            },
+            Mode::Projects => match key {
+                KeyCode::Char('q') | KeyCode::Esc if self.output.is_some() => self.output = None,
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Down | KeyCode::Char('j') => {
+                    move_selection(&mut self.selected, self.projects.len(), 1);
+                    self.refresh();
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    move_selection(&mut self.selected, self.projects.len(), -1);
+                    self.refresh();
+                }
+                KeyCode::Enter | KeyCode::Char('s') if !project.is_empty() => {
+                    self.send("project", "switch", json!({"project-name": project}))
+                }
+                KeyCode::Char('p') if !project.is_empty() => {
+                    self.load_plugins();
+                    self.mode = Mode::Plugins(ListState::default().with_selected(Some(0)));
+                }
+                KeyCode::Char('e') if !project.is_empty() => {
+                    self.mode = Mode::Properties(ListState::default().with_selected(Some(0)));
+                }
+                KeyCode::Char('a') if !project.is_empty() => {
+                    self.actions.clear();
+                    self.load_actions(view.plugins.clone());
+                    self.mode = Mode::Actions(ListState::default());
+                }
+                KeyCode::Char('r') => self.refresh(),
+                _ => {}
+            },
+            Mode::Plugins(state) => match key {
+                KeyCode::Esc | KeyCode::Char('q') => self.mode = Mode::Projects,
+                KeyCode::Down | KeyCode::Char('j') => move_selection(state, self.plugins.len(), 1),
+                KeyCode::Up | KeyCode::Char('k') => move_selection(state, self.plugins.len(), -1),
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    let plugin = match state.selected().and_then(|i| self.plugins.get(i)) {
+                        Some(plugin) => plugin.clone(),
+                        None => return,
+                    };
+                    let command = if view.plugins.contains(&plugin) { "disable-plugin" } else { "enable-plugin" };
+                    self.send(
+                        "project",
+                        command,
+                        json!({"project-name": project, "plugin": plugin}),
+                    );
+                }
+                _ => {}
+            },
+            Mode::Properties(state) => {
+                let keys: Vec<&String> = view.properties.keys().collect();
+                let key_name = state.selected().and_then(|i| keys.get(i)).map(|k| k.to_string());
+                match key {
+                    KeyCode::Esc | KeyCode::Char('q') => self.mode = Mode::Projects,
+                    KeyCode::Down | KeyCode::Char('j') => move_selection(state, keys.len(), 1),
+                    KeyCode::Up | KeyCode::Char('k') => move_selection(state, keys.len(), -1),
+                    KeyCode::Char('n') => {
+                        self.mode = Mode::Input {
+                            prompt: "new property, as key=value: ".to_string(),
+                            buffer: String::new(),
+                            purpose: Purpose::NewProperty,
+                        }
+                    }
+                    KeyCode::Char('d') => {
+                        if let Some(property) = key_name {
+                            self.send(
+                                "project",
+                                "del-property",
+                                json!({"project-name": project, "property": property}),
+                            );
+                        }
+                    }
+                    KeyCode::Enter => {
+                        if let Some(property) = key_name {
+                            let value = view.properties.get(&property).cloned().unwrap_or_default();
+                            self.mode = Mode::Input {
+                                prompt: format!("{} = ", property),
+                                buffer: value,
+                                purpose: Purpose::SetProperty(property),
+                            };
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Mode::Actions(state) => match key {
+                KeyCode::Esc | KeyCode::Char('q') => self.mode = Mode::Projects,
+                KeyCode::Down | KeyCode::Char('j') => move_selection(state, self.actions.len(), 1),
+                KeyCode::Up | KeyCode::Char('k') => move_selection(state, self.actions.len(), -1),
+                KeyCode::Enter => {
+                    let (plugin, descriptor) =
+                        match state.selected().and_then(|i| self.actions.get(i)) {
+                            Some(action) => action.clone(),
+                            None => return,
+                        };
+                    if descriptor.params.is_empty() {
+                        self.mode = Mode::Projects;
+                        self.submit(&project, Purpose::ActionArgs(plugin, descriptor), String::new());
+                    } else {
+                        self.mode = Mode::Input {
+                            prompt: format!("{} {} arguments: ", plugin, descriptor.name),
+                            buffer: String::new(),
+                            purpose: Purpose::ActionArgs(plugin, descriptor),
+                        };
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn submit(&mut self, project: &str, purpose: Purpose, input: String) {
+        match purpose {
+            Purpose::SetProperty(property) => self.send(
+                "project",
+                "set-property",
+                json!({"project-name": project, "property": property, "value": input}),
+            ),
+            Purpose::NewProperty => match input.split_once('=') {
+                Some((property, value)) => self.send(
+                    "project",
+                    "set-property",
+                    json!({"project-name": project, "property": property.trim(), "value": value}),
+                ),
+                None => self.message = "a property is written as key=value".to_string(),
+            },
+            Purpose::ActionArgs(plugin, descriptor) => {
+                // parsed like the command line does
+                let mut words = vec![descriptor.name.clone()];
+                if descriptor.needs_project {
+                    words.push(project.to_string());
+                }
+                words.extend(input.split_whitespace().map(String::from));
+                let matches = match action_command(&plugin, &descriptor).try_get_matches_from(words) {
+                    Ok(matches) => matches,
+                    Err(e) => {
+                        self.message = e.to_string().lines().next().unwrap_or_default().to_string();
+                        return;
+                    }
+                };
+                let mut params = json!({
+                    "plugin": plugin,
+                    "action": descriptor.name,
+                    "args": action_arguments(&descriptor, &matches),
+                });
+                if descriptor.needs_project {
+                    params["project-name"] = json!(project);
+                }
+                self.send("plugin", "run", params);
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, log, footer] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(EVENT_LOG_LENGTH as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .projects
+            .iter()
+            .map(|p| {
+                let marker = if p.current { "*" } else { " " };
+                let line = format!("{} {}  {}", marker, p.name, format_duration(p.time_today));
+                let style = if p.current {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("projects, * current, time today"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.selected);
+
+        self.draw_details(frame, right);
+
+        let events: Vec<Line> = self.events.iter().map(|e| Line::from(e.as_str())).collect();
+        frame.render_widget(
+            Paragraph::new(events).block(Block::default().borders(Borders::ALL).title("events")),
+            log,
+        );
+
+        let footer_text = match &self.mode {
+            Mode::Input { prompt, buffer, .. } => {
+                let text = format!("{}{}", prompt, buffer);
+                frame.set_cursor_position((footer.x + text.chars().count() as u16, footer.y));
+                text
+            }
+            _ if !self.message.is_empty() => self.message.clone(),
+            Mode::Projects => {
+                "enter switch  p plugins  e properties  a actions  r refresh  q quit".to_string()
+            }
+            Mode::Plugins(_) => "enter toggle  esc back".to_string(),
+            Mode::Properties(_) => "enter edit  n new  d delete  esc back".to_string(),
+            Mode::Actions(_) => "enter run  esc back".to_string(),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    fn draw_details(&mut self, frame: &mut Frame, area: Rect) {
+        let project = self.project().cloned().unwrap_or_default();
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let block = |title: String| Block::default().borders(Borders::ALL).title(title);
+        match &mut self.mode {
+            Mode::Plugins(state) => {
+                let items: Vec<ListItem> = self
+                    .plugins
+                    .iter()
+                    .map(|plugin| {
+                        let enabled = if project.plugins.contains(plugin) { "[x]" } else { "[ ]" };
+                        ListItem::new(format!("{} {}", enabled, plugin))
+                    })
+                    .collect();
+                let list = List::new(items)
+                    .block(block(format!("{} plugins", project.name)))
+                    .highlight_style(highlight);
+                frame.render_stateful_widget(list, area, state);
+            }
+            Mode::Properties(state) => {
+                let items: Vec<ListItem> = project
+                    .properties
+                    .iter()
+                    .map(|(key, value)| ListItem::new(format!("{} = {}", key, value)))
+                    .collect();
+                let list = List::new(items)
+                    .block(block(format!("{} properties", project.name)))
+                    .highlight_style(highlight);
+                frame.render_stateful_widget(list, area, state);
+            }
+            Mode::Actions(state) => {
+                let items: Vec<ListItem> = self
+                    .actions
+                    .iter()
+                    .map(|(plugin, action)| {
+                        ListItem::new(format!("{} {} - {}", plugin, action.name, action.description))
+                    })
+                    .collect();
+                let list = List::new(items)
+                    .block(block(format!("{} actions", project.name)))
+                    .highlight_style(highlight);
+                frame.render_stateful_widget(list, area, state);
+            }
+            _ => {
+                let text = match &self.output {
+                    Some(output) => output.clone(),
+                    None => describe(&project),
+                };
+                let title = match &self.output {
+                    Some(_) => "output, esc to close".to_string(),
+                    None => project.name.clone(),
+                };
+                frame.render_widget(
+                    Paragraph::new(text).wrap(Wrap { trim: false }).block(block(title)),
+                    area,
+                );
+            }
+        }
+    }
+}
+
+fn describe(project: &ProjectView) -> String {
+    let mut lines = vec![
+        format!(
+            "path: {}",
+            project.properties.get("path").map(|p| p.as_str()).unwrap_or("none")
+        ),
+        format!("today: {}", format_duration(project.time_today)),
+        format!("plugins: {}", project.plugins.join(", ")),
+    ];
+    for (plugin, status) in &project.status {
+        lines.push(format!("  {}: {}", plugin, status));
+    }
+    if !project.contexts.is_empty() {
+        lines.push("contexts:".to_string());
+        for context in &project.contexts {
+            lines.push(format!("  {} ({})", context.name, context.path));
+        }
+    }
+    lines.join("\n")
+}
+
+fn run_dashboard(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    updates: &Receiver<Update>,
+) -> Result<(), String> {
+    while !dashboard.quit {
+        terminal
+            .draw(|frame| dashboard.draw(frame))
+            .map_err(|e| e.to_string())?;
+        if event::poll(Duration::from_millis(200)).map_err(|e| e.to_string())? {
+            if let TermEvent::Key(key) = event::read().map_err(|e| e.to_string())? {
+                if key.kind == KeyEventKind::Press {
+                    if !matches!(dashboard.mode, Mode::Input { .. }) {
+                        dashboard.message.clear();
+                    }
+                    dashboard.on_key(key.code);
+                }
+            }
+        }
+        while let Ok(update) = updates.try_recv() {
+            dashboard.apply(update);
+        }
+        if dashboard.last_refresh.elapsed() >= REFRESH_INTERVAL {
+            dashboard.refresh();
+        }
+    }
+    Ok(())
+}
+
+pub fn run(socket_path: &str) -> Result<(), String> {
+    // fails early, before taking over the terminal, when the daemon is not running
+    request(socket_path, "daemon", "status", json!({}))?;
+    let (updates, receiver) = channel();
+    let mut dashboard = Dashboard::new(socket_path, updates.clone());
+    subscribe(socket_path.to_string(), updates);
+    dashboard.refresh();
+    let mut terminal = ratatui::try_init().map_err(|e| format!("cannot use the terminal: {}", e))?;
+    let result = run_dashboard(&mut terminal, &mut dashboard, &receiver);
+    ratatui::restore();
+    result
+}